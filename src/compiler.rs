@@ -1,15 +1,22 @@
+mod bytecode;
 mod chunk;
+mod disasm;
 mod error;
 mod instruction;
 mod io;
+mod module;
+mod options;
 
-use self::io::absolute_path;
-use crate::parser::{Ast, BinaryOp, BlockExpr, Expr, Literal, Parser, Statement, UnaryOp};
+pub use self::module::resolve_module;
+use self::module::ModuleRegistry;
+use crate::parser::{Ast, BinaryOp, BlockExpr, Expr, Literal, LogicalOp, Statement, UnaryOp};
 use crate::sourcefile::{MetaData, SourceFile};
 use crate::vm::{FuncProtoRef, Integer, Value};
+pub use bytecode::BytecodeError;
 pub use chunk::{Chunk, CompiledSource, FuncProto, JumpCondition};
 pub use error::CompileError;
 pub use instruction::{BinaryInstr, Instruction, UnaryInstr};
+pub use options::CompileOptions;
 use std::convert::TryInto;
 use std::rc::Rc;
 
@@ -22,6 +29,8 @@ pub struct Compiler<'a> {
     depth: u8,
     closure_scopes: Vec<ClosureScope>,
     metadata: MetaData,
+    options: CompileOptions,
+    modules: ModuleRegistry,
 }
 
 #[derive(Debug, Clone, PartialEq)]
@@ -54,27 +63,44 @@ struct ClosureScope {
  * Compiling
  */
 impl<'a> Compiler<'a> {
-    pub fn compile(SourceFile { ast, metadata }: SourceFile) -> CompileResult<CompiledSource> {
-        let mut compiler = Self::new(metadata);
+    // Compiles with the default options, the tests don't need the others
+    #[cfg(test)]
+    pub fn compile(source: SourceFile) -> CompileResult<CompiledSource> {
+        Self::compile_with(source, CompileOptions::default())
+    }
+
+    pub fn compile_with(
+        SourceFile { ast, metadata }: SourceFile,
+        options: CompileOptions,
+    ) -> CompileResult<CompiledSource> {
+        let mut compiler = Self::new(metadata, options);
         compiler.compile_module(ast)?;
 
         let constants = match compiler.constant_table {
             ConstantTable::Owned(c) => c,
             _ => unreachable!(),
         };
+        // Every module imported by the source is run from the root chunk
+        let mut chunk = compiler.chunk;
+        for (key, module) in compiler.modules.into_chunks() {
+            chunk.add_import(key, module);
+        }
         Ok(CompiledSource {
-            chunk: compiler.chunk,
+            chunk,
             constant_table: Rc::new(constants),
         })
     }
 
     fn compile_with_table(
         SourceFile { ast, metadata }: SourceFile,
+        options: CompileOptions,
         table: &'a mut ConstantTableStruct,
-    ) -> CompileResult<Chunk> {
-        let mut compiler = Self::with_table(metadata, table);
+        modules: ModuleRegistry,
+    ) -> CompileResult<(Chunk, ModuleRegistry)> {
+        let mut compiler = Self::with_table(metadata, options, table);
+        compiler.modules = modules;
         compiler.compile_module(ast)?;
-        Ok(compiler.chunk)
+        Ok((compiler.chunk, compiler.modules))
     }
 
     fn compile_module(&mut self, ast: Ast) -> CompileResult<()> {
@@ -82,19 +108,25 @@ impl<'a> Compiler<'a> {
         self.add_instr(Instruction::Return { return_value: true })
     }
 
-    fn new(metadata: MetaData) -> Self {
+    fn new(metadata: MetaData, options: CompileOptions) -> Self {
         Compiler {
-            chunk: Chunk::new(),
+            chunk: Chunk::new().with_dir(metadata.dir.clone()),
             constant_table: ConstantTable::default(),
             locals: Vec::new(),
             depth: 0,
             closure_scopes: Vec::new(),
             metadata,
+            options,
+            modules: ModuleRegistry::default(),
         }
     }
 
-    fn with_table(metadata: MetaData, table: &'a mut ConstantTableStruct) -> Self {
-        let mut compiler = Compiler::new(metadata);
+    fn with_table(
+        metadata: MetaData,
+        options: CompileOptions,
+        table: &'a mut ConstantTableStruct,
+    ) -> Self {
+        let mut compiler = Compiler::new(metadata, options);
         compiler.constant_table = ConstantTable::Borrowed(table);
         compiler
     }
@@ -116,6 +148,14 @@ impl<'a> Compiler<'a> {
             Statement::Var { name, value } => self.var_stmt(name, value),
             Statement::Let { name, value } => self.let_stmt(name, value),
             Statement::Set { variable, value } => self.set_stmt(variable, value),
+            Statement::CompoundSet {
+                variable,
+                op,
+                value,
+            } => self.compound_set_stmt(variable, op, value),
+            Statement::SetMultiple { variables, values } => {
+                self.set_multiple_stmt(variables, values)
+            }
             Statement::Block(statements) => self.block_stmt(statements),
             Statement::If {
                 condition,
@@ -126,6 +166,11 @@ impl<'a> Compiler<'a> {
                 condition,
                 then_block,
             } => self.while_stmt(condition, *then_block),
+            Statement::For {
+                variables,
+                iter,
+                body,
+            } => self.for_stmt(variables, iter, body),
             Statement::Print(expr) => {
                 self.compile_expr(expr)?;
                 self.add_instr(Instruction::Print)
@@ -135,6 +180,12 @@ impl<'a> Compiler<'a> {
                 self.add_instr(Instruction::Return { return_value: true })
             }
             Statement::Import { path, name } => self.import_stmt(path, name),
+            Statement::FromImport { path, names } => self.import_from_stmt(path, names),
+            Statement::Export(names) => {
+                self.chunk.add_exports(names);
+                Ok(())
+            }
+            Statement::Class { .. } => unreachable!("Classes are desugared by the analyzer"),
         }
     }
 
@@ -166,10 +217,15 @@ impl<'a> Compiler<'a> {
 
     fn set_stmt(&mut self, variable: Expr, value: Expr) -> CompileResult<()> {
         // TODO: pattern matching for tuple expressions
+        self.compile_expr(value)?;
+        self.assign(variable)
+    }
+
+    // Pops the value on the stack and assigns it to the variable
+    fn assign(&mut self, variable: Expr) -> CompileResult<()> {
         match variable {
             Expr::Identifier(name) => {
                 let index = self.add_constant(name.clone().into(), false)?;
-                self.compile_expr(value)?;
                 if let Some((index, frame)) = self.resolve_local(name.as_str()) {
                     let index = index as u16;
                     if frame > 1 {
@@ -183,7 +239,6 @@ impl<'a> Compiler<'a> {
                 }
             }
             Expr::Access { table, field } => {
-                self.compile_expr(value)?;
                 self.compile_expr(*field)?;
                 self.compile_expr(*table)?;
                 self.add_instr(Instruction::SetField)
@@ -193,6 +248,58 @@ impl<'a> Compiler<'a> {
         Ok(())
     }
 
+    // The table and the key are evaluated once and duplicated for getting and setting
+    fn compound_set_stmt(
+        &mut self,
+        variable: Expr,
+        op: BinaryOp,
+        value: Expr,
+    ) -> CompileResult<()> {
+        match variable {
+            Expr::Identifier(_) => {
+                let binary = Expr::Binary {
+                    left: Box::new(variable.clone()),
+                    op,
+                    right: Box::new(value),
+                };
+                self.set_stmt(variable, binary)
+            }
+            Expr::Access { table, field } => {
+                self.compile_expr(*table)?;
+                match *field {
+                    Expr::Literal(Literal::Str(string)) => {
+                        let index = self.add_constant(string.into(), false)?;
+                        self.add_instr(Instruction::Dup { len: 1 })?;
+                        self.add_instr(Instruction::GetFieldImm { index })?;
+                        self.compile_expr(value)?;
+                        self.add_instr(Instruction::Bin(binary_instr(op)))?;
+                        self.add_instr(Instruction::SetFieldImm { index })
+                    }
+                    field => {
+                        self.compile_expr(field)?;
+                        self.add_instr(Instruction::Dup { len: 2 })?;
+                        self.add_instr(Instruction::GetField)?;
+                        self.compile_expr(value)?;
+                        self.add_instr(Instruction::Bin(binary_instr(op)))?;
+                        self.add_instr(Instruction::SetIndex)
+                    }
+                }
+            }
+            _ => Err(CompileError::InvalidAssignmentTarget(variable)),
+        }
+    }
+
+    // All values are evaluated before the variables are assigned from the last one
+    fn set_multiple_stmt(&mut self, variables: Vec<Expr>, values: Vec<Expr>) -> CompileResult<()> {
+        for value in values {
+            self.compile_expr(value)?;
+        }
+        for variable in variables.into_iter().rev() {
+            self.assign(variable)?;
+        }
+        Ok(())
+    }
+
     fn block_stmt(&mut self, statements: Vec<Statement>) -> CompileResult<()> {
         self.enter_scope();
         for stmt in statements {
@@ -211,6 +318,8 @@ impl<'a> Compiler<'a> {
 
         let patch_index = self.add_placeholder()?;
         self.compile_expr(then_block)?;
+        // Values of the blocks are discarded
+        self.add_instr(Instruction::Pop)?;
 
         let offset = self.get_offset(patch_index)?;
         if let Some(else_block) = else_block {
@@ -219,6 +328,7 @@ impl<'a> Compiler<'a> {
 
             let patch_index = self.add_placeholder()?;
             self.compile_expr(else_block)?;
+            self.add_instr(Instruction::Pop)?;
             let offset = self.get_offset(patch_index)?;
             self.patch_placeholder(patch_index, offset as i8, JumpCondition::None)?;
         } else {
@@ -239,56 +349,100 @@ impl<'a> Compiler<'a> {
         })
     }
 
+    // The iterator is kept in a hidden local below the loop variables
+    fn for_stmt(
+        &mut self,
+        variables: Vec<String>,
+        iter: Expr,
+        body: Vec<Statement>,
+    ) -> CompileResult<()> {
+        let len = variables.len() as u8;
+        self.compile_expr(iter)?;
+        self.add_instr(Instruction::Iter)?;
+        self.enter_scope();
+        self.push_local(String::new());
+
+        let start_index = self.instructions().len();
+        self.ident(String::new())?;
+        self.add_instr(Instruction::IterNext { len })?;
+        let patch_index = self.add_placeholder()?;
+        // Values of the item are on the stack
+        self.enter_scope();
+        for variable in variables {
+            self.push_local(variable);
+        }
+        for stmt in body {
+            self.compile_stmt(stmt)?;
+        }
+        self.exit_scope(false)?;
+        let offset = self.instructions().len() - patch_index + 1;
+        self.patch_placeholder(patch_index, offset as i8, JumpCondition::WhenFalse)?;
+        self.add_instr(Instruction::Jump {
+            offset: -((self.instructions().len() - start_index) as i8),
+        })?;
+        self.exit_scope(false)
+    }
+
     fn import_stmt(&mut self, path: Vec<String>, name: String) -> CompileResult<()> {
-        use std::path::PathBuf;
-        // Get source file
-        let abs_path = if Self::is_std(&path) {
-            let current_dir = PathBuf::from(file!());
-            let src_dir = current_dir.parent().expect("Expected parent be 'src'");
-            let mut lib_dir = PathBuf::from(src_dir);
-            lib_dir.push("vm");
-            lib_dir.push("lib");
-
-            let last = path.len() - 2;
-            for (i, p) in path.into_iter().skip(1).enumerate() {
-                if i == last {
-                    lib_dir.push(p + ".flux");
-                } else {
-                    lib_dir.push(p);
-                }
-            }
-            debug!("Directory of {}: {:#?}", &name, &lib_dir);
-            lib_dir
-        } else {
-            absolute_path(self.metadata.current_dir(), path.as_slice())
+        let path_index = self.import_module(path, &name)?;
+        self.add_instr(Instruction::Import { path_index })?;
+        let index = self.add_constant(name.into(), false)?;
+        self.add_instr(Instruction::SetGlobal { index })
+    }
+
+    fn import_from_stmt(&mut self, path: Vec<String>, names: Vec<String>) -> CompileResult<()> {
+        let path_index = self.import_module(path.clone(), &path.join("."))?;
+        for name in names {
+            let name_index = self.add_constant(name.into(), false)?;
+            self.add_instr(Instruction::ImportFrom {
+                path_index,
+                name_index,
+            })?;
+            self.add_instr(Instruction::SetGlobal { index: name_index })?;
+        }
+        Ok(())
+    }
+
+    // Compiles the module unless it already is, returns the constant index of its key
+    fn import_module(&mut self, path: Vec<String>, name: &str) -> CompileResult<u8> {
+        if !self.options.imports {
+            return Err(CompileError::ImportDisabled {
+                name: name.to_string(),
+            });
+        }
+        let module_error = |error| match error {
+            // Reported once for the whole cycle
+            CompileError::CircularImport { .. } => error,
+            error => CompileError::ModuleError {
+                name: name.to_string(),
+                error: Box::new(error),
+            },
         };
-        let source = io::read_file(abs_path.clone())?;
+        let module = resolve_module(&path, &self.metadata.dir, &self.options.search_paths)?;
+        let key = module.key.clone();
+        let key_index = self.add_constant(key.clone().into(), false)?;
+        if self.modules.contains(&key) {
+            return Ok(key_index);
+        }
+        self.modules.start(&key)?;
         // Parse and store
-        let ast = Parser::parse_str(source.as_str())?;
-        debug!("Module {}: {:#?}", &name, &ast);
-        let metadata = MetaData {
-            dir: abs_path
-                .parent()
-                .expect("Expected a parent directory")
-                .to_owned(),
-        };
+        let ast = module
+            .parse(&self.options)
+            .map_err(|error| module_error(error.into()))?;
+        debug!("Module {}: {:#?}", name, &ast);
+        let metadata = MetaData { dir: module.dir };
         // Compile the module
-        let chunk = Compiler::compile_with_table(
+        let modules = std::mem::take(&mut self.modules);
+        let (chunk, modules) = Compiler::compile_with_table(
             SourceFile { ast, metadata },
+            self.options.clone(),
             self.constant_table.as_mut(),
+            modules,
         )
-        .map_err(|error| CompileError::ModuleError {
-            name: name.clone(),
-            error: Box::new(error),
-        })?;
-        // Add import to table ad push instruction
-        let name_index = self.add_constant(name.clone().into(), false)?;
-        self.chunk.add_import(chunk, name, name_index)
-    }
-
-    #[inline]
-    fn is_std(path: &[String]) -> bool {
-        &path[0] == "std"
+        .map_err(module_error)?;
+        self.modules = modules;
+        self.modules.finish(key, chunk);
+        Ok(key_index)
     }
 
     fn compile_expr(&mut self, expr: Expr) -> CompileResult<()> {
@@ -298,6 +452,8 @@ impl<'a> Compiler<'a> {
             Expr::Identifier(name) => self.ident(name),
             Expr::Unary { op, expr } => self.unary(*expr, op),
             Expr::Binary { left, op, right } => self.binary(*left, *right, op),
+            Expr::Logical { left, op, right } => self.logical(*left, *right, op),
+            Expr::Optional(expr) => self.optional(*expr),
             Expr::Grouping(expr) => self.compile_expr(*expr),
             Expr::Tuple(exprs) => self.tuple(exprs),
             Expr::Access { table, field } => self.access(*table, *field),
@@ -315,6 +471,15 @@ impl<'a> Compiler<'a> {
                 then_block,
                 else_block,
             } => self.if_expr(*condition, *then_block, *else_block),
+            Expr::Range {
+                start,
+                end,
+                inclusive,
+            } => {
+                self.compile_expr(*start)?;
+                self.compile_expr(*end)?;
+                self.add_instr(Instruction::Range { inclusive })
+            }
             Expr::Rec => self.add_instr(Instruction::Rec),
             _ => Err(CompileError::UnimplementedExpr(expr)),
         }
@@ -328,10 +493,8 @@ impl<'a> Compiler<'a> {
             } else {
                 Instruction::False
             }),
-            Literal::Number(n) => match n.fract() == 0.0 {
-                true => self.int_literal(n as i64),
-                false => self.add_constant(Value::Number(n), true).map(|_| ()),
-            },
+            Literal::Int(i) => self.int_literal(i),
+            Literal::Number(n) => self.add_constant(Value::Number(n), true).map(|_| ()),
             Literal::Str(string) => {
                 self.add_constant(string.into(), true)?;
                 Ok(())
@@ -370,29 +533,30 @@ impl<'a> Compiler<'a> {
         let unary = match op {
             UnaryOp::Minus => UnaryInstr::Negate,
             UnaryOp::Bang => UnaryInstr::Not,
+            UnaryOp::BitNot => UnaryInstr::BitNot,
+            UnaryOp::ToString => UnaryInstr::ToString,
         };
         self.add_instr(Instruction::Unary(unary))
     }
 
-    fn binary(&mut self, left: Expr, right: Expr, op: BinaryOp) -> CompileResult<()> {
+    // The deciding operand is the result, `a and b` is `a` if it is falsy
+    fn logical(&mut self, left: Expr, right: Expr, op: LogicalOp) -> CompileResult<()> {
         self.compile_expr(left)?;
+        let patch_index = self.add_placeholder()?;
         self.compile_expr(right)?;
-        let binary = match op {
-            BinaryOp::Plus => BinaryInstr::Add,
-            BinaryOp::Minus => BinaryInstr::Sub,
-            BinaryOp::Star => BinaryInstr::Mul,
-            BinaryOp::Slash => BinaryInstr::Div,
-            BinaryOp::Rem => BinaryInstr::Rem,
-
-            BinaryOp::Greater => BinaryInstr::Gt,
-            BinaryOp::Less => BinaryInstr::Lt,
-            BinaryOp::GreaterEqual => BinaryInstr::Ge,
-            BinaryOp::LessEqual => BinaryInstr::Le,
-
-            BinaryOp::EqualEqual => BinaryInstr::Eq,
-            BinaryOp::BangEqual => BinaryInstr::Ne,
+        let offset = self.get_offset(patch_index)?;
+        let condition = match op {
+            LogicalOp::And => JumpCondition::WhenFalseOrPop,
+            LogicalOp::Or => JumpCondition::WhenTrueOrPop,
+            LogicalOp::Coalesce => JumpCondition::WhenNotNilOrPop,
         };
-        self.add_instr(Instruction::Bin(binary))
+        self.patch_placeholder(patch_index, offset, condition)
+    }
+
+    fn binary(&mut self, left: Expr, right: Expr, op: BinaryOp) -> CompileResult<()> {
+        self.compile_expr(left)?;
+        self.compile_expr(right)?;
+        self.add_instr(Instruction::Bin(binary_instr(op)))
     }
 
     fn tuple(&mut self, exprs: Vec<Expr>) -> CompileResult<()> {
@@ -405,6 +569,11 @@ impl<'a> Compiler<'a> {
 
     fn access(&mut self, table: Expr, field: Expr) -> CompileResult<()> {
         self.compile_expr(table)?;
+        self.access_field(field)
+    }
+
+    // Gets the field of the table on the stack
+    fn access_field(&mut self, field: Expr) -> CompileResult<()> {
         let access_instr = match field {
             Expr::Literal(lit) => match lit {
                 Literal::Str(string) => {
@@ -425,11 +594,16 @@ impl<'a> Compiler<'a> {
     }
 
     fn self_access(&mut self, table: Expr, method: String, args: Vec<Expr>) -> CompileResult<()> {
+        self.compile_expr(table)?;
+        self.method_call(method, args)
+    }
+
+    // Calls the method of the table on the stack
+    fn method_call(&mut self, method: String, args: Vec<Expr>) -> CompileResult<()> {
         let index = self.add_constant(method.into(), false)?;
         let table_stack_index: u8 = args.len().try_into().unwrap();
         let args_len = (args.len() + 1).try_into().unwrap();
 
-        self.compile_expr(table)?;
         self.compile_args(args)?;
         self.add_instr(Instruction::GetMethodImm {
             index,
@@ -438,6 +612,34 @@ impl<'a> Compiler<'a> {
         self.add_instr(Instruction::Call { args_len })
     }
 
+    // A nil receiver skips the access and is the result
+    fn optional(&mut self, expr: Expr) -> CompileResult<()> {
+        let patch_index = match expr {
+            Expr::Access { table, field } => {
+                let patch_index = self.nil_guard(*table)?;
+                self.access_field(*field)?;
+                patch_index
+            }
+            Expr::SelfAccess {
+                table,
+                method,
+                args,
+            } => {
+                let patch_index = self.nil_guard(*table)?;
+                self.method_call(method, args)?;
+                patch_index
+            }
+            _ => unreachable!("only accesses are optional"),
+        };
+        let offset = self.get_offset(patch_index)?;
+        self.patch_placeholder(patch_index, offset, JumpCondition::WhenNil)
+    }
+
+    fn nil_guard(&mut self, receiver: Expr) -> CompileResult<usize> {
+        self.compile_expr(receiver)?;
+        self.add_placeholder()
+    }
+
     fn table_init(&mut self, keys: Option<Vec<Expr>>, values: Vec<Expr>) -> CompileResult<()> {
         let len = values.len();
         let has_keys = match keys {
@@ -593,18 +795,7 @@ impl<'a> Compiler<'a> {
         jump_offset: i8,
         jump_cond: JumpCondition,
     ) -> CompileResult<()> {
-        let offset = jump_offset;
-        let instr = match jump_cond {
-            JumpCondition::None => Instruction::Jump { offset },
-            JumpCondition::WhenTrue => Instruction::JumpIf {
-                when_true: true,
-                offset,
-            },
-            JumpCondition::WhenFalse => Instruction::JumpIf {
-                when_true: false,
-                offset,
-            },
-        };
+        let instr = jump_cond.jump(jump_offset);
         match self.instructions()[index] {
             Instruction::Placeholder | Instruction::Jump { .. } | Instruction::JumpIf { .. } => {
                 self.instructions_mut()[index] = instr;
@@ -818,3 +1009,27 @@ impl Default for ConstantTable<'_> {
         })
     }
 }
+
+fn binary_instr(op: BinaryOp) -> BinaryInstr {
+    match op {
+        BinaryOp::Plus => BinaryInstr::Add,
+        BinaryOp::Minus => BinaryInstr::Sub,
+        BinaryOp::Star => BinaryInstr::Mul,
+        BinaryOp::Slash => BinaryInstr::Div,
+        BinaryOp::Rem => BinaryInstr::Rem,
+
+        BinaryOp::Greater => BinaryInstr::Gt,
+        BinaryOp::Less => BinaryInstr::Lt,
+        BinaryOp::GreaterEqual => BinaryInstr::Ge,
+        BinaryOp::LessEqual => BinaryInstr::Le,
+
+        BinaryOp::EqualEqual => BinaryInstr::Eq,
+        BinaryOp::BangEqual => BinaryInstr::Ne,
+
+        BinaryOp::BitAnd => BinaryInstr::BitAnd,
+        BinaryOp::BitOr => BinaryInstr::BitOr,
+        BinaryOp::BitXor => BinaryInstr::BitXor,
+        BinaryOp::ShiftLeft => BinaryInstr::Shl,
+        BinaryOp::ShiftRight => BinaryInstr::Shr,
+    }
+}
@@ -0,0 +1,862 @@
+use super::{
+    BinaryInstr, Chunk, CompiledSource, ConstantTableStruct, FuncProto, Instruction, UnaryInstr,
+};
+use crate::vm::Value;
+use std::convert::TryInto;
+use std::path::PathBuf;
+use std::rc::Rc;
+
+/// First bytes of a `.fluxc` file
+pub const MAGIC: [u8; 4] = *b"FLXC";
+/// Incremented whenever the encoding or the instruction set changes
+pub const VERSION: u16 = 1;
+
+// Magic, version then the checksum of the rest
+const HEADER_LEN: usize = 4 + 2 + 8;
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum BytecodeError {
+    InvalidMagic,
+    UnsupportedVersion(u16),
+    ChecksumMismatch,
+    UnexpectedEnd,
+    TrailingBytes,
+    InvalidOpcode(u8),
+    InvalidTag(u8),
+    InvalidUtf8,
+    // Only literals can be constants of a compiled source
+    UnsupportedConstant(&'static str),
+    ConstantOutOfBounds(u8),
+    PrototypeOutOfBounds(u32),
+    JumpOutOfBounds { pc: usize, offset: i8 },
+    MissingModule(String),
+}
+
+type Result<T> = std::result::Result<T, BytecodeError>;
+
+impl CompiledSource {
+    /// Encodes the source with its constants, prototypes and imported modules
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        let mut writer = Writer::default();
+        writer.constants(&self.constant_table)?;
+        writer.chunk(&self.chunk);
+        writer.len(self.chunk.imports().len());
+        // Sorted so that the same source is always encoded the same
+        let mut imports: Vec<_> = self.chunk.imports().iter().collect();
+        imports.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, chunk) in imports {
+            writer.string(key);
+            writer.chunk(chunk);
+        }
+        let mut bytes = Vec::with_capacity(HEADER_LEN + writer.bytes.len());
+        bytes.extend_from_slice(&MAGIC);
+        bytes.extend_from_slice(&VERSION.to_le_bytes());
+        bytes.extend_from_slice(&checksum(&writer.bytes).to_le_bytes());
+        bytes.extend(writer.bytes);
+        Ok(bytes)
+    }
+
+    /// Decodes a source encoded by `to_bytes`, runtime imports are resolved relative to `dir`
+    pub fn from_bytes(bytes: &[u8], dir: PathBuf) -> Result<Self> {
+        if bytes.len() < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Err(BytecodeError::InvalidMagic);
+        }
+        let mut reader = Reader {
+            bytes,
+            pos: MAGIC.len(),
+        };
+        let version = reader.u16()?;
+        if version != VERSION {
+            return Err(BytecodeError::UnsupportedVersion(version));
+        }
+        if reader.u64()? != checksum(&bytes[HEADER_LEN..]) {
+            return Err(BytecodeError::ChecksumMismatch);
+        }
+        let constant_table = reader.constants()?;
+        let mut chunk = reader.chunk()?.with_dir(dir.clone());
+        for _ in 0..reader.len()? {
+            let key = reader.string()?;
+            let module = reader.chunk()?.with_dir(dir.clone());
+            chunk.add_import(key, module);
+        }
+        if reader.pos != bytes.len() {
+            return Err(BytecodeError::TrailingBytes);
+        }
+        let source = CompiledSource {
+            chunk,
+            constant_table: Rc::new(constant_table),
+        };
+        source.validate()?;
+        Ok(source)
+    }
+
+    // Checks the operands. The vm reports the remaining inconsistencies, like locals
+    // outside the stack or running past the last instruction, as runtime errors.
+    fn validate(&self) -> Result<()> {
+        let table = &self.constant_table;
+        let modules = self.chunk.imports();
+        let instructions = std::iter::once(self.chunk.instructions())
+            .chain(modules.values().map(Chunk::instructions))
+            .chain(table.prototypes.iter().map(|proto| &*proto.instructions));
+        for instructions in instructions {
+            validate_instructions(instructions, table, |key| modules.contains_key(key))?;
+        }
+        Ok(())
+    }
+}
+
+fn validate_instructions(
+    instructions: &[Instruction],
+    table: &ConstantTableStruct,
+    has_module: impl Fn(&str) -> bool,
+) -> Result<()> {
+    let constant = |index: u8| {
+        table
+            .constants
+            .get(index as usize)
+            .ok_or(BytecodeError::ConstantOutOfBounds(index))
+    };
+    let module = |index: u8| match constant(index)? {
+        Value::Str(key) if has_module(key) => Ok(()),
+        value => Err(BytecodeError::MissingModule(value.to_string())),
+    };
+    for (pc, instr) in instructions.iter().enumerate() {
+        match *instr {
+            Instruction::Constant { index }
+            | Instruction::SetGlobal { index }
+            | Instruction::GetGlobal { index }
+            | Instruction::GetMethodImm { index, .. }
+            | Instruction::GetFieldImm { index }
+            | Instruction::SetFieldImm { index } => {
+                constant(index)?;
+            }
+            Instruction::Import { path_index } => module(path_index)?,
+            Instruction::ImportFrom {
+                path_index,
+                name_index,
+            } => {
+                module(path_index)?;
+                constant(name_index)?;
+            }
+            Instruction::FuncDef { proto_index, .. }
+                if proto_index as usize >= table.prototypes.len() =>
+            {
+                return Err(BytecodeError::PrototypeOutOfBounds(proto_index));
+            }
+            Instruction::Jump { offset }
+            | Instruction::JumpIf { offset, .. }
+            | Instruction::JumpIfOrPop { offset, .. }
+            | Instruction::JumpIfNotNilOrPop { offset }
+            | Instruction::JumpIfNil { offset } => {
+                let target = pc as isize + offset as isize;
+                if target < 0 || target as usize >= instructions.len() {
+                    return Err(BytecodeError::JumpOutOfBounds { pc, offset });
+                }
+            }
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+// FNV-1a
+fn checksum(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+const NIL: u8 = 0;
+const FALSE: u8 = 1;
+const TRUE: u8 = 2;
+const INT: u8 = 3;
+const NUMBER: u8 = 4;
+const STR: u8 = 5;
+
+#[derive(Default)]
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn u8(&mut self, value: u8) {
+        self.bytes.push(value);
+    }
+
+    fn bool(&mut self, value: bool) {
+        self.u8(value as u8);
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u64(&mut self, value: u64) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn len(&mut self, len: usize) {
+        self.u32(len.try_into().expect("Expected less than 2^32 items"));
+    }
+
+    fn string(&mut self, string: &str) {
+        self.len(string.len());
+        self.bytes.extend_from_slice(string.as_bytes());
+    }
+
+    fn constants(&mut self, table: &ConstantTableStruct) -> Result<()> {
+        self.len(table.constants.len());
+        for constant in &table.constants {
+            match constant {
+                Value::Nil => self.u8(NIL),
+                Value::Bool(false) => self.u8(FALSE),
+                Value::Bool(true) => self.u8(TRUE),
+                Value::Int(n) => {
+                    self.u8(INT);
+                    self.u64(*n as u64);
+                }
+                Value::Number(n) => {
+                    self.u8(NUMBER);
+                    self.u64(n.to_bits());
+                }
+                Value::Str(string) => {
+                    self.u8(STR);
+                    self.string(string);
+                }
+                Value::Embedded(string) => {
+                    self.u8(STR);
+                    self.string(string);
+                }
+                value => return Err(BytecodeError::UnsupportedConstant(value.type_name())),
+            }
+        }
+        self.len(table.prototypes.len());
+        for proto in &table.prototypes {
+            self.u8(proto.args_len);
+            self.instructions(&proto.instructions);
+        }
+        Ok(())
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) {
+        self.instructions(chunk.instructions());
+        match chunk.exports() {
+            Some(names) => {
+                self.bool(true);
+                self.len(names.len());
+                for name in names {
+                    self.string(name);
+                }
+            }
+            None => self.bool(false),
+        }
+    }
+
+    fn instructions(&mut self, instructions: &[Instruction]) {
+        self.len(instructions.len());
+        for instr in instructions {
+            self.instruction(*instr);
+        }
+    }
+
+    fn instruction(&mut self, instr: Instruction) {
+        match instr {
+            Instruction::Bin(bin) => {
+                self.u8(0);
+                self.u8(bin as u8);
+            }
+            Instruction::Unary(unary) => {
+                self.u8(1);
+                self.u8(unary as u8);
+            }
+            Instruction::Nil => self.u8(2),
+            Instruction::Unit => self.u8(3),
+            Instruction::True => self.u8(4),
+            Instruction::False => self.u8(5),
+            Instruction::Constant { index } => {
+                self.u8(6);
+                self.u8(index);
+            }
+            Instruction::SetGlobal { index } => {
+                self.u8(7);
+                self.u8(index);
+            }
+            Instruction::GetGlobal { index } => {
+                self.u8(8);
+                self.u8(index);
+            }
+            Instruction::SetLocal { index, frame } => {
+                self.u8(9);
+                self.u16(index);
+                self.u8(frame);
+            }
+            Instruction::GetLocal { index, frame } => {
+                self.u8(10);
+                self.u16(index);
+                self.u8(frame);
+            }
+            Instruction::GetMethodImm {
+                index,
+                table_stack_index,
+            } => {
+                self.u8(11);
+                self.u8(index);
+                self.u8(table_stack_index);
+            }
+            Instruction::GetFieldImm { index } => {
+                self.u8(12);
+                self.u8(index);
+            }
+            Instruction::GetField => self.u8(13),
+            Instruction::SetFieldImm { index } => {
+                self.u8(14);
+                self.u8(index);
+            }
+            Instruction::SetField => self.u8(15),
+            Instruction::SetIndex => self.u8(16),
+            Instruction::Dup { len } => {
+                self.u8(17);
+                self.u8(len);
+            }
+            Instruction::Pop => self.u8(18),
+            Instruction::Return { return_value } => {
+                self.u8(19);
+                self.bool(return_value);
+            }
+            Instruction::Tuple { len } => {
+                self.u8(20);
+                self.u8(len);
+            }
+            Instruction::InitTable { len, has_keys } => {
+                self.u8(21);
+                self.u16(len);
+                self.bool(has_keys);
+            }
+            Instruction::JumpIf { when_true, offset } => {
+                self.u8(22);
+                self.bool(when_true);
+                self.u8(offset as u8);
+            }
+            Instruction::JumpIfOrPop { when_true, offset } => {
+                self.u8(23);
+                self.bool(when_true);
+                self.u8(offset as u8);
+            }
+            Instruction::JumpIfNotNilOrPop { offset } => {
+                self.u8(24);
+                self.u8(offset as u8);
+            }
+            Instruction::JumpIfNil { offset } => {
+                self.u8(25);
+                self.u8(offset as u8);
+            }
+            Instruction::Jump { offset } => {
+                self.u8(26);
+                self.u8(offset as u8);
+            }
+            Instruction::Placeholder => self.u8(27),
+            Instruction::Print => self.u8(28),
+            Instruction::FuncDef {
+                proto_index,
+                has_env,
+            } => {
+                self.u8(29);
+                self.u32(proto_index);
+                self.bool(has_env);
+            }
+            Instruction::Call { args_len } => {
+                self.u8(30);
+                self.u8(args_len);
+            }
+            Instruction::GetUpval { index } => {
+                self.u8(31);
+                self.u16(index);
+            }
+            Instruction::SetUpval { index } => {
+                self.u8(32);
+                self.u16(index);
+            }
+            Instruction::CloseUpval { index } => {
+                self.u8(33);
+                self.u8(index);
+            }
+            Instruction::Integer(n) => {
+                self.u8(34);
+                self.u32(n as u32);
+            }
+            Instruction::Import { path_index } => {
+                self.u8(35);
+                self.u8(path_index);
+            }
+            Instruction::ImportFrom {
+                path_index,
+                name_index,
+            } => {
+                self.u8(36);
+                self.u8(path_index);
+                self.u8(name_index);
+            }
+            Instruction::ExitBlock { pop, return_value } => {
+                self.u8(37);
+                self.u16(pop);
+                self.bool(return_value);
+            }
+            Instruction::Rec => self.u8(38),
+            Instruction::Range { inclusive } => {
+                self.u8(39);
+                self.bool(inclusive);
+            }
+            Instruction::Iter => self.u8(40),
+            Instruction::IterNext { len } => {
+                self.u8(41);
+                self.u8(len);
+            }
+        }
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8]> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BytecodeError::UnexpectedEnd)?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn i8(&mut self) -> Result<i8> {
+        Ok(self.u8()? as i8)
+    }
+
+    fn bool(&mut self) -> Result<bool> {
+        match self.u8()? {
+            0 => Ok(false),
+            1 => Ok(true),
+            tag => Err(BytecodeError::InvalidTag(tag)),
+        }
+    }
+
+    fn u16(&mut self) -> Result<u16> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64> {
+        Ok(u64::from_le_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    // Every item takes at least a byte, so longer lengths can only be corrupt
+    fn len(&mut self) -> Result<usize> {
+        let len = self.u32()? as usize;
+        if len > self.bytes.len() - self.pos {
+            Err(BytecodeError::UnexpectedEnd)
+        } else {
+            Ok(len)
+        }
+    }
+
+    fn string(&mut self) -> Result<String> {
+        let len = self.len()?;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| BytecodeError::InvalidUtf8)
+    }
+
+    fn constants(&mut self) -> Result<ConstantTableStruct> {
+        let len = self.len()?;
+        let mut constants = Vec::with_capacity(len);
+        for _ in 0..len {
+            let constant = match self.u8()? {
+                NIL => Value::Nil,
+                FALSE => Value::Bool(false),
+                TRUE => Value::Bool(true),
+                INT => Value::Int(self.u64()? as i64),
+                NUMBER => Value::Number(f64::from_bits(self.u64()?)),
+                STR => Value::new_str(self.string()?),
+                tag => return Err(BytecodeError::InvalidTag(tag)),
+            };
+            constants.push(constant);
+        }
+        let len = self.len()?;
+        let mut prototypes = Vec::with_capacity(len);
+        for _ in 0..len {
+            let args_len = self.u8()?;
+            let instructions = self.instructions()?.into_boxed_slice();
+            prototypes.push(Rc::new(FuncProto {
+                args_len,
+                instructions,
+            }));
+        }
+        Ok(ConstantTableStruct {
+            constants,
+            prototypes,
+        })
+    }
+
+    fn chunk(&mut self) -> Result<Chunk> {
+        let mut chunk = Chunk::new();
+        *chunk.instructions_mut() = self.instructions()?;
+        if self.bool()? {
+            let len = self.len()?;
+            let mut names = Vec::with_capacity(len);
+            for _ in 0..len {
+                names.push(self.string()?);
+            }
+            chunk.add_exports(names);
+        }
+        Ok(chunk)
+    }
+
+    fn instructions(&mut self) -> Result<Vec<Instruction>> {
+        let len = self.len()?;
+        let mut instructions = Vec::with_capacity(len);
+        for _ in 0..len {
+            instructions.push(self.instruction()?);
+        }
+        Ok(instructions)
+    }
+
+    fn instruction(&mut self) -> Result<Instruction> {
+        Ok(match self.u8()? {
+            0 => Instruction::Bin(self.binary()?),
+            1 => Instruction::Unary(match self.u8()? {
+                0 => UnaryInstr::Negate,
+                1 => UnaryInstr::Not,
+                2 => UnaryInstr::BitNot,
+                3 => UnaryInstr::ToString,
+                op => return Err(BytecodeError::InvalidOpcode(op)),
+            }),
+            2 => Instruction::Nil,
+            3 => Instruction::Unit,
+            4 => Instruction::True,
+            5 => Instruction::False,
+            6 => Instruction::Constant { index: self.u8()? },
+            7 => Instruction::SetGlobal { index: self.u8()? },
+            8 => Instruction::GetGlobal { index: self.u8()? },
+            9 => Instruction::SetLocal {
+                index: self.u16()?,
+                frame: self.u8()?,
+            },
+            10 => Instruction::GetLocal {
+                index: self.u16()?,
+                frame: self.u8()?,
+            },
+            11 => Instruction::GetMethodImm {
+                index: self.u8()?,
+                table_stack_index: self.u8()?,
+            },
+            12 => Instruction::GetFieldImm { index: self.u8()? },
+            13 => Instruction::GetField,
+            14 => Instruction::SetFieldImm { index: self.u8()? },
+            15 => Instruction::SetField,
+            16 => Instruction::SetIndex,
+            17 => Instruction::Dup { len: self.u8()? },
+            18 => Instruction::Pop,
+            19 => Instruction::Return {
+                return_value: self.bool()?,
+            },
+            20 => Instruction::Tuple { len: self.u8()? },
+            21 => Instruction::InitTable {
+                len: self.u16()?,
+                has_keys: self.bool()?,
+            },
+            22 => Instruction::JumpIf {
+                when_true: self.bool()?,
+                offset: self.i8()?,
+            },
+            23 => Instruction::JumpIfOrPop {
+                when_true: self.bool()?,
+                offset: self.i8()?,
+            },
+            24 => Instruction::JumpIfNotNilOrPop { offset: self.i8()? },
+            25 => Instruction::JumpIfNil { offset: self.i8()? },
+            26 => Instruction::Jump { offset: self.i8()? },
+            27 => Instruction::Placeholder,
+            28 => Instruction::Print,
+            29 => Instruction::FuncDef {
+                proto_index: self.u32()?,
+                has_env: self.bool()?,
+            },
+            30 => Instruction::Call {
+                args_len: self.u8()?,
+            },
+            31 => Instruction::GetUpval { index: self.u16()? },
+            32 => Instruction::SetUpval { index: self.u16()? },
+            33 => Instruction::CloseUpval { index: self.u8()? },
+            34 => Instruction::Integer(self.u32()? as i32),
+            35 => Instruction::Import {
+                path_index: self.u8()?,
+            },
+            36 => Instruction::ImportFrom {
+                path_index: self.u8()?,
+                name_index: self.u8()?,
+            },
+            37 => Instruction::ExitBlock {
+                pop: self.u16()?,
+                return_value: self.bool()?,
+            },
+            38 => Instruction::Rec,
+            39 => Instruction::Range {
+                inclusive: self.bool()?,
+            },
+            40 => Instruction::Iter,
+            41 => Instruction::IterNext { len: self.u8()? },
+            op => return Err(BytecodeError::InvalidOpcode(op)),
+        })
+    }
+
+    fn binary(&mut self) -> Result<BinaryInstr> {
+        const BINARY: [BinaryInstr; 16] = [
+            BinaryInstr::Add,
+            BinaryInstr::Sub,
+            BinaryInstr::Mul,
+            BinaryInstr::Div,
+            BinaryInstr::Gt,
+            BinaryInstr::Lt,
+            BinaryInstr::Ge,
+            BinaryInstr::Le,
+            BinaryInstr::Eq,
+            BinaryInstr::Ne,
+            BinaryInstr::Rem,
+            BinaryInstr::BitAnd,
+            BinaryInstr::BitOr,
+            BinaryInstr::BitXor,
+            BinaryInstr::Shl,
+            BinaryInstr::Shr,
+        ];
+        let op = self.u8()?;
+        BINARY
+            .get(op as usize)
+            .copied()
+            .ok_or(BytecodeError::InvalidOpcode(op))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn compiled(instructions: Vec<Instruction>, constants: Vec<Value>) -> CompiledSource {
+        let mut chunk = Chunk::new();
+        *chunk.instructions_mut() = instructions;
+        CompiledSource {
+            chunk,
+            constant_table: Rc::new(ConstantTableStruct {
+                constants,
+                prototypes: Vec::new(),
+            }),
+        }
+    }
+
+    #[test]
+    fn instructions_round_trip() {
+        let instructions = vec![
+            Instruction::Bin(BinaryInstr::Shr),
+            Instruction::Unary(UnaryInstr::BitNot),
+            Instruction::Unary(UnaryInstr::ToString),
+            Instruction::SetLocal {
+                index: 300,
+                frame: 2,
+            },
+            Instruction::GetMethodImm {
+                index: 0,
+                table_stack_index: 3,
+            },
+            Instruction::InitTable {
+                len: 1000,
+                has_keys: true,
+            },
+            Instruction::JumpIfOrPop {
+                when_true: true,
+                offset: 1,
+            },
+            Instruction::Jump { offset: -6 },
+            Instruction::Integer(-70_000),
+            Instruction::ExitBlock {
+                pop: 2,
+                return_value: false,
+            },
+            Instruction::Range { inclusive: true },
+            Instruction::IterNext { len: 2 },
+            Instruction::Return { return_value: true },
+        ];
+        let constants = vec![
+            Value::new_str("name"),
+            Value::Int(i64::MIN),
+            Value::Number(-0.5),
+            Value::Bool(true),
+            Value::Nil,
+        ];
+        let source = compiled(instructions, constants);
+        let bytes = source.to_bytes().unwrap();
+        assert_eq!(&bytes[..4], b"FLXC");
+        assert_eq!(
+            CompiledSource::from_bytes(&bytes, PathBuf::new()),
+            Ok(source)
+        );
+    }
+
+    #[test]
+    fn invalid_bytecode_is_rejected() {
+        let source = compiled(
+            vec![
+                Instruction::Constant { index: 0 },
+                Instruction::Print,
+                Instruction::Return {
+                    return_value: false,
+                },
+            ],
+            vec![Value::new_str("text")],
+        );
+        let bytes = source.to_bytes().unwrap();
+        let load = |bytes: &[u8]| CompiledSource::from_bytes(bytes, PathBuf::new());
+        assert_eq!(load(b"FLX"), Err(BytecodeError::InvalidMagic));
+        let mut newer = bytes.clone();
+        newer[4] = 2;
+        assert_eq!(load(&newer), Err(BytecodeError::UnsupportedVersion(2)));
+        assert_eq!(
+            load(&bytes[..bytes.len() - 1]),
+            Err(BytecodeError::ChecksumMismatch)
+        );
+        // Any corrupted byte is an error
+        for i in 0..bytes.len() {
+            let mut corrupt = bytes.clone();
+            corrupt[i] ^= 0x55;
+            assert!(load(&corrupt).is_err());
+        }
+        // Well formed but inconsistent sources
+        let invalid = [
+            (
+                compiled(vec![Instruction::GetGlobal { index: 3 }], vec![]),
+                BytecodeError::ConstantOutOfBounds(3),
+            ),
+            (
+                compiled(
+                    vec![Instruction::FuncDef {
+                        proto_index: 0,
+                        has_env: false,
+                    }],
+                    vec![],
+                ),
+                BytecodeError::PrototypeOutOfBounds(0),
+            ),
+            (
+                compiled(vec![Instruction::Jump { offset: 2 }], vec![]),
+                BytecodeError::JumpOutOfBounds { pc: 0, offset: 2 },
+            ),
+            (
+                compiled(
+                    vec![Instruction::Import { path_index: 0 }],
+                    vec![Value::new_str("lib")],
+                ),
+                BytecodeError::MissingModule("lib".to_string()),
+            ),
+        ];
+        for (source, error) in invalid {
+            assert_eq!(load(&source.to_bytes().unwrap()), Err(error));
+        }
+    }
+
+    #[test]
+    fn malformed_code_fails_at_runtime() {
+        use crate::vm::{Limits, RuntimeError, Vm};
+
+        let run = |instructions: Vec<Instruction>| {
+            let bytes = compiled(instructions, vec![Value::new_str("x")])
+                .to_bytes()
+                .unwrap();
+            let source = CompiledSource::from_bytes(&bytes, PathBuf::new()).unwrap();
+            Vm::new()
+                .with_limits(Limits {
+                    instruction_budget: Some(10_000),
+                    ..Limits::default()
+                })
+                .run(source)
+        };
+        let ret = Instruction::Return { return_value: true };
+        assert_eq!(
+            run(vec![Instruction::SetGlobal { index: 0 }, ret]),
+            Err(RuntimeError::EmptyStack)
+        );
+        assert_eq!(
+            run(vec![
+                Instruction::GetLocal {
+                    index: 500,
+                    frame: 0
+                },
+                ret
+            ]),
+            Err(RuntimeError::InvalidLocal { index: 500 })
+        );
+        assert_eq!(
+            run(vec![
+                Instruction::Unit,
+                Instruction::SetLocal {
+                    index: 500,
+                    frame: 3
+                },
+                ret
+            ]),
+            Err(RuntimeError::EmptyFrame)
+        );
+        assert_eq!(
+            run(vec![
+                Instruction::GetMethodImm {
+                    index: 0,
+                    table_stack_index: 9
+                },
+                ret
+            ]),
+            Err(RuntimeError::EmptyStack)
+        );
+        assert_eq!(
+            run(vec![Instruction::Call { args_len: 0 }, ret]),
+            Err(RuntimeError::EmptyStack)
+        );
+        assert_eq!(
+            run(vec![Instruction::Unit]),
+            Err(RuntimeError::PcOutOfBounds { pc: 1 })
+        );
+        assert_eq!(
+            run(vec![Instruction::Rec, ret]),
+            Err(RuntimeError::EmptyFrame)
+        );
+        // Jumps from 0 to 127 to 129 to 1, as far back as an offset goes
+        let mut jumps = vec![Instruction::Nil; 130];
+        jumps[0] = Instruction::Jump { offset: 127 };
+        jumps[1] = Instruction::Unit;
+        jumps[2] = ret;
+        jumps[127] = Instruction::Jump { offset: 2 };
+        jumps[129] = Instruction::Jump { offset: -128 };
+        jumps.push(ret);
+        assert_eq!(run(jumps), Ok(Value::Unit));
+        // A jump back to the first instruction
+        let looping = vec![
+            Instruction::Integer(1),
+            Instruction::Jump { offset: -1 },
+            ret,
+        ];
+        assert_eq!(
+            run(looping),
+            Err(RuntimeError::BudgetExhausted { budget: 10_000 })
+        );
+    }
+}
@@ -1,5 +1,6 @@
 use super::{CompileError, CompileResult, ConstantTableStruct, Instruction};
 use std::collections::HashMap;
+use std::path::{Path, PathBuf};
 use std::rc::Rc;
 
 #[derive(Clone, Debug, PartialEq)]
@@ -12,6 +13,10 @@ pub struct CompiledSource {
 pub struct Chunk {
     instructions: Vec<Instruction>,
     imports: HashMap<String, Chunk>,
+    // Globals visible to importers, all of them when the module doesn't export
+    exports: Option<Vec<String>>,
+    // Directory of the source, runtime imports are resolved relative to it
+    dir: PathBuf,
 }
 
 #[derive(Clone, Debug, PartialEq)]
@@ -25,6 +30,37 @@ pub enum JumpCondition {
     None,
     WhenTrue,
     WhenFalse,
+    // The value is kept when jumping
+    WhenTrueOrPop,
+    WhenFalseOrPop,
+    WhenNotNilOrPop,
+    WhenNil,
+}
+
+impl JumpCondition {
+    pub fn jump(self, offset: i8) -> Instruction {
+        match self {
+            JumpCondition::None => Instruction::Jump { offset },
+            JumpCondition::WhenTrue => Instruction::JumpIf {
+                when_true: true,
+                offset,
+            },
+            JumpCondition::WhenFalse => Instruction::JumpIf {
+                when_true: false,
+                offset,
+            },
+            JumpCondition::WhenTrueOrPop => Instruction::JumpIfOrPop {
+                when_true: true,
+                offset,
+            },
+            JumpCondition::WhenFalseOrPop => Instruction::JumpIfOrPop {
+                when_true: false,
+                offset,
+            },
+            JumpCondition::WhenNotNilOrPop => Instruction::JumpIfNotNilOrPop { offset },
+            JumpCondition::WhenNil => Instruction::JumpIfNil { offset },
+        }
+    }
 }
 
 impl Chunk {
@@ -32,20 +68,27 @@ impl Chunk {
         Self::default()
     }
 
+    pub fn with_dir(mut self, dir: PathBuf) -> Self {
+        self.dir = dir;
+        self
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
     #[inline]
     fn push_instr(&mut self, instr: Instruction) -> CompileResult<()> {
         self.instructions.push(instr);
         Ok(())
     }
 
-    pub fn add_import(&mut self, import: Chunk, name: String, name_index: u8) -> CompileResult<()> {
-        if self.imports.contains_key(&name) {
-            panic!("module '{}' is already imported", &name);
-        }
-        // let name_index = self.add_constant(name.clone().into())?;
-        self.push_instr(Instruction::Import { name_index })?;
-        self.imports.insert(name, import);
-        Ok(())
+    pub fn add_exports(&mut self, names: Vec<String>) {
+        self.exports.get_or_insert_with(Vec::new).extend(names);
+    }
+
+    pub fn exports(&self) -> Option<&[String]> {
+        self.exports.as_deref()
     }
 
     pub fn push_placeholder(&mut self) -> CompileResult<usize> {
@@ -60,18 +103,7 @@ impl Chunk {
         jump_offset: i8,
         jump_cond: JumpCondition,
     ) -> CompileResult<()> {
-        let offset = jump_offset;
-        let instr = match jump_cond {
-            JumpCondition::None => Instruction::Jump { offset },
-            JumpCondition::WhenTrue => Instruction::JumpIf {
-                when_true: true,
-                offset,
-            },
-            JumpCondition::WhenFalse => Instruction::JumpIf {
-                when_true: false,
-                offset,
-            },
-        };
+        let instr = jump_cond.jump(jump_offset);
         match self.instructions[index] {
             Instruction::Placeholder | Instruction::Jump { .. } | Instruction::JumpIf { .. } => {
                 self.instructions[index] = instr;
@@ -93,8 +125,12 @@ impl Chunk {
         std::mem::replace(&mut self.imports, HashMap::new())
     }
 
-    pub fn imports(&mut self) -> &mut HashMap<String, Chunk> {
-        &mut self.imports
+    pub fn imports(&self) -> &HashMap<String, Chunk> {
+        &self.imports
+    }
+
+    pub fn add_import(&mut self, key: String, chunk: Chunk) {
+        self.imports.insert(key, chunk);
     }
 }
 
@@ -103,6 +139,8 @@ impl Default for Chunk {
         Chunk {
             instructions: Vec::new(),
             imports: HashMap::new(),
+            exports: None,
+            dir: PathBuf::new(),
         }
     }
 }
@@ -0,0 +1,170 @@
+use super::{Chunk, CompiledSource, Instruction};
+use crate::vm::Value;
+use std::collections::BTreeSet;
+use std::convert::TryFrom;
+use std::fmt::Write;
+
+impl CompiledSource {
+    /// Lists the instructions of the source, its imported modules and function prototypes.
+    /// Constant operands are followed by their values and jumps by the labels they go to.
+    pub fn disassemble(&self) -> String {
+        let mut out = String::new();
+        let constants = &self.constant_table.constants;
+        chunk(&mut out, "main", self.chunk.instructions(), constants);
+        let mut modules: Vec<_> = self.chunk.imports().iter().collect();
+        modules.sort_by(|a, b| a.0.cmp(b.0));
+        for (key, module) in modules {
+            chunk(
+                &mut out,
+                &module_title(key, module),
+                module.instructions(),
+                constants,
+            );
+        }
+        for (i, proto) in self.constant_table.prototypes.iter().enumerate() {
+            let title = format!("proto {} ({} args)", i, proto.args_len);
+            chunk(&mut out, &title, &proto.instructions, constants);
+        }
+        out
+    }
+}
+
+fn module_title(key: &str, module: &Chunk) -> String {
+    match module.exports() {
+        Some(names) => format!("module {} (exports {})", key, names.join(", ")),
+        None => format!("module {}", key),
+    }
+}
+
+fn chunk(out: &mut String, title: &str, instructions: &[Instruction], constants: &[Value]) {
+    if !out.is_empty() {
+        out.push('\n');
+    }
+    writeln!(out, "== {} ==", title).unwrap();
+    let labels: BTreeSet<_> = instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(pc, instr)| jump_target(pc, *instr))
+        .collect();
+    for (pc, instr) in instructions.iter().enumerate() {
+        if labels.contains(&pc) {
+            writeln!(out, "L{}:", pc).unwrap();
+        }
+        writeln!(out, "{:>6}  {}", pc, instruction(pc, *instr, constants)).unwrap();
+    }
+}
+
+fn jump_target(pc: usize, instr: Instruction) -> Option<usize> {
+    let offset = match instr {
+        Instruction::Jump { offset }
+        | Instruction::JumpIf { offset, .. }
+        | Instruction::JumpIfOrPop { offset, .. }
+        | Instruction::JumpIfNotNilOrPop { offset }
+        | Instruction::JumpIfNil { offset } => offset,
+        _ => return None,
+    };
+    usize::try_from(pc as isize + offset as isize).ok()
+}
+
+fn instruction(pc: usize, instr: Instruction, constants: &[Value]) -> String {
+    let value = |index: u8| match constants.get(index as usize) {
+        Some(Value::Str(string)) => format!("{:?}", string.as_str()),
+        Some(value) => value.to_string(),
+        None => "<invalid>".to_string(),
+    };
+    let constant = |index: u8| format!("{:<4} ; {}", index, value(index));
+    let label = || match jump_target(pc, instr) {
+        Some(target) => format!("L{}", target),
+        None => "<invalid>".to_string(),
+    };
+    let (name, operands) = match instr {
+        Instruction::Bin(bin) => ("Bin", format!("{:?}", bin)),
+        Instruction::Unary(unary) => ("Unary", format!("{:?}", unary)),
+        Instruction::Nil => ("Nil", String::new()),
+        Instruction::Unit => ("Unit", String::new()),
+        Instruction::True => ("True", String::new()),
+        Instruction::False => ("False", String::new()),
+        Instruction::Constant { index } => ("Constant", constant(index)),
+        Instruction::SetGlobal { index } => ("SetGlobal", constant(index)),
+        Instruction::GetGlobal { index } => ("GetGlobal", constant(index)),
+        Instruction::SetLocal { index, frame } => {
+            ("SetLocal", format!("{} frame {}", index, frame))
+        }
+        Instruction::GetLocal { index, frame } => {
+            ("GetLocal", format!("{} frame {}", index, frame))
+        }
+        Instruction::GetMethodImm {
+            index,
+            table_stack_index,
+        } => (
+            "GetMethodImm",
+            format!("{} table {} ; {}", index, table_stack_index, value(index)),
+        ),
+        Instruction::GetFieldImm { index } => ("GetFieldImm", constant(index)),
+        Instruction::GetField => ("GetField", String::new()),
+        Instruction::SetFieldImm { index } => ("SetFieldImm", constant(index)),
+        Instruction::SetField => ("SetField", String::new()),
+        Instruction::SetIndex => ("SetIndex", String::new()),
+        Instruction::Dup { len } => ("Dup", len.to_string()),
+        Instruction::Pop => ("Pop", String::new()),
+        Instruction::Return { return_value } => ("Return", value_flag(return_value)),
+        Instruction::Tuple { len } => ("Tuple", len.to_string()),
+        Instruction::InitTable { len, has_keys } => (
+            "InitTable",
+            format!("{}{}", len, if has_keys { " keys" } else { "" }),
+        ),
+        Instruction::JumpIf { when_true, .. } => {
+            ("JumpIf", format!("{} -> {}", when_true, label()))
+        }
+        Instruction::JumpIfOrPop { when_true, .. } => {
+            ("JumpIfOrPop", format!("{} -> {}", when_true, label()))
+        }
+        Instruction::JumpIfNotNilOrPop { .. } => ("JumpIfNotNilOrPop", format!("-> {}", label())),
+        Instruction::JumpIfNil { .. } => ("JumpIfNil", format!("-> {}", label())),
+        Instruction::Jump { .. } => ("Jump", format!("-> {}", label())),
+        Instruction::Placeholder => ("Placeholder", String::new()),
+        Instruction::Print => ("Print", String::new()),
+        Instruction::FuncDef {
+            proto_index,
+            has_env,
+        } => (
+            "FuncDef",
+            format!("proto {}{}", proto_index, if has_env { " env" } else { "" }),
+        ),
+        Instruction::Call { args_len } => ("Call", format!("{} args", args_len)),
+        Instruction::GetUpval { index } => ("GetUpval", index.to_string()),
+        Instruction::SetUpval { index } => ("SetUpval", index.to_string()),
+        Instruction::CloseUpval { index } => ("CloseUpval", index.to_string()),
+        Instruction::Integer(n) => ("Integer", n.to_string()),
+        Instruction::Import { path_index } => ("Import", constant(path_index)),
+        Instruction::ImportFrom {
+            path_index,
+            name_index,
+        } => (
+            "ImportFrom",
+            format!(
+                "{} {} ; {} {}",
+                path_index,
+                name_index,
+                value(path_index),
+                value(name_index)
+            ),
+        ),
+        Instruction::ExitBlock { pop, return_value } => (
+            "ExitBlock",
+            format!("pop {} {}", pop, value_flag(return_value)),
+        ),
+        Instruction::Rec => ("Rec", String::new()),
+        Instruction::Range { inclusive } => (
+            "Range",
+            if inclusive { "inclusive" } else { "exclusive" }.to_string(),
+        ),
+        Instruction::Iter => ("Iter", String::new()),
+        Instruction::IterNext { len } => ("IterNext", len.to_string()),
+    };
+    format!("{:<18}{}", name, operands).trim_end().to_string()
+}
+
+fn value_flag(return_value: bool) -> String {
+    if return_value { "value" } else { "unit" }.to_string()
+}
@@ -1,6 +1,7 @@
 use crate::compiler::Instruction;
 use crate::parser::{Expr, ParserError};
 use std::io;
+use std::path::PathBuf;
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum CompileError {
@@ -18,6 +19,18 @@ pub enum CompileError {
         name: String,
         error: Box<CompileError>,
     },
+    ImportDisabled {
+        name: String,
+    },
+    // Every file the import was looked for in
+    ModuleNotFound {
+        name: String,
+        attempted: Vec<PathBuf>,
+    },
+    // The canonical paths of the modules importing each other, the first one is repeated last
+    CircularImport {
+        cycle: Vec<String>,
+    },
 }
 
 impl From<ParserError> for CompileError {
@@ -55,6 +55,12 @@ pub enum Instruction {
      * Pop the table, pop the key then pop the value then set the value to the respective key
      */
     SetField,
+    /* Pop the value, pop the key then pop the table and set the key to the value */
+    SetIndex,
+    /* Push copies of the top len values */
+    Dup {
+        len: u8,
+    },
     /*Simply pop the top value from stack */
     Pop,
     /*Pop the value and return */
@@ -75,6 +81,19 @@ pub enum Instruction {
         when_true: bool,
         offset: i8,
     },
+    /* Peek value if truth value matches with 'when_true' then branch, otherwise pop it */
+    JumpIfOrPop {
+        when_true: bool,
+        offset: i8,
+    },
+    /* Peek value if it isn't nil then branch, otherwise pop it */
+    JumpIfNotNilOrPop {
+        offset: i8,
+    },
+    /* Peek value if it is nil then branch */
+    JumpIfNil {
+        offset: i8,
+    },
     /* Directly jump */
     Jump {
         offset: i8,
@@ -100,8 +119,13 @@ pub enum Instruction {
         index: u8,
     },
     Integer(i32),
-    // Run the file then push the global table to stack
+    // Run the module once then push its export table to stack
     Import {
+        path_index: u8,
+    },
+    // Push a value exported by the module
+    ImportFrom {
+        path_index: u8,
         name_index: u8,
     },
     ExitBlock {
@@ -110,6 +134,16 @@ pub enum Instruction {
     },
     // rercursive call
     Rec,
+    /* Pop the end then the start and push a range */
+    Range {
+        inclusive: bool,
+    },
+    /* Pop a value and push an iterator over it */
+    Iter,
+    /* Pop the iterator then push the values of its next item and 'true', or 'false' if finished */
+    IterNext {
+        len: u8,
+    },
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
@@ -125,12 +159,19 @@ pub enum BinaryInstr {
     Eq,
     Ne,
     Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    Shl,
+    Shr,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq)]
 pub enum UnaryInstr {
     Negate,
     Not,
+    BitNot,
+    ToString,
 }
 
 impl BinaryInstr {
@@ -144,4 +185,15 @@ impl BinaryInstr {
             _ => false,
         }
     }
+
+    pub fn is_bitwise(self) -> bool {
+        matches!(
+            self,
+            BinaryInstr::BitAnd
+                | BinaryInstr::BitOr
+                | BinaryInstr::BitXor
+                | BinaryInstr::Shl
+                | BinaryInstr::Shr
+        )
+    }
 }
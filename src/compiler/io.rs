@@ -1,13 +1,13 @@
 use super::{CompileError, CompileResult};
 use std::fs::File;
 use std::io::Read;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-pub fn absolute_path<S>(dir: &str, file_path: &[S]) -> PathBuf
+pub fn absolute_path<S>(dir: impl AsRef<Path>, file_path: &[S]) -> PathBuf
 where
     S: AsRef<str>,
 {
-    let mut path = PathBuf::from(dir);
+    let mut path = dir.as_ref().to_path_buf();
     for p in file_path {
         path.push(p.as_ref());
     }
@@ -15,15 +15,25 @@ where
     path
 }
 
-pub fn read_file(path: PathBuf) -> CompileResult<String> {
-    match File::open(path) {
-        Ok(mut file) => {
-            let mut string = String::new();
-            file.read_to_string(&mut string).expect("failed to write");
-            Ok(string)
-        }
-        Err(err) => Err(CompileError::IoError(err.kind())),
+// A directory imported as a module runs its 'init.flux'
+pub fn package_path<S>(dir: impl AsRef<Path>, file_path: &[S]) -> PathBuf
+where
+    S: AsRef<str>,
+{
+    let mut path = dir.as_ref().to_path_buf();
+    for p in file_path {
+        path.push(p.as_ref());
     }
+    path.push("init.flux");
+    path
+}
+
+pub fn read_file(path: PathBuf) -> CompileResult<String> {
+    let mut string = String::new();
+    File::open(path)
+        .and_then(|mut file| file.read_to_string(&mut string))
+        .map_err(|err| CompileError::IoError(err.kind()))?;
+    Ok(string)
 }
 
 #[cfg(test)]
@@ -38,6 +48,8 @@ mod tests {
 
         assert!(path.starts_with("src/lib/math"));
         assert_eq!(path, PathBuf::from("src/lib/math/sqrt.flux"));
+        let path = package_path(dir, &file_path);
+        assert_eq!(path, PathBuf::from("src/lib/math/sqrt/init.flux"));
     }
 
     #[test]
@@ -0,0 +1,137 @@
+use super::io::{absolute_path, package_path, read_file};
+use super::{Chunk, CompileError, CompileOptions, CompileResult};
+use crate::parser::{Ast, Parser, ParserError};
+use crate::vm::lib::STD_MODULES;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Modules imported while compiling a source, keyed by their canonical path.
+/// Each module is compiled once however many times it is imported.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ModuleRegistry {
+    chunks: HashMap<String, Chunk>,
+    // Modules being compiled, the last one is the innermost import
+    compiling: Vec<String>,
+}
+
+impl ModuleRegistry {
+    pub fn contains(&self, key: &str) -> bool {
+        self.chunks.contains_key(key)
+    }
+
+    // Fails if the module is already being compiled higher up the imports
+    pub fn start(&mut self, key: &str) -> CompileResult<()> {
+        match self.compiling.iter().position(|module| module == key) {
+            Some(start) => {
+                let mut cycle = self.compiling[start..].to_vec();
+                cycle.push(key.to_string());
+                Err(CompileError::CircularImport { cycle })
+            }
+            None => {
+                self.compiling.push(key.to_string());
+                Ok(())
+            }
+        }
+    }
+
+    pub fn finish(&mut self, key: String, chunk: Chunk) {
+        self.compiling.pop();
+        self.chunks.insert(key, chunk);
+    }
+
+    pub fn into_chunks(self) -> HashMap<String, Chunk> {
+        self.chunks
+    }
+}
+
+/// Source of an imported module and the key it is registered with
+pub struct ModuleSource {
+    pub key: String,
+    pub source: String,
+    // Imports of the module are resolved relative to it
+    pub dir: PathBuf,
+}
+
+impl ModuleSource {
+    /// Parses the module, it can use the natives of the importing vm
+    pub fn parse(&self, options: &CompileOptions) -> Result<Ast, ParserError> {
+        let mut parser = Parser::new(&self.source)?;
+        match &options.natives {
+            Some(natives) => parser.with_globals(natives.iter().cloned()).parse(),
+            None => parser.parse(),
+        }
+    }
+}
+
+// Directory of the embedded std modules, they import each other without the `std` prefix
+const STD_DIR: &str = "<std>";
+
+/// Finds the module in the standard library, then the directory of the importing file,
+/// then the search paths. Each directory is tried as `a/b.flux` then `a/b/init.flux`.
+pub fn resolve_module(
+    path: &[String],
+    current_dir: &Path,
+    search_paths: &[PathBuf],
+) -> CompileResult<ModuleSource> {
+    let std_name = match path {
+        [std, name @ ..] if std == "std" => Some(name.join("/")),
+        _ if current_dir == Path::new(STD_DIR) => Some(path.join("/")),
+        _ => None,
+    };
+    if let Some(name) = std_name {
+        if let Some((_, source)) = STD_MODULES.iter().find(|(module, _)| *module == name) {
+            return Ok(ModuleSource {
+                key: format!("std:{}", name),
+                source: source.to_string(),
+                dir: PathBuf::from(STD_DIR),
+            });
+        }
+    }
+    let mut attempted = Vec::new();
+    for dir in std::iter::once(current_dir).chain(search_paths.iter().map(PathBuf::as_path)) {
+        for file in [absolute_path(dir, path), package_path(dir, path)] {
+            if file.is_file() {
+                let source =
+                    read_file(file.clone()).map_err(|error| CompileError::ModuleError {
+                        name: path.join("."),
+                        error: Box::new(error),
+                    })?;
+                // The same file imported through different paths is the same module
+                let file = file.canonicalize().unwrap_or(file);
+                return Ok(ModuleSource {
+                    key: file.to_string_lossy().into_owned(),
+                    source,
+                    dir: file
+                        .parent()
+                        .expect("Expected a parent directory")
+                        .to_owned(),
+                });
+            }
+            attempted.push(file);
+        }
+    }
+    Err(CompileError::ModuleNotFound {
+        name: path.join("."),
+        attempted,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn std_modules_resolve() {
+        let path = |path: &str| -> Vec<String> { path.split('.').map(str::to_string).collect() };
+        let module = resolve_module(&path("std.array"), Path::new(""), &[]).unwrap();
+        assert_eq!(module.key, "std:array");
+        assert_eq!(module.dir, Path::new(STD_DIR));
+        // Inside the std modules the prefix is optional
+        let module = resolve_module(&path("array"), &module.dir, &[]).unwrap();
+        assert_eq!(module.key, "std:array");
+        assert!(matches!(
+            resolve_module(&path("array"), Path::new(""), &[]),
+            Err(CompileError::ModuleNotFound { .. })
+        ));
+    }
+}
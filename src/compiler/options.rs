@@ -0,0 +1,37 @@
+use std::path::PathBuf;
+
+/// Environment variable listing module search paths, separated like `PATH`
+const FLUX_PATH: &str = "FLUX_PATH";
+
+/// Options that change how sources are compiled
+#[derive(Clone, Debug, PartialEq)]
+pub struct CompileOptions {
+    /// Whether import statements are allowed
+    pub imports: bool,
+    /// Directories searched in order for the imports not found next to the importing file
+    pub search_paths: Vec<PathBuf>,
+    /// Globals declared in the imported modules, all the predefined natives if none
+    pub natives: Option<Vec<String>>,
+}
+
+impl CompileOptions {
+    /// The search paths in `FLUX_PATH`, none if it is not set
+    pub fn env_search_paths() -> Vec<PathBuf> {
+        match std::env::var_os(FLUX_PATH) {
+            Some(paths) => std::env::split_paths(&paths)
+                .filter(|path| !path.as_os_str().is_empty())
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+}
+
+impl Default for CompileOptions {
+    fn default() -> Self {
+        CompileOptions {
+            imports: true,
+            search_paths: Vec::new(),
+            natives: None,
+        }
+    }
+}
@@ -1,8 +1,9 @@
-use super::compiler::CompileError;
+use super::compiler::{BytecodeError, CompileError};
 use super::parser::ParserError;
 use super::scanner::LexError;
 use super::vm::RuntimeError;
 use std::fmt::{Display, Formatter};
+use std::io;
 
 pub type FluxResult<T> = std::result::Result<T, FluxError>;
 
@@ -12,6 +13,8 @@ pub enum FluxError {
     Parse(ParserError),
     Compile(CompileError),
     Runtime(Box<RuntimeError>),
+    Io(io::ErrorKind),
+    Bytecode(BytecodeError),
 }
 
 impl From<LexError> for FluxError {
@@ -38,6 +41,18 @@ impl From<RuntimeError> for FluxError {
     }
 }
 
+impl From<BytecodeError> for FluxError {
+    fn from(error: BytecodeError) -> Self {
+        FluxError::Bytecode(error)
+    }
+}
+
+impl From<io::Error> for FluxError {
+    fn from(error: io::Error) -> Self {
+        FluxError::Io(error.kind())
+    }
+}
+
 impl Display for FluxError {
     fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
         match self {
@@ -46,6 +61,8 @@ impl Display for FluxError {
             FluxError::Compile(c) => write!(f, "{:?}", c),
             FluxError::Runtime(r) => write!(f, "{:?}", r),
             FluxError::Parse(c) => write!(f, "{}", c),
+            FluxError::Io(kind) => write!(f, "IO Error: {:?}", kind),
+            FluxError::Bytecode(b) => write!(f, "Invalid bytecode: {:?}", b),
         }
     }
 }
@@ -0,0 +1,662 @@
+mod builder;
+
+use crate::compiler::{CompileOptions, CompiledSource, Compiler};
+use crate::error::FluxResult;
+use crate::parser::Parser;
+use crate::scanner::Token;
+use crate::sourcefile::{MetaData, SourceFile};
+use crate::vm::{InterruptHandle, IntoNative, RuntimeError, UserData, Value, Vm};
+pub use builder::VmBuilder;
+use std::fs;
+use std::path::Path;
+use std::vec;
+
+/// An interpreter instance. Globals persist between evaluations.
+pub struct Flux {
+    vm: Vm,
+    options: CompileOptions,
+}
+
+impl Flux {
+    /// Creates an interpreter with all natives and imports enabled
+    pub fn new() -> Self {
+        VmBuilder::new().build()
+    }
+
+    pub fn builder() -> VmBuilder {
+        VmBuilder::new()
+    }
+
+    pub fn eval_str(&mut self, source: &str) -> FluxResult<Value> {
+        self.eval(source, MetaData::default())
+    }
+
+    /// Evaluates a line typed in a REPL. Its top level `let` bindings are kept as globals,
+    /// so the next lines can use them.
+    pub fn eval_line(&mut self, source: &str) -> FluxResult<Value> {
+        let compiled = self.compile_line(source, MetaData::default())?;
+        Ok(self.vm.run(compiled)?)
+    }
+
+    /// Evaluates a file like `eval_line`, its top level bindings stay defined
+    pub fn load_file(&mut self, path: impl AsRef<Path>) -> FluxResult<Value> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let compiled = self.compile_line(&source, MetaData { dir })?;
+        Ok(self.vm.run(compiled)?)
+    }
+
+    /// Evaluates a file, its imports are resolved relative to its directory.
+    /// Files with the `fluxc` extension are run as bytecode.
+    pub fn eval_file(&mut self, path: impl AsRef<Path>) -> FluxResult<Value> {
+        let compiled = self.compile_or_load(path.as_ref())?;
+        Ok(self.vm.run(compiled)?)
+    }
+
+    /// Compiles a file with its imports to bytecode which `eval_bytecode` runs
+    pub fn compile_file(&self, path: impl AsRef<Path>) -> FluxResult<Vec<u8>> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path)?;
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        Ok(self.compile(&source, MetaData { dir })?.to_bytes()?)
+    }
+
+    /// Runs bytecode produced by `compile_file`, it is validated before running
+    pub fn eval_bytecode(&mut self, bytes: &[u8]) -> FluxResult<Value> {
+        let compiled = CompiledSource::from_bytes(bytes, MetaData::default().dir)?;
+        Ok(self.vm.run(compiled)?)
+    }
+
+    /// Parses, analyzes and compiles the source without running it
+    pub fn check_str(&self, source: &str) -> FluxResult<()> {
+        self.compile(source, MetaData::default()).map(|_| ())
+    }
+
+    /// Like `check_str`, the imports of the file are compiled too
+    pub fn check_file(&self, path: impl AsRef<Path>) -> FluxResult<()> {
+        self.compile_or_load(path.as_ref()).map(|_| ())
+    }
+
+    /// Lists the instructions the source compiles to, with those of its functions and imports
+    pub fn disassemble_str(&self, source: &str) -> FluxResult<String> {
+        Ok(self.compile(source, MetaData::default())?.disassemble())
+    }
+
+    /// Like `disassemble_str`, with the bindings compiled the way `eval_line` does
+    pub fn disassemble_line(&self, source: &str) -> FluxResult<String> {
+        Ok(self
+            .compile_line(source, MetaData::default())?
+            .disassemble())
+    }
+
+    /// Like `disassemble_str`, files with the `fluxc` extension are loaded as bytecode
+    pub fn disassemble_file(&self, path: impl AsRef<Path>) -> FluxResult<String> {
+        Ok(self.compile_or_load(path.as_ref())?.disassemble())
+    }
+
+    /// Calls the global function with the arguments in source order
+    pub fn call_function(&mut self, name: &str, args: Vec<Value>) -> FluxResult<Value> {
+        let function =
+            self.vm
+                .get_global(name)
+                .cloned()
+                .ok_or_else(|| RuntimeError::UndefinedVariable {
+                    name: name.to_string(),
+                })?;
+        Ok(self.vm.call_value(function, args)?)
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<Value> {
+        self.vm.get_global(name).cloned()
+    }
+
+    /// Defines or overwrites a global, it is visible to the sources evaluated afterwards
+    pub fn set_global(&mut self, name: &str, value: impl Into<Value>) {
+        self.vm.set_global(name, value.into())
+    }
+
+    /// Registers a Rust closure as a global native function
+    pub fn register<Args>(&mut self, name: &str, function: impl IntoNative<Args>) {
+        self.vm.register(name, function)
+    }
+
+    /// Registers a Rust closure as a method of the userdata type `T`
+    pub fn register_method<T: UserData, Args>(
+        &mut self,
+        name: &str,
+        function: impl IntoNative<Args>,
+    ) {
+        self.vm.register_method::<T, Args>(name, function)
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.vm.interrupt_handle()
+    }
+
+    pub fn vm(&self) -> &Vm {
+        &self.vm
+    }
+
+    pub fn vm_mut(&mut self) -> &mut Vm {
+        &mut self.vm
+    }
+
+    fn eval(&mut self, source: &str, metadata: MetaData) -> FluxResult<Value> {
+        let compiled = self.compile(source, metadata)?;
+        Ok(self.vm.run(compiled)?)
+    }
+
+    fn compile_or_load(&self, path: &Path) -> FluxResult<CompiledSource> {
+        let dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        if path.extension().is_some_and(|ext| ext == "fluxc") {
+            return Ok(CompiledSource::from_bytes(&fs::read(path)?, dir)?);
+        }
+        let source = fs::read_to_string(path)?;
+        self.compile(&source, MetaData { dir })
+    }
+
+    fn compile(&self, source: &str, metadata: MetaData) -> FluxResult<CompiledSource> {
+        self.compile_parser(Parser::new(source)?, metadata)
+    }
+
+    // Like `compile`, the top level `let` bindings are compiled as globals
+    fn compile_line(&self, source: &str, metadata: MetaData) -> FluxResult<CompiledSource> {
+        let parser = Parser::new(source)?.with_persistent_bindings();
+        self.compile_parser(parser, metadata)
+    }
+
+    fn compile_parser(
+        &self,
+        parser: Parser<vec::IntoIter<Token>>,
+        metadata: MetaData,
+    ) -> FluxResult<CompiledSource> {
+        let mut parser = parser.with_globals(self.vm.global_names());
+        let ast = parser.parse()?;
+        let options = CompileOptions {
+            natives: Some(self.vm.native_names()),
+            ..self.options.clone()
+        };
+        let compiled = Compiler::compile_with(SourceFile { ast, metadata }, options)?;
+        if crate::trace_enabled() {
+            println!("{}", compiled.disassemble());
+        }
+        Ok(compiled)
+    }
+}
+
+impl Default for Flux {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::FluxError;
+    use crate::vm::{Limits, Overflow};
+
+    #[test]
+    fn globals_persist() {
+        let mut flux = Flux::new();
+        flux.eval_str("var x = 5;").unwrap();
+        assert_eq!(flux.eval_str("x * 2"), Ok(Value::Int(10)));
+        assert_eq!(flux.get_global("x"), Some(Value::Int(5)));
+    }
+
+    #[test]
+    fn set_global_works() {
+        let mut flux = Flux::new();
+        flux.set_global("answer", 42);
+        assert_eq!(flux.eval_str("answer + 1"), Ok(Value::Int(43)));
+    }
+
+    #[test]
+    fn call_function_works() {
+        let mut flux = Flux::new();
+        flux.eval_str("var sub = fn(a, b) a - b end;").unwrap();
+        // A later evaluation replaces the compiled source
+        flux.eval_str("var unused = \"unused\";").unwrap();
+        assert_eq!(
+            flux.call_function("sub", vec![Value::Int(5), Value::Int(3)]),
+            Ok(Value::Int(2))
+        );
+        assert_eq!(
+            flux.call_function("int", vec![Value::new_str("12")]),
+            Ok(Value::Int(12))
+        );
+        assert_eq!(
+            flux.call_function("missing", vec![]),
+            Err(RuntimeError::UndefinedVariable {
+                name: "missing".to_string()
+            }
+            .into())
+        );
+    }
+
+    #[test]
+    fn builder_works() {
+        let mut flux = Flux::builder()
+            .with_natives(vec!["assert"])
+            .with_imports(false)
+            .with_limits(crate::vm::Limits::default().with_instruction_budget(100))
+            .build();
+        assert_eq!(flux.eval_str("assert(true)"), Ok(Value::Unit));
+        // Interpolation doesn't need the `tostring` native
+        assert_eq!(flux.eval_str("\"{1 + 1}\""), Ok(Value::new_str("2")));
+        assert!(flux.get_global("println").is_none());
+        match flux.eval_str("println(1)") {
+            Err(FluxError::Parse(_)) => (),
+            result => panic!("Expected undeclared native, found {:?}", result),
+        }
+        match flux.eval_str("import foo as foo;") {
+            Err(FluxError::Compile(_)) => (),
+            result => panic!("Expected disabled import, found {:?}", result),
+        }
+    }
+
+    #[test]
+    fn overflow_works() {
+        let max = "9223372036854775807";
+        let mut flux = Flux::new();
+        assert_eq!(
+            flux.eval_str(&format!("{} + 1", max)),
+            Err(RuntimeError::IntegerOverflow.into())
+        );
+        let mut flux = Flux::builder().with_overflow(Overflow::Promote).build();
+        assert_eq!(
+            flux.eval_str(&format!("{} * 2", max)),
+            Ok(Value::Number(i64::MAX as f64 * 2.0))
+        );
+        assert_eq!(
+            flux.eval_str("-0x8000_0000_0000_0000"),
+            Ok(Value::Number(-(i64::MIN as f64)))
+        );
+        assert_eq!(flux.eval_str("1 + 2"), Ok(Value::Int(3)));
+    }
+
+    #[test]
+    fn register_works() {
+        use std::cell::Cell;
+        use std::rc::Rc;
+
+        let mut flux = Flux::new();
+        flux.register("repeat", |s: String, n: i64| Ok(s.repeat(n as usize)));
+        let counter = Rc::new(Cell::new(0));
+        let captured = Rc::clone(&counter);
+        flux.register("bump", move || {
+            captured.set(captured.get() + 1);
+            Ok(captured.get())
+        });
+        assert_eq!(
+            flux.eval_str("repeat(\"ab\", 3)"),
+            Ok(Value::new_str("ababab"))
+        );
+        assert_eq!(flux.eval_str("bump(); bump()"), Ok(Value::Int(2)));
+        assert_eq!(counter.get(), 2);
+    }
+
+    #[test]
+    fn native_args_in_source_order() {
+        use crate::vm::{ArgsLen, NativeFunction};
+
+        let mut flux = Flux::new();
+        flux.vm_mut().register_native(
+            "args",
+            NativeFunction::new(|_vm, args| Ok(Value::Tuple(args)), ArgsLen::Variadic),
+        );
+        assert_eq!(
+            flux.eval_str("args(1, 2, 3)"),
+            Ok(Value::Tuple(vec![
+                Value::Int(1),
+                Value::Int(2),
+                Value::Int(3)
+            ]))
+        );
+    }
+
+    #[test]
+    fn register_errors() {
+        let mut flux = Flux::new();
+        flux.register("half", |n: f64, round: Option<bool>| {
+            let half = n / 2.0;
+            Ok(if round == Some(true) {
+                half.round()
+            } else {
+                half
+            })
+        });
+        assert_eq!(flux.eval_str("half(5, nil)"), Ok(Value::Number(2.5)));
+        assert_eq!(flux.eval_str("half(5, true)"), Ok(Value::Number(3.0)));
+        assert_eq!(
+            flux.eval_str("half(5)"),
+            Err(RuntimeError::WrongNumberOfArgs {
+                expected: 2,
+                found: 1
+            }
+            .into())
+        );
+        assert_eq!(
+            flux.eval_str("half(\"5\", nil)"),
+            Err(RuntimeError::InvalidArgument {
+                index: 0,
+                expected: "number",
+                found: "string"
+            }
+            .into())
+        );
+        assert_eq!(
+            flux.eval_str("half(5, 1)"),
+            Err(RuntimeError::InvalidArgument {
+                index: 1,
+                expected: "bool",
+                found: "int"
+            }
+            .into())
+        );
+    }
+
+    #[test]
+    fn user_data_works() {
+        use std::cell::Cell;
+        use std::fmt::{self, Formatter};
+        use std::rc::Rc;
+
+        struct Counter(Cell<i64>);
+
+        impl UserData for Counter {
+            fn display(&self, f: &mut Formatter) -> fmt::Result {
+                write!(f, "Counter({})", self.0.get())
+            }
+        }
+
+        let mut flux = Flux::new();
+        flux.register_method::<Counter, _>("add", |this: Rc<Counter>, n: i64| {
+            this.0.set(this.0.get() + n);
+            Ok(())
+        });
+        flux.register_method::<Counter, _>("get", |this: Rc<Counter>| Ok(this.0.get()));
+        let counter = Rc::new(Counter(Cell::new(0)));
+        flux.set_global("counter", Rc::clone(&counter));
+        flux.set_global("same", Rc::clone(&counter));
+        flux.set_global("other", Value::user_data(Counter(Cell::new(0))));
+
+        assert_eq!(
+            flux.eval_str("counter:add(2); counter:add(3); counter:get()"),
+            Ok(Value::Int(5))
+        );
+        assert_eq!(counter.0.get(), 5);
+        assert_eq!(flux.eval_str("counter == same"), Ok(Value::Bool(true)));
+        assert_eq!(flux.eval_str("counter == other"), Ok(Value::Bool(false)));
+        assert_eq!(
+            flux.get_global("counter").unwrap().to_string(),
+            "Counter(5)"
+        );
+        assert_eq!(
+            flux.eval_str("counter:missing()"),
+            Err(RuntimeError::TypeError.into())
+        );
+    }
+
+    #[test]
+    fn search_paths_work() {
+        use crate::compiler::CompileError;
+        use std::path::PathBuf;
+
+        let mut libs = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        libs.push("src/flux/tests/libs");
+        let mut flux = Flux::builder().with_search_path(&libs).build();
+        // A package runs its 'init.flux'
+        assert_eq!(
+            flux.eval_str("import greet; greet.hello(\"flux\")"),
+            Ok(Value::new_str("Hello, flux"))
+        );
+        match flux.eval_str("import missing.module;") {
+            Err(FluxError::Compile(CompileError::ModuleNotFound { name, attempted })) => {
+                assert_eq!(name, "missing.module");
+                assert_eq!(
+                    attempted,
+                    [
+                        PathBuf::from("missing/module.flux"),
+                        PathBuf::from("missing/module/init.flux"),
+                        libs.join("missing/module.flux"),
+                        libs.join("missing/module/init.flux"),
+                    ]
+                );
+            }
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn modules_use_the_enabled_natives() {
+        use crate::compiler::CompileError;
+        use crate::vm::RuntimeError;
+        use std::path::PathBuf;
+
+        let mut libs = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        libs.push("src/flux/tests/libs");
+        let mut flux = Flux::builder()
+            .with_natives(vec!["print", "require"])
+            .with_search_path(&libs)
+            .build();
+        match flux.eval_str("import leak; leak.f") {
+            Err(FluxError::Compile(CompileError::ModuleError { name, .. })) => {
+                assert_eq!(name, "leak")
+            }
+            result => panic!("Expected undeclared native, found {:?}", result),
+        }
+        match flux.eval_str("require(\"leak\").f") {
+            Err(FluxError::Runtime(error)) => {
+                assert!(matches!(*error, RuntimeError::ImportError { .. }))
+            }
+            result => panic!("Expected undeclared native, found {:?}", result),
+        }
+        // Bytecode compiled with every native doesn't find the disabled ones
+        let bytes = Flux::new()
+            .compile_file(libs.join("uses_leak.flux"))
+            .unwrap();
+        match flux.eval_bytecode(&bytes) {
+            Err(FluxError::Runtime(error)) => match *error {
+                RuntimeError::ImportError { error, .. } => assert_eq!(
+                    error,
+                    RuntimeError::UndefinedVariable {
+                        name: "readline".to_string()
+                    }
+                    .into()
+                ),
+                error => panic!("Unexpected error {:?}", error),
+            },
+            result => panic!("Expected undefined native, found {:?}", result),
+        }
+        flux.register("twice", |n: i64| Ok(n * 2));
+        assert_eq!(
+            flux.eval_str("import registered; registered.doubled"),
+            Ok(Value::Int(42))
+        );
+    }
+
+    #[test]
+    fn memory_limit_is_per_vm() {
+        use crate::vm::RuntimeError;
+
+        let mut flux = Flux::builder()
+            .with_limits(Limits::default().with_max_memory(4096))
+            .build();
+        flux.eval_str("var keep = {}; var n = 0;").unwrap();
+        let result = (0..50)
+            .map(|_| flux.eval_str("keep[n] = \"value {n}\"; n = n + 1;"))
+            .find(Result::is_err);
+        assert_eq!(
+            result,
+            Some(Err(RuntimeError::OutOfMemory { limit: 4096 }.into()))
+        );
+        assert!(flux.vm().memory_usage() > 3000);
+        flux.eval_str("keep = nil;").unwrap();
+        assert!(flux.vm().memory_usage() < 3000);
+        // The released entries make room again
+        assert_eq!(
+            flux.eval_str("keep = { \"value\" }; len(keep)"),
+            Ok(Value::Int(1))
+        );
+    }
+
+    #[test]
+    fn imports_share_limits() {
+        use crate::vm::RuntimeError;
+        use std::path::PathBuf;
+
+        let mut libs = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        libs.push("src/flux/tests/libs");
+        let flux = || {
+            Flux::builder()
+                .with_search_path(&libs)
+                .with_limits(Limits::default().with_instruction_budget(2000))
+                .build()
+        };
+        let count = "let i = 0; while i < 100 then i = i + 1; end";
+        assert_eq!(
+            flux().eval_str("import busy; busy.total"),
+            Ok(Value::Int(4950))
+        );
+        assert!(flux().eval_str(count).is_ok());
+        // The module is charged to the importing run
+        assert_eq!(
+            flux().eval_str(&format!("import busy; {}", count)),
+            Err(RuntimeError::BudgetExhausted { budget: 2000 }.into())
+        );
+        match flux().eval_str(&format!("{} import busy;", count)) {
+            Err(FluxError::Runtime(error)) => match *error {
+                RuntimeError::ImportError { error, .. } => {
+                    assert_eq!(error, RuntimeError::BudgetExhausted { budget: 2000 }.into())
+                }
+                error => panic!("Unexpected error {:?}", error),
+            },
+            result => panic!("Unexpected result {:?}", result),
+        }
+    }
+
+    #[test]
+    fn require_persists_between_evaluations() {
+        use std::path::PathBuf;
+
+        let mut libs = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        libs.push("src/flux/tests/libs");
+        let mut flux = Flux::builder().with_search_path(libs).build();
+        flux.eval_str("var plugin = \"greet\";").unwrap();
+        flux.eval_str("var greet = require(plugin);").unwrap();
+        assert_eq!(
+            flux.eval_str("greet.hello(\"repl\")"),
+            Ok(Value::new_str("Hello, repl"))
+        );
+        assert_eq!(
+            flux.eval_str("import greet as same; same == greet"),
+            Ok(Value::Bool(true))
+        );
+    }
+
+    #[test]
+    fn bytecode_works() {
+        use std::path::PathBuf;
+
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("src/vm/tests/modules/main.flux");
+        let flux = Flux::new();
+        let bytes = flux.compile_file(&path).unwrap();
+        assert_eq!(flux.compile_file(&path).unwrap(), bytes);
+        // Imported modules are included
+        assert_eq!(Flux::new().eval_bytecode(&bytes), Ok(Value::Int(12)));
+        assert!(matches!(
+            Flux::new().eval_bytecode(&bytes[..bytes.len() / 2]),
+            Err(FluxError::Bytecode(_))
+        ));
+    }
+
+    #[test]
+    fn eval_line_keeps_bindings() {
+        let mut flux = Flux::new();
+        flux.eval_line("let a = 2;").unwrap();
+        flux.eval_line("fn scale(n) if n == 0 then 0 else a + scale(n - 1) end end")
+            .unwrap();
+        assert_eq!(flux.eval_line("scale(21)"), Ok(Value::Int(42)));
+        flux.eval_line("let a = 3;").unwrap();
+        assert_eq!(flux.eval_line("scale(2)"), Ok(Value::Int(6)));
+        // Only the top level bindings outlive the line
+        flux.eval_line("if true then let b = 1; end").unwrap();
+        assert!(flux.eval_line("b").is_err());
+        flux.eval_str("let c = 1;").unwrap();
+        assert!(flux.eval_str("c").is_err());
+        flux.eval_line("let tostring = 5;").unwrap();
+        assert_eq!(flux.eval_line("\"{a}\""), Ok(Value::new_str("3")));
+    }
+
+    #[test]
+    fn check_works() {
+        let flux = Flux::new();
+        assert_eq!(flux.check_str("let x = 1; x + 1"), Ok(()));
+        assert!(matches!(flux.check_str("x + 1"), Err(FluxError::Parse(_))));
+        assert!(matches!(
+            flux.check_str("let = 1"),
+            Err(FluxError::Parse(_))
+        ));
+        // Nothing is run
+        assert_eq!(flux.check_str("assert(false)"), Ok(()));
+    }
+
+    #[test]
+    fn truncated_sources_are_rejected() {
+        use crate::parser::{ParserError, ParserErrorKind};
+        use std::path::PathBuf;
+
+        let mut flux = Flux::new();
+        for source in &["let x =", "foo(", "if true then", "let t = {1,", "fn f(x)"] {
+            assert!(
+                matches!(
+                    flux.check_str(source),
+                    Err(FluxError::Parse(ParserError {
+                        kind: ParserErrorKind::UnexpectedEof,
+                        ..
+                    }))
+                ),
+                "{}",
+                source
+            );
+            assert!(matches!(flux.eval_str(source), Err(FluxError::Parse(_))));
+        }
+        let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+        path.push("src/flux/tests/truncated.flux");
+        assert!(matches!(flux.check_file(&path), Err(FluxError::Parse(_))));
+        // The block ends between statements
+        assert_eq!(
+            flux.eval_str("if true then 1 else 2 end"),
+            Ok(Value::Int(1))
+        );
+    }
+
+    #[test]
+    fn disassemble_works() {
+        let flux = Flux::new();
+        let listing = flux
+            .disassemble_str(
+                "var add = fn(a, b) a + b end; if add(1, 2) > 2 then \"big\" else nil end",
+            )
+            .unwrap();
+        let lines: Vec<_> = listing.lines().map(str::trim_end).collect();
+        assert_eq!(
+            lines[..4],
+            [
+                "== main ==",
+                "     0  FuncDef           proto 1",
+                "     1  Call              0 args",
+                "     2  Return            value"
+            ]
+        );
+        assert!(lines.contains(&"== proto 0 (2 args) =="));
+        assert!(lines.contains(&"     1  SetGlobal         0    ; \"add\""));
+        // Jumps go to labels
+        let jump = lines.iter().find(|line| line.contains("JumpIf")).unwrap();
+        let label = format!("{}:", &jump[jump.find("-> ").unwrap() + 3..]);
+        assert!(lines.contains(&label.as_str()));
+    }
+}
@@ -0,0 +1,84 @@
+use super::Flux;
+use crate::compiler::CompileOptions;
+use crate::vm::{predefined_globals, Limits, Overflow, Vm};
+use std::collections::HashSet;
+use std::path::PathBuf;
+
+/// Configures the natives, imports, limits and integer overflow of a `Flux` instance
+#[derive(Clone, Debug, Default)]
+pub struct VmBuilder {
+    limits: Limits,
+    overflow: Overflow,
+    // Enabled predefined natives, all of them if none
+    natives: Option<HashSet<String>>,
+    options: CompileOptions,
+}
+
+impl VmBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    /// Enables only the given predefined natives
+    pub fn with_natives<S>(mut self, names: impl IntoIterator<Item = S>) -> Self
+    where
+        S: Into<String>,
+    {
+        self.natives = Some(names.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn without_natives(mut self) -> Self {
+        self.natives = Some(HashSet::new());
+        self
+    }
+
+    /// Enables or disables import statements
+    pub fn with_imports(mut self, enabled: bool) -> Self {
+        self.options.imports = enabled;
+        self
+    }
+
+    /// Adds a directory searched for the imports not found next to the importing file,
+    /// directories are searched in the order they are added
+    pub fn with_search_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.search_paths.push(path.into());
+        self
+    }
+
+    /// Adds the directories listed in the `FLUX_PATH` environment variable
+    pub fn with_env_search_paths(mut self) -> Self {
+        self.options
+            .search_paths
+            .extend(CompileOptions::env_search_paths());
+        self
+    }
+
+    pub fn build(self) -> Flux {
+        let mut vm = Vm::new()
+            .with_limits(self.limits)
+            .with_overflow(self.overflow);
+        vm.set_compile_options(self.options.clone());
+        if let Some(natives) = self.natives {
+            for (name, _) in predefined_globals() {
+                if !natives.contains(name) {
+                    vm.remove_global(name);
+                }
+            }
+        }
+        Flux {
+            vm,
+            options: self.options,
+        }
+    }
+}
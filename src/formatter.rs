@@ -0,0 +1,285 @@
+use crate::scanner::Scanner;
+use crate::FluxResult;
+
+const INDENT: &str = "    ";
+
+/// Reindents the source by four spaces per block, strips trailing whitespace, collapses
+/// consecutive blank lines and ends it with a single newline. Only the whitespace is changed,
+/// lines starting inside a multi-line string are kept as they are.
+pub fn format_source(source: &str) -> FluxResult<String> {
+    Scanner::new(source).scan()?;
+    let mut indenter = Indenter::default();
+    let mut out = String::new();
+    let mut blank_lines = 0;
+    for line in source.lines() {
+        if indenter.in_string() {
+            indenter.line(line);
+            out.push_str(line);
+            out.push('\n');
+            continue;
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            blank_lines += 1;
+            continue;
+        }
+        if blank_lines > 0 && !out.is_empty() {
+            out.push('\n');
+        }
+        blank_lines = 0;
+        for _ in 0..indenter.line(line) {
+            out.push_str(INDENT);
+        }
+        out.push_str(line);
+        out.push('\n');
+    }
+    Ok(out)
+}
+
+/// Whether the source ends inside a block, a bracket or a string.
+/// The REPL keeps reading lines until it doesn't.
+pub fn is_incomplete(source: &str) -> bool {
+    let mut indenter = Indenter::default();
+    for line in source.lines() {
+        indenter.line(line);
+    }
+    !indenter.levels.is_empty() || !indenter.modes.is_empty()
+}
+
+enum Mode {
+    String,
+    // Number of `#` around the raw string
+    RawString(usize),
+    // Braces opened in the interpolated expression
+    Interpolation(usize),
+}
+
+#[derive(Clone, PartialEq)]
+enum Token {
+    Word(String),
+    Punct(char),
+}
+
+enum Block {
+    Open,
+    Close,
+    Else,
+    None,
+}
+
+// A line leaving several blocks open, like `foo(fn()`, is indented once and
+// the line closing the last of them goes back to its indentation
+#[derive(Default)]
+struct Indenter {
+    // Number of blocks left open by each indenting line
+    levels: Vec<usize>,
+    modes: Vec<Mode>,
+    previous: Option<Token>,
+    // The `then` of an `else if` doesn't open a block of its own
+    else_if: bool,
+}
+
+impl Indenter {
+    fn in_string(&self) -> bool {
+        matches!(
+            self.modes.last(),
+            Some(Mode::String) | Some(Mode::RawString(_))
+        )
+    }
+
+    // Scans the line and returns its indentation, which leading closing tokens decrease
+    fn line(&mut self, line: &str) -> usize {
+        let chars: Vec<char> = line.chars().collect();
+        let mut indent = None;
+        let mut opened = 0;
+        let mut i = 0;
+        while i < chars.len() {
+            let c = chars[i];
+            i += 1;
+            match self.modes.last_mut() {
+                Some(Mode::String) => match c {
+                    '\\' => i += 1,
+                    '"' => {
+                        self.modes.pop();
+                    }
+                    '{' => self.modes.push(Mode::Interpolation(0)),
+                    _ => (),
+                },
+                Some(Mode::RawString(hashes)) => {
+                    let hashes = *hashes;
+                    if c == '"' && chars[i..].iter().take_while(|c| **c == '#').count() >= hashes {
+                        i += hashes;
+                        self.modes.pop();
+                    }
+                }
+                Some(Mode::Interpolation(braces)) => match c {
+                    '{' => *braces += 1,
+                    '}' if *braces == 0 => {
+                        self.modes.pop();
+                    }
+                    '}' => *braces -= 1,
+                    '"' => self.modes.push(Mode::String),
+                    _ => (),
+                },
+                None if c.is_whitespace() => (),
+                None if c == '/' && chars.get(i) == Some(&'/') => break,
+                None => {
+                    let token = if c.is_alphanumeric() || c == '_' {
+                        let start = i - 1;
+                        while chars
+                            .get(i)
+                            .is_some_and(|c| c.is_alphanumeric() || *c == '_')
+                        {
+                            i += 1;
+                        }
+                        let word: String = chars[start..i].iter().collect();
+                        if word == "r" && matches!(chars.get(i), Some('"') | Some('#')) {
+                            let hashes = chars[i..].iter().take_while(|c| **c == '#').count();
+                            i += hashes + 1;
+                            self.modes.push(Mode::RawString(hashes));
+                            Token::Punct('"')
+                        } else {
+                            Token::Word(word)
+                        }
+                    } else {
+                        if c == '"' {
+                            self.modes.push(Mode::String);
+                        }
+                        Token::Punct(c)
+                    };
+                    let block = self.block(&token);
+                    match block {
+                        Block::Open => opened += 1,
+                        Block::Close if opened > 0 => opened -= 1,
+                        Block::Close => {
+                            if let Some(level) = self.levels.last_mut() {
+                                *level -= 1;
+                                if *level == 0 {
+                                    self.levels.pop();
+                                }
+                            }
+                        }
+                        Block::Else | Block::None => (),
+                    }
+                    if indent.is_none() {
+                        indent = match block {
+                            Block::Close => None,
+                            Block::Else => Some(self.levels.len().saturating_sub(1)),
+                            _ => Some(self.levels.len()),
+                        };
+                    }
+                    self.previous = Some(token);
+                }
+            }
+        }
+        let indent = indent.unwrap_or(self.levels.len());
+        if opened > 0 {
+            self.levels.push(opened);
+        }
+        indent
+    }
+
+    fn block(&mut self, token: &Token) -> Block {
+        let after_class = self.previous == Some(Token::Word("class".to_string()));
+        let after_else = self.previous == Some(Token::Word("else".to_string()));
+        match token {
+            Token::Punct('{') | Token::Punct('(') | Token::Punct('[') => Block::Open,
+            Token::Punct('}') | Token::Punct(')') | Token::Punct(']') => Block::Close,
+            Token::Punct(_) => Block::None,
+            Token::Word(word) => match word.as_str() {
+                "if" if after_else => {
+                    self.else_if = true;
+                    Block::None
+                }
+                "then" if self.else_if => {
+                    self.else_if = false;
+                    Block::None
+                }
+                "then" | "do" | "fn" => Block::Open,
+                "end" => Block::Close,
+                "else" => Block::Else,
+                _ if after_class => Block::Open,
+                _ => Block::None,
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn format_works() {
+        let source = r#"
+let f = fn(x)
+if x then
+        print(1)
+   else if x == 2 then
+print(2)
+else
+  print("{x} then
+  do")
+end
+
+
+   end
+let t = {
+"a" = 1, "b" = [1,
+2],
+}
+foo(fn()
+1
+end)
+class Point
+fn init(self)
+let s = r"fn
+(";
+end
+end
+"#;
+        let expected = r#"let f = fn(x)
+    if x then
+        print(1)
+    else if x == 2 then
+        print(2)
+    else
+        print("{x} then
+  do")
+    end
+
+end
+let t = {
+    "a" = 1, "b" = [1,
+        2],
+}
+foo(fn()
+    1
+end)
+class Point
+    fn init(self)
+        let s = r"fn
+(";
+    end
+end
+"#;
+        assert_eq!(format_source(source).unwrap(), expected);
+        assert_eq!(format_source(expected).unwrap(), expected);
+    }
+
+    #[test]
+    fn incomplete_works() {
+        assert!(is_incomplete("fn foo(x)"));
+        assert!(is_incomplete("let t = {\n\"a\" = 1,"));
+        assert!(is_incomplete("let s = r\"abc"));
+        assert!(is_incomplete("if x then\n1\nelse if y then"));
+        assert!(!is_incomplete("if x then\n1\nelse if y then\n2\nend"));
+        assert!(!is_incomplete("let s = \"fn {x}\" // do"));
+        assert!(!is_incomplete(""));
+    }
+
+    #[test]
+    fn format_errors() {
+        assert!(format_source("let s = \"abc").is_err());
+    }
+}
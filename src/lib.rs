@@ -0,0 +1,38 @@
+#[macro_use]
+extern crate lazy_static;
+#[macro_use]
+extern crate maplit;
+extern crate dirs;
+
+#[macro_use]
+mod macros;
+mod compiler;
+pub mod error;
+mod flux;
+mod formatter;
+mod parser;
+mod scanner;
+mod sourcefile;
+#[cfg(test)]
+mod util;
+mod vm;
+
+pub use error::{FluxError, FluxResult};
+pub use flux::{Flux, VmBuilder};
+pub use formatter::{format_source, is_incomplete};
+use std::sync::atomic::{AtomicBool, Ordering};
+pub use vm::{
+    FromValue, InterruptHandle, IntoNative, IntoValue, Limits, NativeFunction, Overflow, Range,
+    RuntimeError, Table, TableRef, UserData, UserDataRef, Value, Vm,
+};
+
+static TRACE: AtomicBool = AtomicBool::new(false);
+
+/// Prints the compiled code to stdout, and in debug builds every executed instruction and the stack
+pub fn set_trace(enabled: bool) {
+    TRACE.store(enabled, Ordering::Relaxed)
+}
+
+fn trace_enabled() -> bool {
+    TRACE.load(Ordering::Relaxed)
+}
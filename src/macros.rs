@@ -1,7 +1,8 @@
 #[allow(unused_macros)]
 macro_rules! debug {
     ($($arg:tt)*) => {
-        if cfg!(debug_assertions) {
+        // Compiled out of release builds, it runs for every instruction
+        if cfg!(debug_assertions) && $crate::trace_enabled() {
             println!($($arg)*)
         }
     };
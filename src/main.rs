@@ -1,73 +1,538 @@
-#[macro_use]
-extern crate lazy_static;
-#[macro_use]
-extern crate maplit;
-extern crate dirs;
-
-#[macro_use]
-mod macros;
-mod compiler;
-pub mod error;
-mod parser;
-mod scanner;
-mod sourcefile;
-mod util;
-mod vm;
-
-use compiler::Compiler;
-use parser::Parser;
-use sourcefile::{MetaData, SourceFile};
-use std::io::Write;
-use util::run_file;
-use vm::Vm;
+use flux_rs::{format_source, is_incomplete, Flux, FluxError, FluxResult, Table, Value};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
+use std::cell::RefCell;
+use std::io::{self, IsTerminal, Read};
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use std::{env, fs, process};
+
+const USAGE: &str = "\
+Usage: flux [options] [file | -] [args...]
+       flux <command> [options] [args...]
+
+Commands:
+    run [file | -] [args...]    Run a file, stdin or the code given with -e (default)
+    repl                        Start the interactive prompt
+    check <files...>            Parse, analyze and compile the files without running them
+    disasm <file>               Print the instructions the file compiles to
+    compile <file> [-o out]     Compile the file to bytecode, by default to <file>.fluxc
+    fmt [--check] [paths...]    Reindent the files in place, or stdin to stdout
+    test [paths...]             Run the tests of the *_test.flux and test_*.flux files
+
+Options:
+    -e <code>        Run the code instead of a file, the arguments are passed to it
+    -I <dir>         Add a module search path, searched before FLUX_PATH
+    -o <file>        Output file of compile
+    -q, --quiet      Don't print the value of the program nor the passing tests
+    --trace          Print the compiled code, and every executed instruction with the stack
+                     in debug builds
+    --check          List the unformatted files instead of rewriting them
+    -h, --help       Print this message
+    -V, --version    Print the version
+
+Exit codes:
+    0    Success
+    1    Runtime error, failed test or unformatted file
+    2    Invalid command line
+    3    Lex, parse, compile or bytecode error
+    4    IO error
+";
+
+const REPL_HELP: &str = "\
+Top level bindings stay defined until :reset. Input leaving a block, a bracket or a string
+open continues on the next line, Ctrl-C discards it.
+
+Commands:
+    :load <file>      Evaluate a file, its top level bindings stay defined
+    :disasm [code]    Print the instructions of the code, by default of the last input
+    :reset            Forget every binding
+    :help             Print this message
+    :quit             Exit, like Ctrl-D
+";
+
+// Kept in the home directory
+const HISTORY_FILE: &str = ".flux_history";
+
+const SUCCESS: i32 = 0;
+const FAILURE: i32 = 1;
+const USAGE_ERROR: i32 = 2;
+const COMPILE_ERROR: i32 = 3;
+const IO_ERROR: i32 = 4;
+
+const COMMANDS: [&str; 7] = ["run", "repl", "check", "disasm", "compile", "fmt", "test"];
+
+#[derive(Default)]
+struct Options {
+    command: Option<String>,
+    // The files of the command, or the script and its arguments
+    args: Vec<String>,
+    code: Option<String>,
+    output: Option<PathBuf>,
+    search_paths: Vec<String>,
+    quiet: bool,
+    trace: bool,
+    check: bool,
+    help: bool,
+    version: bool,
+}
+
+impl Options {
+    fn parse(mut args: impl Iterator<Item = String>) -> Result<Self, String> {
+        let mut options = Options::default();
+        while let Some(arg) = args.next() {
+            // Everything after the script belongs to it
+            if options.is_run() && (options.code.is_some() || !options.args.is_empty()) {
+                options.args.push(arg);
+                continue;
+            }
+            match arg.as_str() {
+                "-h" | "--help" => options.help = true,
+                "-V" | "--version" => options.version = true,
+                "-q" | "--quiet" => options.quiet = true,
+                "--trace" => options.trace = true,
+                "--check" => options.check = true,
+                "-e" => options.code = Some(value(&mut args, &arg)?),
+                "-o" => options.output = Some(value(&mut args, &arg)?.into()),
+                "-I" => options.search_paths.push(value(&mut args, &arg)?),
+                "--" => options.args.extend(&mut args),
+                "-" => options.args.push(arg),
+                // Search paths are given as '-I dir' or '-Idir'
+                _ if arg.starts_with("-I") => options.search_paths.push(arg[2..].to_string()),
+                _ if arg.starts_with('-') => return Err(format!("Unknown option '{}'", arg)),
+                _ if options.command.is_none()
+                    && options.args.is_empty()
+                    && COMMANDS.contains(&arg.as_str()) =>
+                {
+                    options.command = Some(arg)
+                }
+                _ => options.args.push(arg),
+            }
+        }
+        Ok(options)
+    }
+
+    fn is_run(&self) -> bool {
+        matches!(self.command.as_deref(), None | Some("run"))
+    }
+
+    // The arguments after the script, the code given with -e has no script
+    fn script_args(&self) -> &[String] {
+        match (self.is_run(), &self.code, self.args.split_first()) {
+            (true, Some(_), _) => &self.args,
+            (true, None, Some((_, args))) => args,
+            _ => &[],
+        }
+    }
+
+    fn flux(&self) -> Flux {
+        let mut builder = Flux::builder();
+        for dir in &self.search_paths {
+            builder = builder.with_search_path(dir);
+        }
+        // FLUX_PATH is searched after the directories given in the command line
+        let mut flux = builder.with_env_search_paths().build();
+        let args = self
+            .script_args()
+            .iter()
+            .enumerate()
+            .map(|(i, arg)| (Value::Int(i as _), Value::new_str(arg)))
+            .collect();
+        flux.set_global("args", Table::from_array(args));
+        flux
+    }
+}
+
+fn value(args: &mut impl Iterator<Item = String>, option: &str) -> Result<String, String> {
+    args.next()
+        .ok_or_else(|| format!("Expected a value after {}", option))
+}
 
 fn main() {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() < 2 {
-        match repl() {
-            Ok(()) => (),
-            Err(err) => println!("{:?}", err),
+    let code = match Options::parse(env::args().skip(1)) {
+        Ok(options) if options.help => {
+            print!("{}", USAGE);
+            SUCCESS
         }
-    } else {
-        let path = &args[1];
-        /* let mut file = File::open(path).unwrap();
-        let mut buffer = String::new();
-        file.read_to_string(&mut buffer).unwrap(); */
+        Ok(options) if options.version => {
+            println!("flux {}", env!("CARGO_PKG_VERSION"));
+            SUCCESS
+        }
+        Ok(options) => {
+            flux_rs::set_trace(options.trace);
+            match options.command.as_deref() {
+                None | Some("run") => run(&options),
+                Some("repl") => repl(&options),
+                Some("check") => check(&options),
+                Some("disasm") => disasm(&options),
+                Some("compile") => compile(&options),
+                Some("fmt") => fmt(&options),
+                Some("test") => test(&options),
+                Some(command) => unreachable!("Unknown command {}", command),
+            }
+        }
+        Err(message) => usage_error(&message),
+    };
+    process::exit(code)
+}
+
+fn usage_error(message: &str) -> i32 {
+    eprintln!("Error: {}\n\n{}", message, USAGE);
+    USAGE_ERROR
+}
+
+fn exit_code(error: &FluxError) -> i32 {
+    match error {
+        FluxError::Runtime(_) => FAILURE,
+        FluxError::Lex(_)
+        | FluxError::Parse(_)
+        | FluxError::Compile(_)
+        | FluxError::Bytecode(_) => COMPILE_ERROR,
+        FluxError::Io(_) => IO_ERROR,
+    }
+}
+
+fn report(error: FluxError) -> i32 {
+    eprintln!("Error: {}", error);
+    exit_code(&error)
+}
+
+fn read_stdin() -> FluxResult<String> {
+    let mut source = String::new();
+    io::stdin().read_to_string(&mut source)?;
+    Ok(source)
+}
 
-        let value = run_file(path);
-        match value {
-            Ok(value) => println!("Exited program. Evaluated: {}", value),
-            Err(err) => println!("Error: {}", err),
+fn run(options: &Options) -> i32 {
+    let script = match options.args.first() {
+        _ if options.code.is_some() => None,
+        Some(script) => Some(script.as_str()),
+        // Without a script the program is read from stdin unless a user is typing
+        None if options.command.is_none() && io::stdin().is_terminal() => return repl(options),
+        None => Some("-"),
+    };
+    let mut flux = options.flux();
+    let result = match (script, &options.code) {
+        (_, Some(code)) => flux.eval_str(code),
+        (Some("-"), _) => read_stdin().and_then(|source| flux.eval_str(&source)),
+        (Some(path), _) => flux.eval_file(path),
+        (None, None) => unreachable!("Expected a script"),
+    };
+    match result {
+        Ok(Value::Unit) | Ok(Value::Nil) => SUCCESS,
+        Ok(value) => {
+            if !options.quiet {
+                println!("{}", value);
+            }
+            SUCCESS
         }
+        Err(error) => report(error),
     }
 }
 
-fn repl() -> Result<(), error::FluxError> {
-    let stdin = std::io::stdin();
-    let mut line = String::new();
-    let mut vm = Vm::new();
+fn repl(options: &Options) -> i32 {
+    let mut editor = match DefaultEditor::new() {
+        Ok(editor) => editor,
+        Err(error) => {
+            eprintln!("Error: {}", error);
+            return IO_ERROR;
+        }
+    };
+    let history = dirs::home_dir().map(|home| home.join(HISTORY_FILE));
+    if let Some(history) = &history {
+        // There is none the first time
+        let _ = editor.load_history(history);
+    }
+    let mut flux = options.flux();
+    let mut input = String::new();
+    // Shown by `:disasm` without code
+    let mut last_input = String::new();
     loop {
-        print!("> ");
-        std::io::stdout().flush().unwrap();
-        stdin.read_line(&mut line).unwrap();
-        let mut parser = Parser::new(&line)?;
-        let ast = parser.parse()?;
-        dbg!(&ast);
-        match Compiler::compile(SourceFile {
-            ast,
-            metadata: MetaData::default(),
-        }) {
-            Ok(compiled) => {
-                dbg!(&compiled.chunk);
-                match vm.run(compiled) {
-                    Ok(value) => println!("{}", value),
-                    Err(error) => println!("{:?}", error),
+        let prompt = if input.is_empty() { "> " } else { ". " };
+        match editor.readline(prompt) {
+            Ok(line) => {
+                if !input.is_empty() {
+                    input.push('\n');
+                }
+                input.push_str(&line);
+            }
+            Err(ReadlineError::Interrupted) => {
+                input.clear();
+                continue;
+            }
+            Err(ReadlineError::Eof) => break,
+            Err(error) => {
+                eprintln!("Error: {}", error);
+                break;
+            }
+        }
+        let command = input.trim().strip_prefix(':').map(str::to_string);
+        if command.is_none() && is_incomplete(&input) {
+            continue;
+        }
+        let input = std::mem::take(&mut input);
+        if input.trim().is_empty() {
+            continue;
+        }
+        let _ = editor.add_history_entry(input.trim_end());
+        let disassemble = |listing: String| {
+            print!("{}", listing);
+            Value::Unit
+        };
+        let result = match command {
+            None => {
+                let result = flux.eval_line(&input);
+                last_input = input;
+                result
+            }
+            Some(command) => match command.split_once(' ').unwrap_or((&command, "")) {
+                ("load", path) if !path.trim().is_empty() => flux.load_file(path.trim()),
+                ("disasm", "") => flux.disassemble_line(&last_input).map(disassemble),
+                ("disasm", code) => flux.disassemble_line(code).map(disassemble),
+                ("reset", "") => {
+                    flux = options.flux();
+                    last_input.clear();
+                    Ok(Value::Unit)
+                }
+                ("help", "") => {
+                    print!("{}", REPL_HELP);
+                    Ok(Value::Unit)
+                }
+                ("quit", "") | ("q", "") => break,
+                _ => {
+                    eprintln!("Unknown command ':{}', :help lists the commands", command);
+                    continue;
+                }
+            },
+        };
+        match result {
+            Ok(Value::Unit) => (),
+            Ok(value) => println!("{}", value),
+            Err(error) => eprintln!("Error: {}", error),
+        }
+    }
+    if let Some(history) = &history {
+        let _ = editor.save_history(history);
+    }
+    SUCCESS
+}
+
+fn check(options: &Options) -> i32 {
+    if options.args.is_empty() && options.code.is_none() {
+        return usage_error("check expects the files to check");
+    }
+    let flux = options.flux();
+    let mut code = SUCCESS;
+    if let Some(source) = &options.code {
+        if let Err(error) = flux.check_str(source) {
+            code = report(error);
+        }
+    }
+    for path in &options.args {
+        let result = match path.as_str() {
+            "-" => read_stdin().and_then(|source| flux.check_str(&source)),
+            path => flux.check_file(path),
+        };
+        if let Err(error) = result {
+            eprintln!("{}: {}", path, error);
+            code = code.max(exit_code(&error));
+        }
+    }
+    code
+}
+
+fn disasm(options: &Options) -> i32 {
+    let flux = options.flux();
+    let listing = match (options.args.as_slice(), &options.code) {
+        ([], Some(code)) => flux.disassemble_str(code),
+        ([path], None) if path == "-" => {
+            read_stdin().and_then(|source| flux.disassemble_str(&source))
+        }
+        ([path], None) => flux.disassemble_file(path),
+        _ => return usage_error("disasm expects a file"),
+    };
+    match listing {
+        Ok(listing) => {
+            print!("{}", listing);
+            SUCCESS
+        }
+        Err(error) => report(error),
+    }
+}
+
+fn compile(options: &Options) -> i32 {
+    let input = match options.args.as_slice() {
+        [input] => input,
+        _ => return usage_error("compile expects a file"),
+    };
+    let output = match &options.output {
+        Some(output) => output.clone(),
+        None => Path::new(input).with_extension("fluxc"),
+    };
+    let result = options
+        .flux()
+        .compile_file(input)
+        .and_then(|bytes| Ok(fs::write(output, bytes)?));
+    match result {
+        Ok(()) => SUCCESS,
+        Err(error) => report(error),
+    }
+}
+
+fn fmt(options: &Options) -> i32 {
+    if options.args.is_empty() || options.args == ["-"] {
+        return match read_stdin().and_then(|source| Ok((format_source(&source)?, source))) {
+            Ok((formatted, source)) if options.check => {
+                if formatted == source {
+                    SUCCESS
+                } else {
+                    println!("<stdin>");
+                    FAILURE
                 }
             }
-            Err(err) => {
-                println!("{:?}", err);
+            Ok((formatted, _)) => {
+                print!("{}", formatted);
+                SUCCESS
             }
+            Err(error) => report(error),
         };
-        line.clear();
+    }
+    let mut code = SUCCESS;
+    for path in flux_files(&options.args, |_| true) {
+        let result = fs::read_to_string(&path)
+            .map_err(FluxError::from)
+            .and_then(|source| {
+                let formatted = format_source(&source)?;
+                if formatted == source {
+                    Ok(false)
+                } else if options.check {
+                    println!("{}", path.display());
+                    Ok(true)
+                } else {
+                    fs::write(&path, formatted)?;
+                    Ok(false)
+                }
+            });
+        match result {
+            Ok(true) => code = code.max(FAILURE),
+            Ok(false) => (),
+            Err(error) => {
+                eprintln!("{}: {}", path.display(), error);
+                code = code.max(exit_code(&error));
+            }
+        }
+    }
+    code
+}
+
+fn test(options: &Options) -> i32 {
+    let paths = if !options.args.is_empty() {
+        options.args.clone()
+    } else if Path::new("tests").is_dir() {
+        vec!["tests".to_string()]
+    } else {
+        vec![".".to_string()]
+    };
+    let files = flux_files(&paths, |name| {
+        name.ends_with("_test.flux") || name.starts_with("test_")
+    });
+    let (mut passed, mut failed) = (0, 0);
+    let mut result = |name: String, result: FluxResult<Value>| match result {
+        Ok(_) => {
+            passed += 1;
+            if !options.quiet {
+                println!("test {} ... ok", name);
+            }
+        }
+        Err(error) => {
+            failed += 1;
+            println!("test {} ... FAILED\n    {}", name, error);
+        }
+    };
+    for file in files {
+        // Every file runs in a fresh interpreter, `test(name, fn)` adds a test to run after it
+        let mut flux = options.flux();
+        let tests = Rc::new(RefCell::new(Vec::new()));
+        let added = Rc::clone(&tests);
+        flux.register("test", move |name: String, function: Value| {
+            added.borrow_mut().push((name, function));
+            Ok(Value::Unit)
+        });
+        if let Err(error) = flux.eval_file(&file) {
+            result(file.display().to_string(), Err(error));
+            continue;
+        }
+        let tests = std::mem::take(&mut *tests.borrow_mut());
+        // A file without tests tests its top level code
+        if tests.is_empty() {
+            result(file.display().to_string(), Ok(Value::Unit));
+        }
+        for (name, function) in tests {
+            let value = flux.vm_mut().call_value(function, Vec::new());
+            result(
+                format!("{}::{}", file.display(), name),
+                value.map_err(FluxError::from),
+            );
+        }
+    }
+    let status = if failed == 0 { "ok" } else { "FAILED" };
+    println!(
+        "\ntest result: {}. {} passed; {} failed",
+        status, passed, failed
+    );
+    if failed == 0 {
+        SUCCESS
+    } else {
+        FAILURE
+    }
+}
+
+// The given files and the `.flux` files in the given directories whose names match
+fn flux_files(paths: &[String], matches: fn(&str) -> bool) -> Vec<PathBuf> {
+    fn visit(dir: &Path, matches: fn(&str) -> bool, files: &mut Vec<PathBuf>) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(_) => return,
+        };
+        for path in entries.filter_map(|entry| Some(entry.ok()?.path())) {
+            let name = path.file_name().unwrap_or_default().to_string_lossy();
+            if name.starts_with('.') {
+                continue;
+            }
+            if path.is_dir() {
+                visit(&path, matches, files);
+            } else if name.ends_with(".flux") && matches(&name) {
+                files.push(path);
+            }
+        }
+    }
+    let mut files = Vec::new();
+    for path in paths.iter().map(Path::new) {
+        if path.is_dir() {
+            visit(path, matches, &mut files);
+        } else {
+            files.push(path.to_path_buf());
+        }
+    }
+    files.sort();
+    files
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        Options::parse(args.iter().map(|arg| arg.to_string())).unwrap()
+    }
+
+    #[test]
+    fn truncated_sources_are_compile_errors() {
+        let truncated = concat!(env!("CARGO_MANIFEST_DIR"), "/src/flux/tests/truncated.flux");
+        assert_eq!(check(&options(&["check", "-e", "let x ="])), COMPILE_ERROR);
+        assert_eq!(check(&options(&["check", truncated])), COMPILE_ERROR);
+        assert_eq!(run(&options(&["run", "-e", "foo("])), COMPILE_ERROR);
+        assert_eq!(run(&options(&["run", truncated])), COMPILE_ERROR);
+        assert_eq!(check(&options(&["check", "-e", "let x = 1;"])), SUCCESS);
     }
 }
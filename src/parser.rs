@@ -7,9 +7,10 @@ mod statement;
 pub use super::scanner::{Token, TokenType};
 use crate::scanner::Scanner;
 pub use error::{ParserError, ParserErrorKind};
-pub use expr::{BinaryOp, BlockExpr, Expr, Literal, UnaryOp};
+pub use expr::{BinaryOp, BlockExpr, Expr, Literal, LogicalOp, UnaryOp};
 use lookahead::LookAhead;
 pub use statement::Statement;
+use std::collections::HashSet;
 use std::ops::{Deref, DerefMut};
 
 type Result<T> = std::result::Result<T, ParserError>;
@@ -28,6 +29,12 @@ where
     I: Iterator<Item = Token>,
 {
     lookahead: LookAhead<I>,
+    // Globals defined by the host, predefined natives are assumed if none
+    globals: Option<HashSet<String>>,
+    // Bases of the classes whose methods are parsed, `super` refers to the last one
+    class_bases: Vec<Option<Expr>>,
+    // Top level `let` bindings are declared as globals
+    persistent_bindings: bool,
 }
 
 impl Parser<std::vec::IntoIter<Token>> {
@@ -35,9 +42,15 @@ impl Parser<std::vec::IntoIter<Token>> {
         let mut scanner = Scanner::new(source);
         scanner.scan()?;
         let lookahead = LookAhead::new(scanner.extract_tokens().into_iter());
-        Ok(Parser { lookahead })
+        Ok(Parser {
+            lookahead,
+            globals: None,
+            class_bases: Vec::new(),
+            persistent_bindings: false,
+        })
     }
 
+    #[cfg(test)]
     pub fn parse_str(source: &str) -> Result<Ast> {
         let mut parser = Parser::new(source)?;
         parser.parse()
@@ -48,6 +61,18 @@ impl<I> Parser<I>
 where
     I: Iterator<Item = Token>,
 {
+    pub fn with_globals(mut self, globals: impl IntoIterator<Item = String>) -> Self {
+        self.globals = Some(globals.into_iter().collect());
+        self
+    }
+
+    /// Makes the top level `let` bindings globals, so that the sources evaluated
+    /// afterwards by the same vm can use them like the lines of a REPL
+    pub fn with_persistent_bindings(mut self) -> Self {
+        self.persistent_bindings = true;
+        self
+    }
+
     pub fn parse(&mut self) -> Result<Ast> {
         use analyzer::Analyzer;
         let block = self.block_expr(TokenType::Eof)?;
@@ -74,8 +99,25 @@ where
             self.import_stmt()
         } else {
             let expr = self.expression()?;
-            if self.match_token(TokenType::Equal).is_ok() {
+            // `class` is only a keyword when followed by a name
+            if expr == Expr::Identifier(Self::CLASS.to_string())
+                && self.current()?.get_type() == TokenType::Identifier
+            {
+                self.class_stmt()
+            } else if expr == Expr::Identifier(Self::FROM.to_string())
+                && self.current()?.get_type() == TokenType::Identifier
+            {
+                self.import_from_stmt()
+            } else if expr == Expr::Identifier(Self::EXPORT.to_string())
+                && self.current()?.get_type() == TokenType::LeftCurly
+            {
+                self.export_stmt()
+            } else if self.match_token(TokenType::Equal).is_ok() {
                 self.assign_stmt(expr)
+            } else if self.match_token(TokenType::Comma).is_ok() {
+                self.multiple_assign_stmt(expr)
+            } else if let Some(op) = self.compound_assign_op() {
+                self.compound_assign_stmt(expr, op)
             } else if self.match_token(TokenType::Semicolon).is_ok() {
                 Ok(Statement::Expr(expr))
             } else {
@@ -166,52 +208,24 @@ where
         })
     }
 
-    const ITERATOR_NAME: &'static str = "";
-
-    // Desugar for to a while inside a block
+    // for x, y in iter do ... end
     fn for_stmt(&mut self) -> Result<Statement> {
-        let variable = self.match_token(TokenType::Identifier)?;
-        if variable.text() == Self::ITERATOR_NAME {
-            return Err(self.make_error(ParserErrorKind::ReservedIdentifier(
-                Self::ITERATOR_NAME.to_string(),
-            ))?);
+        let mut variables = vec![self.match_token(TokenType::Identifier)?.text().to_string()];
+        while self.match_token(TokenType::Comma).is_ok() {
+            variables.push(self.match_token(TokenType::Identifier)?.text().to_string());
         }
         self.match_token(TokenType::In)?;
         let iter = self.expression()?;
 
         // body
         self.match_token(TokenType::Do)?;
-
-        let mut for_block = self.block_stmt()?;
-        for_block.push(Statement::Set {
-            variable: Expr::Identifier(variable.text().to_string()),
-            value: Expr::Call {
-                func: Box::new(Expr::Identifier(Self::ITERATOR_NAME.to_string())),
-                args: vec![],
-            },
-        });
+        let body = self.block_stmt()?;
         self.match_token(TokenType::End)?;
-        Ok(Statement::Block(vec![
-            Statement::Let {
-                name: Self::ITERATOR_NAME.to_string(),
-                value: iter.clone(),
-            },
-            Statement::Let {
-                name: variable.text().to_string(),
-                value: Expr::Call {
-                    func: Box::new(Expr::Identifier(Self::ITERATOR_NAME.to_string())),
-                    args: vec![],
-                },
-            },
-            Statement::While {
-                condition: Expr::Binary {
-                    left: Box::new(Expr::Identifier(variable.text().to_string())),
-                    op: BinaryOp::BangEqual,
-                    right: Box::new(Expr::nil()),
-                },
-                then_block: Box::new(Statement::Block(for_block)),
-            },
-        ]))
+        Ok(Statement::For {
+            variables,
+            iter,
+            body,
+        })
     }
 
     #[allow(dead_code)]
@@ -238,17 +252,99 @@ where
     }
 
     fn import_stmt(&mut self) -> Result<Statement> {
-        let mut path = Vec::new();
-        let module = self.match_token(TokenType::Identifier)?;
-        path.push(module.extract_text());
+        let path = self.module_path()?;
+        // `import a.b;` binds the module to `b`
+        let name = match self.match_token(TokenType::As) {
+            Ok(_) => self.match_token(TokenType::Identifier)?.extract_text(),
+            Err(_) => path.last().expect("Expected a module name").clone(),
+        };
+        let _ = self.match_token(TokenType::Semicolon)?;
+        Ok(Statement::Import { path, name })
+    }
+
+    const FROM: &'static str = "from";
+    const EXPORT: &'static str = "export";
+
+    fn import_from_stmt(&mut self) -> Result<Statement> {
+        let path = self.module_path()?;
+        self.match_token(TokenType::Import)?;
+        let mut names = vec![self.match_token(TokenType::Identifier)?.extract_text()];
+        while self.match_token(TokenType::Comma).is_ok() {
+            names.push(self.match_token(TokenType::Identifier)?.extract_text());
+        }
+        self.match_token(TokenType::Semicolon)?;
+        Ok(Statement::FromImport { path, names })
+    }
+
+    fn module_path(&mut self) -> Result<Vec<String>> {
+        let mut path = vec![self.match_token(TokenType::Identifier)?.extract_text()];
         while self.match_token(TokenType::Dot).is_ok() {
-            let module = self.match_token(TokenType::Identifier)?;
-            path.push(module.extract_text());
+            path.push(self.match_token(TokenType::Identifier)?.extract_text());
         }
-        self.match_token(TokenType::As)?;
+        Ok(path)
+    }
+
+    // export { a, b }
+    fn export_stmt(&mut self) -> Result<Statement> {
+        self.match_token(TokenType::LeftCurly)?;
+        let mut names = Vec::new();
+        while let Ok(token) = self.match_token(TokenType::Identifier) {
+            names.push(token.extract_text());
+            if self.match_token(TokenType::Comma).is_err() {
+                break;
+            }
+        }
+        self.match_token(TokenType::RightCurly)?;
+        let _ = self.match_token(TokenType::Semicolon);
+        Ok(Statement::Export(names))
+    }
+
+    const CLASS: &'static str = "class";
+    const EXTENDS: &'static str = "extends";
+    const SUPER: &'static str = "super";
+
+    fn class_stmt(&mut self) -> Result<Statement> {
         let name = self.match_token(TokenType::Identifier)?.extract_text();
-        let _ = self.match_token(TokenType::Semicolon)?;
-        Ok(Statement::Import { path, name })
+        let base = if self.current()?.text() == Self::EXTENDS {
+            self.advance()?;
+            Some(self.access()?)
+        } else {
+            None
+        };
+        self.class_bases.push(base.clone());
+        let mut methods = Vec::new();
+        while self.match_token(TokenType::End).is_err() {
+            self.match_token(TokenType::Fn)?;
+            let method = self.match_token(TokenType::Identifier)?.extract_text();
+            let function = self.function()?;
+            methods.push((method, function));
+        }
+        self.class_bases.pop();
+        Ok(Statement::Class {
+            name,
+            base,
+            methods,
+        })
+    }
+
+    // super:method(args) is base.method(self, args)
+    fn super_call(&mut self) -> Result<Expr> {
+        let base = match self.class_bases.last() {
+            Some(Some(base)) => base.clone(),
+            _ => return Err(self.make_error(ParserErrorKind::SuperOutsideSubclass)?),
+        };
+        self.match_token(TokenType::Colon)?;
+        let method = self.match_token(TokenType::Identifier)?.extract_text();
+        self.match_token(TokenType::LeftParen)?;
+        let mut args = vec![Expr::Identifier("self".to_string())];
+        args.extend(self.call_args()?);
+        Ok(Expr::Call {
+            func: Box::new(Expr::Access {
+                table: Box::new(base),
+                field: Box::new(Expr::string(method)),
+            }),
+            args,
+        })
     }
 
     fn assign_stmt(&mut self, variable: Expr) -> Result<Statement> {
@@ -257,16 +353,79 @@ where
         Ok(Statement::Set { variable, value })
     }
 
+    fn multiple_assign_stmt(&mut self, first: Expr) -> Result<Statement> {
+        let mut variables = vec![first, self.expression()?];
+        while self.match_token(TokenType::Comma).is_ok() {
+            variables.push(self.expression()?);
+        }
+        self.match_token(TokenType::Equal)?;
+        let mut values = vec![self.expression()?];
+        while self.match_token(TokenType::Comma).is_ok() {
+            values.push(self.expression()?);
+        }
+        self.match_token(TokenType::Semicolon)?;
+        if variables.len() != values.len() {
+            return Err(self.make_error(ParserErrorKind::AssignmentCountMismatch {
+                variables: variables.len(),
+                values: values.len(),
+            })?);
+        }
+        Ok(Statement::SetMultiple { variables, values })
+    }
+
+    fn compound_assign_op(&mut self) -> Option<BinaryOp> {
+        [
+            (TokenType::PlusEqual, BinaryOp::Plus),
+            (TokenType::MinusEqual, BinaryOp::Minus),
+            (TokenType::StarEqual, BinaryOp::Star),
+            (TokenType::SlashEqual, BinaryOp::Slash),
+            (TokenType::RemEqual, BinaryOp::Rem),
+        ]
+        .iter()
+        .find_map(|(typ, op)| self.match_token(*typ).ok().map(|_| *op))
+    }
+
+    fn compound_assign_stmt(&mut self, variable: Expr, op: BinaryOp) -> Result<Statement> {
+        let value = self.expression()?;
+        self.match_token(TokenType::Semicolon)?;
+        Ok(Statement::CompoundSet {
+            variable,
+            op,
+            value,
+        })
+    }
+
     pub(self) fn expression(&mut self) -> Result<Expr> {
         self.binary()
     }
 
     fn binary(&mut self) -> Result<Expr> {
-        self.comparasion()
+        self.or()
+    }
+
+    fn or(&mut self) -> Result<Expr> {
+        self.logical(TokenType::Or, Self::and)
+    }
+
+    fn and(&mut self) -> Result<Expr> {
+        self.logical(TokenType::And, Self::comparasion)
+    }
+
+    fn logical(&mut self, typ: TokenType, operand: fn(&mut Self) -> Result<Expr>) -> Result<Expr> {
+        let mut left = operand(self)?;
+        while self.match_token(typ).is_ok() {
+            let right = operand(self)?;
+            left = Expr::Logical {
+                left: Box::new(left),
+                op: typ.into(),
+                right: Box::new(right),
+            }
+        }
+        Ok(left)
     }
 
     fn comparasion(&mut self) -> Result<Expr> {
-        let mut left = self.addition()?;
+        let mut left = self.coalesce()?;
         while let Some(token) = [
             TokenType::Less,
             TokenType::Greater,
@@ -279,7 +438,7 @@ where
         .find_map(|t| self.match_token(*t).ok())
         {
             let binop: BinaryOp = token.get_type().into();
-            let right = self.addition()?;
+            let right = self.coalesce()?;
             left = Expr::Binary {
                 left: Box::new(left),
                 op: binop,
@@ -289,6 +448,67 @@ where
         Ok(left)
     }
 
+    // `a ?? b` binds tighter than comparisons, `x ?? 0 > 5` is `(x ?? 0) > 5`
+    fn coalesce(&mut self) -> Result<Expr> {
+        self.logical(TokenType::QuestionQuestion, Self::range)
+    }
+
+    // Ranges don't chain, `a..b..c` is an error
+    fn range(&mut self) -> Result<Expr> {
+        let start = self.bit_or()?;
+        match self
+            .match_token(TokenType::DotDot)
+            .or_else(|_| self.match_token(TokenType::DotDotEqual))
+        {
+            Ok(token) => {
+                let end = self.bit_or()?;
+                Ok(Expr::Range {
+                    start: Box::new(start),
+                    end: Box::new(end),
+                    inclusive: token.get_type() == TokenType::DotDotEqual,
+                })
+            }
+            Err(_) => Ok(start),
+        }
+    }
+
+    // Left associative binary operators of the same precedence
+    fn binary_level(
+        &mut self,
+        types: &[TokenType],
+        operand: fn(&mut Self) -> Result<Expr>,
+    ) -> Result<Expr> {
+        let mut left = operand(self)?;
+        while let Some(token) = types.iter().find_map(|t| self.match_token(*t).ok()) {
+            let right = operand(self)?;
+            left = Expr::Binary {
+                left: Box::new(left),
+                op: token.get_type().into(),
+                right: Box::new(right),
+            }
+        }
+        Ok(left)
+    }
+
+    fn bit_or(&mut self) -> Result<Expr> {
+        self.binary_level(&[TokenType::Pipe], Self::bit_xor)
+    }
+
+    fn bit_xor(&mut self) -> Result<Expr> {
+        self.binary_level(&[TokenType::Caret], Self::bit_and)
+    }
+
+    fn bit_and(&mut self) -> Result<Expr> {
+        self.binary_level(&[TokenType::Ampersand], Self::shift)
+    }
+
+    fn shift(&mut self) -> Result<Expr> {
+        self.binary_level(
+            &[TokenType::LessLess, TokenType::GreaterGreater],
+            Self::addition,
+        )
+    }
+
     fn addition(&mut self) -> Result<Expr> {
         let mut left = self.multiplication()?;
         while let Ok(token) = self
@@ -329,6 +549,7 @@ where
             .match_token(TokenType::Plus)
             .or_else(|_| self.match_token(TokenType::Minus))
             .or_else(|_| self.match_token(TokenType::Bang))
+            .or_else(|_| self.match_token(TokenType::Tilde))
         {
             let unop: UnaryOp = token.get_type().into();
             let expr = self.unary()?;
@@ -349,7 +570,19 @@ where
             .or_else(|_| self.match_token(TokenType::Colon))
             .or_else(|_| self.match_token(TokenType::LeftBracket))
             .or_else(|_| self.match_token(TokenType::LeftParen))
+            .or_else(|_| self.match_token(TokenType::Question))
         {
+            // `a?.b`, `a?[k]` and `a?:m()` are nil when `a` is nil
+            let (token, optional) = match token.get_type() {
+                TokenType::Question => {
+                    let token = self
+                        .match_token(TokenType::Dot)
+                        .or_else(|_| self.match_token(TokenType::Colon))
+                        .or_else(|_| self.match_token(TokenType::LeftBracket))?;
+                    (token, true)
+                }
+                _ => (token, false),
+            };
             match token.get_type() {
                 TokenType::Dot => {
                     let token = self.match_token(TokenType::Identifier)?;
@@ -388,6 +621,9 @@ where
                 }
                 _ => unreachable!(),
             }
+            if optional {
+                expr = Expr::Optional(Box::new(expr));
+            }
         }
         Ok(expr)
     }
@@ -404,16 +640,70 @@ where
         Ok(args)
     }
 
+    // "a {b} c" is parsed as "a" + tostring(b) + " c", empty parts are left out.
+    // The conversion is an operator so that it doesn't depend on the `tostring` global.
+    fn interpolation(&mut self, first: String) -> Result<Expr> {
+        let mut parts = vec![first];
+        let mut values = Vec::new();
+        loop {
+            values.push(self.expression()?);
+            if let Ok(token) = self.match_token(TokenType::InterpolationMid) {
+                parts.push(token.extract_text());
+            } else {
+                parts.push(
+                    self.match_token(TokenType::InterpolationEnd)?
+                        .extract_text(),
+                );
+                break;
+            }
+        }
+        let mut exprs = Vec::with_capacity(parts.len() + values.len());
+        for (part, value) in parts
+            .into_iter()
+            .zip(values.into_iter().map(Some).chain([None]))
+        {
+            if !part.is_empty() {
+                exprs.push(Expr::string(part));
+            }
+            if let Some(value) = value {
+                exprs.push(Expr::Unary {
+                    op: UnaryOp::ToString,
+                    expr: Box::new(value),
+                });
+            }
+        }
+        Ok(exprs
+            .into_iter()
+            .reduce(|left, right| Expr::Binary {
+                left: Box::new(left),
+                op: BinaryOp::Plus,
+                right: Box::new(right),
+            })
+            .expect("interpolated strings have a value"))
+    }
+
     fn primary(&mut self) -> Result<Expr> {
         // println!("primary: {}", self.current()?.text());
         if let Ok(token) = self.match_token(TokenType::String) {
             let string = token.text().to_string();
             Ok(Expr::Literal(Literal::Str(string)))
+        } else if let Ok(token) = self.match_token(TokenType::InterpolationStart) {
+            self.interpolation(token.extract_text())
         } else if let Ok(token) = self.match_token(TokenType::Number) {
-            let number: f64 = token.text().parse().unwrap();
-            Ok(Expr::Literal(Literal::Number(number)))
+            // The scanner writes integers in decimal, anything else is a float
+            match token.text().parse() {
+                Ok(int) => Ok(Expr::integer(int)),
+                Err(_) => Ok(Expr::number(token.text().parse().unwrap())),
+            }
         } else if let Ok(token) = self.match_token(TokenType::Identifier) {
             let name = token.text();
+            // `super:` is a call of the base method inside classes
+            if name == Self::SUPER
+                && !self.class_bases.is_empty()
+                && self.current()?.get_type() == TokenType::Colon
+            {
+                return self.super_call();
+            }
             Ok(Expr::Identifier(name.to_string()))
         } else if self.match_token(TokenType::True).is_ok() {
             Ok(Expr::Literal(Literal::Bool(true)))
@@ -539,33 +829,26 @@ where
     fn block_expr_impl(&mut self) -> Result<BlockExpr> {
         let mut stmts = Vec::new();
         let expr = loop {
+            let typ = self.current()?.get_type();
+            // The block ends before a statement starts, not in the middle of one
+            if Self::BLOCK_ENDING.iter().any(|&t| t == typ) {
+                // Check if last statement can be converted to expr
+                let last_stmt = stmts.last();
+                break match last_stmt.map(Statement::can_convert_expr) {
+                    Some(true) => stmts.pop().unwrap().into_expr().unwrap(),
+                    _ => Expr::Literal(Literal::Unit),
+                };
+            }
             match self.statement() {
                 Ok(stmt) => stmts.push(stmt),
-                Err(err) => {
-                    match err {
-                        ParserError {
-                            kind: ParserErrorKind::UnexpectedExpr(expr),
-                            ..
-                        } => break expr,
-                        // TODO: check if matched with terminating token if so push literal expr
-                        err => {
-                            let typ = self.current()?.get_type();
-                            // We check if it ends with block terminating token so we don't omit any real error
-                            if Self::BLOCK_ENDING.iter().any(|&t| t == typ) {
-                                // Check if last statement can be converted to expr
-                                let last_stmt = stmts.last();
-                                break {
-                                    match last_stmt.map(|s| s.can_convert_expr()) {
-                                        Some(true) => stmts.pop().unwrap().into_expr().unwrap(),
-                                        _ => Expr::Literal(Literal::Unit),
-                                    }
-                                };
-                            } else {
-                                return Err(err);
-                            }
-                        }
-                    }
+                Err(ParserError {
+                    kind: ParserErrorKind::UnexpectedExpr(expr),
+                    ..
+                }) => break expr,
+                Err(_) if self.current()?.get_type() == TokenType::Eof => {
+                    return Err(self.make_error(ParserErrorKind::UnexpectedEof)?)
                 }
+                Err(err) => return Err(err),
             }
         };
         Ok(BlockExpr {
@@ -625,24 +908,138 @@ mod tests {
             parsed,
             Expr::Binary {
                 left: Box::new(Expr::Binary {
-                    left: Box::new(Expr::Literal(Literal::Number(3.0))),
+                    left: Box::new(Expr::Literal(Literal::Int(3))),
                     op: BinaryOp::Plus,
                     right: Box::new(Expr::Binary {
-                        left: Box::new(Expr::Literal(Literal::Number(4.0))),
+                        left: Box::new(Expr::Literal(Literal::Int(4))),
                         op: BinaryOp::Star,
-                        right: Box::new(Expr::Literal(Literal::Number(2.0)))
+                        right: Box::new(Expr::Literal(Literal::Int(2)))
                     }),
                 }),
                 op: BinaryOp::Less,
                 right: Box::new(Expr::Binary {
-                    left: Box::new(Expr::Literal(Literal::Number(20.0))),
+                    left: Box::new(Expr::Literal(Literal::Int(20))),
                     op: BinaryOp::Minus,
-                    right: Box::new(Expr::Literal(Literal::Number(4.0)))
+                    right: Box::new(Expr::Literal(Literal::Int(4)))
                 })
             }
         )
     }
 
+    #[test]
+    fn logical_works() {
+        let source = "a or b and c ?? 1 < 2";
+        let mut parser = Parser::new(source).unwrap();
+        let parsed = parser.expression().unwrap();
+        let ident = |name: &str| Box::new(Expr::Identifier(name.to_string()));
+        assert_eq!(
+            parsed,
+            Expr::Logical {
+                left: ident("a"),
+                op: LogicalOp::Or,
+                right: Box::new(Expr::Logical {
+                    left: ident("b"),
+                    op: LogicalOp::And,
+                    right: Box::new(Expr::Binary {
+                        left: Box::new(Expr::Logical {
+                            left: ident("c"),
+                            op: LogicalOp::Coalesce,
+                            right: Box::new(Expr::Literal(Literal::Int(1)))
+                        }),
+                        op: BinaryOp::Less,
+                        right: Box::new(Expr::Literal(Literal::Int(2)))
+                    })
+                })
+            }
+        )
+    }
+
+    #[test]
+    fn optional_access_works() {
+        let source = "a?.b.c";
+        let mut parser = Parser::new(source).unwrap();
+        let parsed = parser.expression().unwrap();
+        assert_eq!(
+            parsed,
+            Expr::Access {
+                table: Box::new(Expr::Optional(Box::new(Expr::Access {
+                    table: Box::new(Expr::Identifier("a".to_string())),
+                    field: Box::new(Expr::string("b".to_string())),
+                }))),
+                field: Box::new(Expr::string("c".to_string())),
+            }
+        );
+        let mut parser = Parser::new("a?(1)").unwrap();
+        assert!(parser.expression().is_err());
+    }
+
+    #[test]
+    fn assignment_works() {
+        let mut parser = Parser::new("a.b += 1;").unwrap();
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::CompoundSet {
+                variable: Expr::Access {
+                    table: Box::new(Expr::Identifier("a".to_string())),
+                    field: Box::new(Expr::string("b".to_string())),
+                },
+                op: BinaryOp::Plus,
+                value: Expr::Literal(Literal::Int(1)),
+            }
+        );
+        let mut parser = Parser::new("a, b = b, a;").unwrap();
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::SetMultiple {
+                variables: vec![
+                    Expr::Identifier("a".to_string()),
+                    Expr::Identifier("b".to_string())
+                ],
+                values: vec![
+                    Expr::Identifier("b".to_string()),
+                    Expr::Identifier("a".to_string())
+                ],
+            }
+        );
+        let mut parser = Parser::new("a, b = 1;").unwrap();
+        assert_eq!(
+            parser.statement().unwrap_err().kind,
+            ParserErrorKind::AssignmentCountMismatch {
+                variables: 2,
+                values: 1
+            }
+        );
+    }
+
+    #[test]
+    fn import_works() {
+        let path = vec!["std".to_string(), "array".to_string()];
+        let mut parser = Parser::new("import std.array;").unwrap();
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::Import {
+                path: path.clone(),
+                name: "array".to_string(),
+            }
+        );
+        let mut parser = Parser::new("from std.array import iter, range;").unwrap();
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::FromImport {
+                path,
+                names: vec!["iter".to_string(), "range".to_string()],
+            }
+        );
+        let mut parser = Parser::new("export { iter, range, };").unwrap();
+        assert_eq!(
+            parser.statement().unwrap(),
+            Statement::Export(vec!["iter".to_string(), "range".to_string()])
+        );
+        // Still usable as names
+        let mut parser = Parser::new("from = export;").unwrap();
+        assert!(parser.statement().is_ok());
+    }
+
     #[test]
     fn grouping_works() {
         let source = "(3 + 4) * 2";
@@ -652,12 +1049,12 @@ mod tests {
             parsed,
             Expr::Binary {
                 left: Box::new(Expr::Grouping(Box::new(Expr::Binary {
-                    left: Box::new(Expr::Literal(Literal::Number(3.0))),
+                    left: Box::new(Expr::Literal(Literal::Int(3))),
                     op: BinaryOp::Plus,
-                    right: Box::new(Expr::Literal(Literal::Number(4.0)))
+                    right: Box::new(Expr::Literal(Literal::Int(4)))
                 }))),
                 op: BinaryOp::Star,
-                right: Box::new(Expr::Literal(Literal::Number(2.0)))
+                right: Box::new(Expr::Literal(Literal::Int(2)))
             }
         );
     }
@@ -670,7 +1067,7 @@ mod tests {
         assert_eq!(
             parsed,
             Expr::Tuple(vec![
-                Expr::Literal(Literal::Number(3.0)),
+                Expr::Literal(Literal::Int(3)),
                 Expr::Literal(Literal::Str("hello".to_string()))
             ])
         );
@@ -682,9 +1079,9 @@ mod tests {
             parsed,
             Expr::Grouping(Box::new(Expr::Tuple(vec![
                 Expr::Binary {
-                    left: Box::new(Expr::Literal(Literal::Number(3.0))),
+                    left: Box::new(Expr::Literal(Literal::Int(3))),
                     op: BinaryOp::Plus,
-                    right: Box::new(Expr::Literal(Literal::Number(2.0)))
+                    right: Box::new(Expr::Literal(Literal::Int(2)))
                 },
                 Expr::Literal(Literal::Str("hello".to_string())),
                 Expr::Unary {
@@ -705,17 +1102,17 @@ mod tests {
             parsed,
             Expr::TableInit {
                 keys: Some(vec![
-                    Expr::Literal(Literal::Number(3.0)),
+                    Expr::Literal(Literal::Int(3)),
                     Expr::Literal(Literal::Str("foo".to_string())),
                     Expr::Literal(Literal::Str("xd".to_string())),
                 ]),
                 values: vec![
-                    Expr::Literal(Literal::Number(6.0)),
+                    Expr::Literal(Literal::Int(6)),
                     Expr::Identifier("bar".to_string()),
                     Expr::Binary {
-                        left: Box::new(Expr::Literal(Literal::Number(5.0))),
+                        left: Box::new(Expr::Literal(Literal::Int(5))),
                         op: BinaryOp::Plus,
-                        right: Box::new(Expr::Literal(Literal::Number(3.0)))
+                        right: Box::new(Expr::Literal(Literal::Int(3)))
                     },
                 ]
             }
@@ -755,9 +1152,9 @@ mod tests {
                 func: Box::new(Expr::Identifier("foo".to_string())),
                 args: vec![
                     Expr::Binary {
-                        left: Box::new(Expr::Literal(Literal::Number(5.0))),
+                        left: Box::new(Expr::Literal(Literal::Int(5))),
                         op: BinaryOp::Plus,
-                        right: Box::new(Expr::Literal(Literal::Number(2.0))),
+                        right: Box::new(Expr::Literal(Literal::Int(2))),
                     },
                     Expr::Access {
                         table: Box::new(Expr::Identifier("bar".to_string())),
@@ -825,7 +1222,7 @@ mod tests {
                             name: "bar".to_string(),
                             value: Expr::Identifier("foo".to_string()),
                         }],
-                        expr: Box::new(Expr::Literal(Literal::Number(5.0)))
+                        expr: Box::new(Expr::Literal(Literal::Int(5)))
                     })
                 }],
                 expr: Box::new(Expr::unit())
@@ -1,5 +1,5 @@
 use super::{Ast, BlockExpr, Expr, Parser, ParserErrorKind, Result, Statement, Token};
-use crate::vm::lib::PREDEFINED_CONSTANTS;
+use crate::vm::predefined_globals;
 use std::collections::HashSet;
 
 pub struct Analyzer<'a, I>
@@ -35,18 +35,6 @@ impl Scope {
             environment: Some(HashSet::new()),
         }
     }
-
-    fn global() -> Self {
-        let locals: HashSet<String> = PREDEFINED_CONSTANTS
-            .iter()
-            .map(|(name, _)| name.to_string())
-            .collect();
-        Scope {
-            name: None,
-            locals,
-            environment: None,
-        }
-    }
 }
 
 impl<'a, I> Analyzer<'a, I>
@@ -59,10 +47,21 @@ where
     }
 
     fn new(parser: &'a Parser<I>) -> Self {
-        Analyzer {
-            parser,
-            scopes: vec![Scope::global()],
-            globals: HashSet::new(),
+        match &parser.globals {
+            Some(globals) => Analyzer {
+                parser,
+                scopes: vec![Scope::block()],
+                globals: globals.clone(),
+            },
+            // Predefined natives can be shadowed by locals
+            None => Analyzer {
+                parser,
+                scopes: vec![Scope::block()],
+                globals: predefined_globals()
+                    .iter()
+                    .map(|(name, _)| name.to_string())
+                    .collect(),
+            },
         }
     }
 
@@ -80,6 +79,26 @@ where
 
     fn visit_stmt(&mut self, stmt: &mut Statement) -> Result<()> {
         match stmt {
+            Statement::Let { name, value }
+                if self.parser.persistent_bindings && self.is_top_level() =>
+            {
+                match value {
+                    // Declared first so that the function can call itself, like a local
+                    Expr::Function { .. } => {
+                        self.globals.insert(name.to_string());
+                        self.visit_expr(value, Some(name.clone()))?;
+                    }
+                    _ => {
+                        self.visit_expr(value, None)?;
+                        self.globals.insert(name.to_string());
+                    }
+                }
+                *stmt = Statement::Var {
+                    name: name.clone(),
+                    value: value.clone(),
+                };
+                Ok(())
+            }
             Statement::Let { name, value } => {
                 match value {
                     // Also block?
@@ -110,6 +129,18 @@ where
                 self.visit_expr(variable, None)?;
                 self.visit_expr(value, None)
             }
+            Statement::CompoundSet {
+                variable, value, ..
+            } => {
+                self.visit_expr(variable, None)?;
+                self.visit_expr(value, None)
+            }
+            Statement::SetMultiple { variables, values } => {
+                for expr in variables.iter_mut().chain(values.iter_mut()) {
+                    self.visit_expr(expr, None)?;
+                }
+                Ok(())
+            }
             Statement::Block(stmts) => stmts
                 .into_iter()
                 .fold(Ok(()), |result, stmt| result.and(self.visit_stmt(stmt))),
@@ -132,12 +163,39 @@ where
                 self.visit_expr(condition, None)?;
                 self.visit_stmt(then_block.as_mut())
             }
+            Statement::For {
+                variables,
+                iter,
+                body,
+            } => {
+                self.visit_expr(iter, None)?;
+                self.enter_scope();
+                for variable in variables.iter() {
+                    self.add_local(variable)?;
+                }
+                for stmt in body.iter_mut() {
+                    self.visit_stmt(stmt)?;
+                }
+                self.exit_scope();
+                Ok(())
+            }
             Statement::Return(expr) => self.visit_expr(expr, None),
+            // Imported modules and names are globals of the importing module
             Statement::Import { name, .. } => {
-                self.add_local(name)?;
+                self.globals.insert(name.to_string());
                 Ok(())
             }
+            Statement::FromImport { names, .. } => {
+                self.globals.extend(names.iter().cloned());
+                Ok(())
+            }
+            Statement::Export(_) if self.is_top_level() => Ok(()),
+            Statement::Export(_) => Err(self.parser.make_error(ParserErrorKind::InnerExport)?),
             Statement::Expr(expr) => self.visit_expr(expr, None),
+            Statement::Class { .. } => {
+                *stmt = self.desugar_class(stmt.clone())?;
+                self.visit_stmt(stmt)
+            }
             _ => unimplemented!(),
         }
     }
@@ -238,6 +296,9 @@ where
             Binary { left, right, .. } => self
                 .visit_expr(left.as_mut(), None)
                 .and(self.visit_expr(right.as_mut(), None)),
+            Logical { left, right, .. } => self
+                .visit_expr(left.as_mut(), None)
+                .and(self.visit_expr(right.as_mut(), None)),
             Grouping(expr) => self.visit_expr(expr.as_mut(), None),
             Tuple(exprs) => exprs
                 .into_iter()
@@ -250,6 +311,7 @@ where
                 args.into_iter()
                     .fold(Ok(()), |res, arg| res.and(self.visit_expr(arg, None)))
             }
+            Optional(expr) => self.visit_expr(expr.as_mut(), None),
             TableInit { keys, values } => {
                 if let Some(keys) = keys {
                     for key in keys {
@@ -307,10 +369,55 @@ where
                 self.visit_expr(then_block.as_mut(), None)?;
                 self.visit_expr(else_block.as_mut(), None)
             }
+            Range { start, end, .. } => self
+                .visit_expr(start.as_mut(), None)
+                .and(self.visit_expr(end.as_mut(), None)),
             Rec => Ok(()),
         }
     }
 
+    // A class is a table of its methods with the base in `__super__`.
+    // Top level classes are globals so methods can refer to their own class.
+    fn desugar_class(&self, class: Statement) -> Result<Statement> {
+        let (name, base, methods) = match class {
+            Statement::Class {
+                name,
+                base,
+                methods,
+            } => (name, base, methods),
+            _ => unreachable!(),
+        };
+        let mut keys = vec![Expr::string("__name__".to_owned())];
+        let mut values = vec![Expr::string(name.clone())];
+        if let Some(base) = base {
+            keys.push(Expr::string("__super__".to_owned()));
+            values.push(base);
+        }
+        for (method, function) in methods {
+            let takes_self = match &function {
+                Expr::Function { args, .. } => args.first().map(String::as_str) == Some("self"),
+                _ => false,
+            };
+            if !takes_self {
+                return Err(self.parser.make_error(ParserErrorKind::MethodWithoutSelf {
+                    class: name,
+                    method,
+                })?);
+            }
+            keys.push(Expr::string(method));
+            values.push(function);
+        }
+        let value = Expr::TableInit {
+            keys: Some(keys),
+            values,
+        };
+        Ok(if self.is_top_level() {
+            Statement::Var { name, value }
+        } else {
+            Statement::Let { name, value }
+        })
+    }
+
     fn add_local(&mut self, name: &str) -> Result<()> {
         let inserted = self
             .scopes
@@ -433,4 +540,60 @@ mod tests {
             }
         )
     }
+
+    #[test]
+    fn inner_export_is_forbidden() {
+        let source = "
+        var a = fn()
+            export { a }
+        end;
+        ";
+        let parse_result = Parser::parse_str(source);
+        assert_eq!(parse_result.unwrap_err().kind, ParserErrorKind::InnerExport)
+    }
+
+    #[test]
+    fn method_without_self_is_forbidden() {
+        let source = "
+        class Foo
+            fn bar(x) x end
+        end
+        ";
+        assert_eq!(
+            Parser::parse_str(source).unwrap_err().kind,
+            ParserErrorKind::MethodWithoutSelf {
+                class: "Foo".to_string(),
+                method: "bar".to_string(),
+            }
+        );
+    }
+
+    #[test]
+    fn super_outside_subclass_is_forbidden() {
+        let source = "
+        class Foo
+            fn bar(self) super:bar() end
+        end
+        ";
+        assert_eq!(
+            Parser::parse_str(source).unwrap_err().kind,
+            ParserErrorKind::SuperOutsideSubclass
+        );
+    }
+
+    #[test]
+    fn persistent_bindings_are_globals() {
+        let source = "
+        let a = 1;
+        fn fact(n) if n < 2 then 1 else n * fact(n - 1) end end
+        if true then
+            let b = 2;
+        end
+        ";
+        let mut parser = Parser::new(source).unwrap().with_persistent_bindings();
+        let Ast(block) = parser.parse().unwrap();
+        assert!(matches!(&block.stmts[0], Statement::Var { name, .. } if name == "a"));
+        assert!(matches!(&block.stmts[1], Statement::Var { name, .. } if name == "fact"));
+        assert!(!matches!(&block.stmts[2], Statement::Var { .. }));
+    }
 }
@@ -25,6 +25,16 @@ pub enum ParserErrorKind {
     Undeclared { name: String },
     // var declaration is only allowed at top level
     InnerVarDeclaration { name: String },
+    // export is only allowed at top level
+    InnerExport,
+    // first argument of a method must be `self`
+    MethodWithoutSelf { class: String, method: String },
+    // `super` is only allowed in methods of a class with a base
+    SuperOutsideSubclass,
+    // `a, b = x, y` needs a value for every variable
+    AssignmentCountMismatch { variables: usize, values: usize },
+    // the source ends in the middle of a statement or an expression
+    UnexpectedEof,
 }
 
 impl Display for ParserError {
@@ -14,6 +14,12 @@ pub enum Expr {
         op: BinaryOp,
         right: Box<Expr>,
     },
+    // Evaluates the right operand only if the left one doesn't decide the result
+    Logical {
+        left: Box<Expr>,
+        op: LogicalOp,
+        right: Box<Expr>,
+    },
     Grouping(Box<Expr>),
     Tuple(Vec<Expr>),
     Access {
@@ -25,6 +31,8 @@ pub enum Expr {
         method: String,
         args: Vec<Expr>,
     },
+    // Access or method call which is nil when its receiver is nil
+    Optional(Box<Expr>),
     TableInit {
         keys: Option<Vec<Expr>>,
         values: Vec<Expr>,
@@ -46,6 +54,11 @@ pub enum Expr {
         // Else is mandatory when if is expression
         else_block: Box<Expr>,
     },
+    Range {
+        start: Box<Expr>,
+        end: Box<Expr>,
+        inclusive: bool,
+    },
     Rec, // Function calls itself
 }
 
@@ -55,8 +68,12 @@ impl Expr {
         Expr::Literal(Literal::Str(s))
     }
 
-    pub fn integer(i: f64) -> Self {
-        Expr::Literal(Literal::Number(i))
+    pub fn integer(i: i64) -> Self {
+        Expr::Literal(Literal::Int(i))
+    }
+
+    pub fn number(n: f64) -> Self {
+        Expr::Literal(Literal::Number(n))
     }
 
     pub fn bool(b: bool) -> Self {
@@ -87,6 +104,7 @@ impl Into<Expr> for BlockExpr {
 #[derive(Debug, Clone, PartialEq)]
 pub enum Literal {
     Str(String),
+    Int(i64),
     Number(f64),
     Bool(bool),
     Unit,
@@ -97,6 +115,9 @@ pub enum Literal {
 pub enum UnaryOp {
     Minus,
     Bang,
+    BitNot,
+    // Only produced by string interpolation
+    ToString,
 }
 
 impl From<TokenType> for UnaryOp {
@@ -104,6 +125,7 @@ impl From<TokenType> for UnaryOp {
         match typ {
             TokenType::Minus => UnaryOp::Minus,
             TokenType::Bang => UnaryOp::Bang,
+            TokenType::Tilde => UnaryOp::BitNot,
             _ => panic!("Unexpected type to convert to UnaryOp: {:?}", typ),
         }
     }
@@ -122,6 +144,11 @@ pub enum BinaryOp {
     GreaterEqual,
     LessEqual,
     Rem,
+    BitAnd,
+    BitOr,
+    BitXor,
+    ShiftLeft,
+    ShiftRight,
 }
 
 impl From<TokenType> for BinaryOp {
@@ -138,7 +165,30 @@ impl From<TokenType> for BinaryOp {
             TokenType::EqualEqual => BinaryOp::EqualEqual,
             TokenType::BangEqual => BinaryOp::BangEqual,
             TokenType::Rem => BinaryOp::Rem,
+            TokenType::Ampersand => BinaryOp::BitAnd,
+            TokenType::Pipe => BinaryOp::BitOr,
+            TokenType::Caret => BinaryOp::BitXor,
+            TokenType::LessLess => BinaryOp::ShiftLeft,
+            TokenType::GreaterGreater => BinaryOp::ShiftRight,
             _ => panic!("Unexpected type to convert to BinaryOp: {:?}", typ),
         }
     }
 }
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LogicalOp {
+    And,
+    Or,
+    Coalesce,
+}
+
+impl From<TokenType> for LogicalOp {
+    fn from(typ: TokenType) -> LogicalOp {
+        match typ {
+            TokenType::And => LogicalOp::And,
+            TokenType::Or => LogicalOp::Or,
+            TokenType::QuestionQuestion => LogicalOp::Coalesce,
+            _ => panic!("Unexpected type to convert to LogicalOp: {:?}", typ),
+        }
+    }
+}
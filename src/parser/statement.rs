@@ -1,4 +1,4 @@
-use super::Expr;
+use super::{BinaryOp, Expr};
 
 #[derive(Clone, Debug, PartialEq)]
 pub enum Statement {
@@ -17,6 +17,17 @@ pub enum Statement {
         variable: Expr,
         value: Expr,
     },
+    // a += 1, the variable is evaluated once
+    CompoundSet {
+        variable: Expr,
+        op: BinaryOp,
+        value: Expr,
+    },
+    // a, b = b, a, values are evaluated before any variable is set
+    SetMultiple {
+        variables: Vec<Expr>,
+        values: Vec<Expr>,
+    },
     Block(Vec<Statement>),
     If {
         condition: Expr,
@@ -27,12 +38,30 @@ pub enum Statement {
         condition: Expr,
         then_block: Box<Statement>,
     },
+    For {
+        variables: Vec<String>,
+        iter: Expr,
+        body: Vec<Statement>,
+    },
     Print(Expr),
     Return(Expr),
     Import {
         path: Vec<String>,
         name: String,
     },
+    // from a.b import c, d;
+    FromImport {
+        path: Vec<String>,
+        names: Vec<String>,
+    },
+    // Globals of the module visible to its importers, all of them without it
+    Export(Vec<String>),
+    // Desugared into a table by the analyzer
+    Class {
+        name: String,
+        base: Option<Expr>,
+        methods: Vec<(String, Expr)>,
+    },
 }
 
 impl Statement {
@@ -3,6 +3,7 @@ mod token;
 
 pub use error::{LexError, LexErrorKind};
 use std::iter::Peekable;
+use std::num::IntErrorKind;
 use std::str::CharIndices;
 use token::KEYWORDS;
 pub use token::{Token, TokenType};
@@ -14,6 +15,8 @@ pub struct Scanner<'a> {
     chars: Peekable<CharIndices<'a>>,
     line: usize,
     tokens: Vec<Token>,
+    // Brace depth of every interpolated expression being scanned, the innermost last
+    interpolations: Vec<usize>,
 }
 
 impl<'a> Scanner<'a> {
@@ -23,6 +26,7 @@ impl<'a> Scanner<'a> {
             chars: source.char_indices().peekable(),
             line: 1,
             tokens: Vec::new(),
+            interpolations: Vec::new(),
         }
     }
 
@@ -54,27 +58,91 @@ impl<'a> Scanner<'a> {
         loop {
             let (start, c) = self.advance()?;
             match c {
-                '.' => return Ok(self.new_token(TokenType::Dot, start, start + 1)),
+                '.' if self.peek().is_ascii_digit() => return self.number(c),
+                '.' => match self.match_char('.') {
+                    Ok((end, _)) => {
+                        return match self.match_char('=') {
+                            Ok((end, _)) => {
+                                Ok(self.new_token(TokenType::DotDotEqual, start, end + 1))
+                            }
+                            Err(_) => Ok(self.new_token(TokenType::DotDot, start, end + 1)),
+                        }
+                    }
+                    Err(_) => return Ok(self.new_token(TokenType::Dot, start, start + 1)),
+                },
                 ',' => return Ok(self.new_token(TokenType::Comma, start, start + 1)),
                 ';' => return Ok(self.new_token(TokenType::Semicolon, start, start + 1)),
                 ':' => return Ok(self.new_token(TokenType::Colon, start, start + 1)),
-                '?' => return Ok(self.new_token(TokenType::Question, start, start + 1)),
+                '?' => {
+                    return self.double_char_token(
+                        TokenType::Question,
+                        TokenType::QuestionQuestion,
+                        '?',
+                        start,
+                    )
+                }
 
                 '(' => return Ok(self.new_token(TokenType::LeftParen, start, start + 1)),
                 ')' => return Ok(self.new_token(TokenType::RightParen, start, start + 1)),
-                '{' => return Ok(self.new_token(TokenType::LeftCurly, start, start + 1)),
-                '}' => return Ok(self.new_token(TokenType::RightCurly, start, start + 1)),
+                '{' => {
+                    if let Some(depth) = self.interpolations.last_mut() {
+                        *depth += 1;
+                    }
+                    return Ok(self.new_token(TokenType::LeftCurly, start, start + 1));
+                }
+                '}' => match self.interpolations.last_mut() {
+                    // End of an interpolated expression, the string continues
+                    Some(0) => {
+                        self.interpolations.pop();
+                        return self.string(true);
+                    }
+                    Some(depth) => {
+                        *depth -= 1;
+                        return Ok(self.new_token(TokenType::RightCurly, start, start + 1));
+                    }
+                    None => return Ok(self.new_token(TokenType::RightCurly, start, start + 1)),
+                },
                 '[' => return Ok(self.new_token(TokenType::LeftBracket, start, start + 1)),
                 ']' => return Ok(self.new_token(TokenType::RightBracket, start, start + 1)),
 
-                '+' => return Ok(self.new_token(TokenType::Plus, start, start + 1)),
-                '-' => return Ok(self.new_token(TokenType::Minus, start, start + 1)),
-                '*' => return Ok(self.new_token(TokenType::Star, start, start + 1)),
+                '+' => {
+                    return self.double_char_token(
+                        TokenType::Plus,
+                        TokenType::PlusEqual,
+                        '=',
+                        start,
+                    )
+                }
+                '-' => {
+                    return self.double_char_token(
+                        TokenType::Minus,
+                        TokenType::MinusEqual,
+                        '=',
+                        start,
+                    )
+                }
+                '*' => {
+                    return self.double_char_token(
+                        TokenType::Star,
+                        TokenType::StarEqual,
+                        '=',
+                        start,
+                    )
+                }
                 '/' => match self.peek() {
                     '/' => self.single_line_comment()?,
-                    _ => return Ok(self.new_token(TokenType::Slash, start, start + 1)),
+                    _ => {
+                        return self.double_char_token(
+                            TokenType::Slash,
+                            TokenType::SlashEqual,
+                            '=',
+                            start,
+                        )
+                    }
                 },
-                '%' => return Ok(self.new_token(TokenType::Rem, start, start + 1)),
+                '%' => {
+                    return self.double_char_token(TokenType::Rem, TokenType::RemEqual, '=', start)
+                }
 
                 '=' => match self.peek() {
                     '=' => {
@@ -95,29 +163,43 @@ impl<'a> Scanner<'a> {
                         start,
                     )
                 }
-                '>' => {
-                    return self.double_char_token(
-                        TokenType::Greater,
-                        TokenType::GreaterEqual,
-                        '=',
-                        start,
-                    )
-                }
-                '<' => {
-                    return self.double_char_token(
-                        TokenType::Less,
-                        TokenType::LessEqual,
-                        '=',
-                        start,
-                    )
-                }
+                '>' => match self.match_char('>') {
+                    Ok((end, _)) => {
+                        return Ok(self.new_token(TokenType::GreaterGreater, start, end + 1))
+                    }
+                    Err(_) => {
+                        return self.double_char_token(
+                            TokenType::Greater,
+                            TokenType::GreaterEqual,
+                            '=',
+                            start,
+                        )
+                    }
+                },
+                '<' => match self.match_char('<') {
+                    Ok((end, _)) => return Ok(self.new_token(TokenType::LessLess, start, end + 1)),
+                    Err(_) => {
+                        return self.double_char_token(
+                            TokenType::Less,
+                            TokenType::LessEqual,
+                            '=',
+                            start,
+                        )
+                    }
+                },
+                '&' => return Ok(self.new_token(TokenType::Ampersand, start, start + 1)),
+                '|' => return Ok(self.new_token(TokenType::Pipe, start, start + 1)),
+                '^' => return Ok(self.new_token(TokenType::Caret, start, start + 1)),
+                '~' => return Ok(self.new_token(TokenType::Tilde, start, start + 1)),
 
-                '\"' => return self.string(start + 1),
+                '\"' => return self.string(false),
 
                 ' ' | '\t' | '\r' => {}
                 '\n' => self.line += 1,
                 c => {
-                    if c.is_alphabetic() || c == '_' {
+                    if c == 'r' && matches!(self.peek(), '"' | '#') {
+                        return self.raw_string();
+                    } else if c.is_alphabetic() || c == '_' {
                         let token = self.identifier(start)?;
                         match KEYWORDS.get(token.text.as_str()) {
                             Some(&typ) => {
@@ -129,8 +211,8 @@ impl<'a> Scanner<'a> {
                             }
                             None => return Ok(token),
                         }
-                    } else if c.is_numeric() {
-                        return self.number(start);
+                    } else if c.is_ascii_digit() {
+                        return self.number(c);
                     } else {
                         return Err(self.make_error(LexErrorKind::InvalidChar(c)));
                     }
@@ -145,20 +227,94 @@ impl<'a> Scanner<'a> {
         Ok(())
     }
 
-    fn string(&mut self, start: usize) -> Result<Token> {
-        let end = loop {
-            match self.match_char('\"') {
-                Ok((i, _)) => break i,
-                Err(LexError {
-                    kind: LexErrorKind::UnexpectedChar(_),
-                    ..
-                }) => {
-                    self.advance()?;
+    // Scans the string until the closing quote or the start of an interpolated expression.
+    // `continued` is true for the part after an interpolated expression
+    fn string(&mut self, continued: bool) -> Result<Token> {
+        let line = self.line;
+        let mut text = String::new();
+        let typ = loop {
+            let (_, c) = self
+                .advance()
+                .map_err(|_| self.make_error(LexErrorKind::UnterminatedString))?;
+            match c {
+                '"' if continued => break TokenType::InterpolationEnd,
+                '"' => break TokenType::String,
+                '{' => {
+                    self.interpolations.push(0);
+                    break if continued {
+                        TokenType::InterpolationMid
+                    } else {
+                        TokenType::InterpolationStart
+                    };
+                }
+                '\\' => text.push(self.escape()?),
+                '\n' => {
+                    self.line += 1;
+                    text.push(c);
                 }
-                Err(err) => return Err(err),
+                c => text.push(c),
             }
         };
-        Ok(self.new_token(TokenType::String, start, end))
+        Ok(Token { typ, text, line })
+    }
+
+    fn escape(&mut self) -> Result<char> {
+        let (_, c) = self
+            .advance()
+            .map_err(|_| self.make_error(LexErrorKind::UnterminatedString))?;
+        match c {
+            'n' => Ok('\n'),
+            't' => Ok('\t'),
+            'r' => Ok('\r'),
+            '0' => Ok('\0'),
+            '\\' | '"' | '{' | '}' => Ok(c),
+            'u' => self.unicode_escape(),
+            c => Err(self.make_error(LexErrorKind::InvalidEscape(c))),
+        }
+    }
+
+    // `\u{1F600}` with 1 to 6 hex digits
+    fn unicode_escape(&mut self) -> Result<char> {
+        let invalid = |scanner: &Self| scanner.make_error(LexErrorKind::InvalidUnicodeEscape);
+        self.match_char('{').map_err(|_| invalid(self))?;
+        let mut digits = String::new();
+        while let Ok((_, c)) = self.match_pred(|c| c.is_ascii_hexdigit()) {
+            digits.push(c);
+        }
+        self.match_char('}').map_err(|_| invalid(self))?;
+        if digits.is_empty() || digits.len() > 6 {
+            return Err(invalid(self));
+        }
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| invalid(self))
+    }
+
+    // `r"..."` or `r#"..."#` with any number of `#`, which may span lines.
+    // Escapes and interpolation are not processed
+    fn raw_string(&mut self) -> Result<Token> {
+        let line = self.line;
+        let mut hashes = 0;
+        while self.match_char('#').is_ok() {
+            hashes += 1;
+        }
+        let (start, _) = self.match_char('"')?;
+        let end = loop {
+            let (i, c) = self
+                .advance()
+                .map_err(|_| self.make_error(LexErrorKind::UnterminatedString))?;
+            match c {
+                '"' if (0..hashes).all(|_| self.match_char('#').is_ok()) => break i,
+                '\n' => self.line += 1,
+                _ => (),
+            }
+        };
+        Ok(Token {
+            typ: TokenType::String,
+            text: self.source[start + 1..end].to_string(),
+            line,
+        })
     }
 
     fn identifier(&mut self, start: usize) -> Result<Token> {
@@ -166,18 +322,77 @@ impl<'a> Scanner<'a> {
         Ok(self.new_token(TokenType::Identifier, start, end + 1))
     }
 
-    fn number(&mut self, start: usize) -> Result<Token> {
-        let end = self.skip_while(start, char::is_numeric);
-        match self.peek() {
-            '.' => {
-                self.advance().unwrap();
-                let end = self.skip_while(end + 1, char::is_numeric);
-                Ok(self.new_token(TokenType::Number, start, end + 1))
+    // Integers are written to the token in decimal, floats without underscores.
+    // `0x` and `0b` literals may set the sign bit, so bitmasks of all 64 bits can be written
+    fn number(&mut self, first: char) -> Result<Token> {
+        let line = self.line;
+        let number = |text| Token {
+            typ: TokenType::Number,
+            text,
+            line,
+        };
+        if first == '0' && matches!(self.peek(), 'x' | 'X' | 'b' | 'B') {
+            let (_, prefix) = self.advance()?;
+            let radix = if prefix == 'x' || prefix == 'X' {
+                16
+            } else {
+                2
+            };
+            let digits = self.digits(char::is_ascii_hexdigit);
+            return match u64::from_str_radix(&digits, radix) {
+                Ok(value) => Ok(number((value as i64).to_string())),
+                Err(err) if *err.kind() == IntErrorKind::PosOverflow => {
+                    Err(self.make_error(LexErrorKind::NumberOverflow))
+                }
+                Err(_) => Err(self.make_error(LexErrorKind::InvalidNumber)),
+            };
+        }
+        let mut text = first.to_string();
+        text += &self.digits(char::is_ascii_digit);
+        let mut is_float = first == '.';
+        // `1..2` is a range
+        if !is_float && self.peek() == '.' && self.peek_nth(1).is_ascii_digit() {
+            self.advance()?;
+            text.push('.');
+            text += &self.digits(char::is_ascii_digit);
+            is_float = true;
+        }
+        let exponent = match (self.peek(), self.peek_nth(1), self.peek_nth(2)) {
+            ('e' | 'E', '+' | '-', c) | ('e' | 'E', c, _) => c.is_ascii_digit(),
+            _ => false,
+        };
+        if exponent {
+            text.push(self.advance()?.1);
+            if let Ok((_, sign)) = self.match_pred(|c| c == '+' || c == '-') {
+                text.push(sign);
             }
-            _ => Ok(self.new_token(TokenType::Number, start, end + 1)),
+            text += &self.digits(char::is_ascii_digit);
+            is_float = true;
+        }
+        if !is_float {
+            return match text.parse::<i64>() {
+                Ok(value) => Ok(number(value.to_string())),
+                Err(_) => Err(self.make_error(LexErrorKind::NumberOverflow)),
+            };
+        }
+        match text.parse::<f64>() {
+            Ok(value) if value.is_finite() => Ok(number(text)),
+            Ok(_) => Err(self.make_error(LexErrorKind::NumberOverflow)),
+            Err(_) => Err(self.make_error(LexErrorKind::InvalidNumber)),
         }
     }
 
+    // Digits are separated by any number of underscores, which are left out
+    fn digits(&mut self, is_digit: fn(&char) -> bool) -> String {
+        let mut digits = String::new();
+        while let Ok((_, c)) = self.match_pred(|c| is_digit(&c) || c == '_') {
+            if c != '_' {
+                digits.push(c);
+            }
+        }
+        digits
+    }
+
     #[inline]
     fn double_char_token(
         &mut self,
@@ -244,6 +459,11 @@ impl<'a> Scanner<'a> {
         self.chars.peek().map(|(_, c)| *c).unwrap_or(' ')
     }
 
+    // Char after the next `n` chars
+    fn peek_nth(&self, n: usize) -> char {
+        self.chars.clone().nth(n).map(|(_, c)| c).unwrap_or(' ')
+    }
+
     fn new_token(&self, typ: TokenType, start: usize, end: usize) -> Token {
         Token {
             typ,
@@ -263,7 +483,7 @@ impl<'a> Scanner<'a> {
 #[cfg(test)]
 mod tests {
     use super::token::{Token, TokenType};
-    use super::Scanner;
+    use super::{LexErrorKind, Scanner};
 
     #[test]
     fn string_works() {
@@ -295,6 +515,22 @@ mod tests {
         );
     }
 
+    #[test]
+    fn range_works() {
+        let source = "1..2 ..=";
+        let mut scanner = Scanner::new(source);
+        let types: Vec<TokenType> = scanner.scan().unwrap().iter().map(|t| t.typ).collect();
+        assert_eq!(
+            &types[..4],
+            &[
+                TokenType::Number,
+                TokenType::DotDot,
+                TokenType::Number,
+                TokenType::DotDotEqual
+            ]
+        );
+    }
+
     #[test]
     fn ident_works() {
         let source = " \nvariable";
@@ -382,4 +618,83 @@ mod tests {
             ]
         );
     }
+
+    #[test]
+    fn interpolation_works() {
+        let source = "\"a {b {1}} c {d}\"";
+        let mut scanner = Scanner::new(source);
+        let tokens: Vec<(TokenType, String)> = scanner
+            .scan()
+            .unwrap()
+            .iter()
+            .map(|t| (t.typ, t.text.clone()))
+            .collect();
+        let token = |typ, text: &str| (typ, text.to_string());
+        assert_eq!(
+            &tokens[..8],
+            &[
+                token(TokenType::InterpolationStart, "a "),
+                token(TokenType::Identifier, "b"),
+                token(TokenType::LeftCurly, "{"),
+                token(TokenType::Number, "1"),
+                token(TokenType::RightCurly, "}"),
+                token(TokenType::InterpolationMid, " c "),
+                token(TokenType::Identifier, "d"),
+                token(TokenType::InterpolationEnd, ""),
+            ]
+        );
+    }
+
+    #[test]
+    fn string_errors() {
+        let kind = |source| Scanner::new(source).scan().unwrap_err().kind;
+        assert_eq!(kind("\"\\q\""), LexErrorKind::InvalidEscape('q'));
+        assert_eq!(kind("\"\\u{110000}\""), LexErrorKind::InvalidUnicodeEscape);
+        assert_eq!(kind("\"\\u41\""), LexErrorKind::InvalidUnicodeEscape);
+        assert_eq!(kind("\"abc"), LexErrorKind::UnterminatedString);
+        assert_eq!(kind("r#\"abc\""), LexErrorKind::UnterminatedString);
+    }
+
+    #[test]
+    fn numeric_literals_work() {
+        let source =
+            "0xFF 0b1010 1_000_000 1e-9 .5 2.5E3 0xFFFF_FFFF_FFFF_FFFF 1..2 9223372036854775807";
+        let mut scanner = Scanner::new(source);
+        let texts: Vec<&str> = scanner
+            .scan()
+            .unwrap()
+            .iter()
+            .map(|t| t.text.as_str())
+            .collect();
+        assert_eq!(
+            &texts[..11],
+            &[
+                "255",
+                "10",
+                "1000000",
+                "1e-9",
+                ".5",
+                "2.5E3",
+                "-1",
+                "1",
+                "..",
+                "2",
+                "9223372036854775807"
+            ]
+        );
+    }
+
+    #[test]
+    fn number_errors() {
+        let kind = |source| Scanner::new(source).scan().unwrap_err().kind;
+        assert_eq!(kind("9223372036854775808"), LexErrorKind::NumberOverflow);
+        assert_eq!(
+            kind("0x1_0000_0000_0000_0000"),
+            LexErrorKind::NumberOverflow
+        );
+        assert_eq!(kind("1e400"), LexErrorKind::NumberOverflow);
+        assert_eq!(kind("0x"), LexErrorKind::InvalidNumber);
+        assert_eq!(kind("0b102"), LexErrorKind::InvalidNumber);
+        assert_eq!(kind("٣"), LexErrorKind::InvalidChar('٣'));
+    }
 }
@@ -14,6 +14,16 @@ pub enum LexErrorKind {
     TooShort,
     // Invalid character
     InvalidChar(char),
+    // Unknown character after a backslash in a string
+    InvalidEscape(char),
+    // `\u{...}` without a valid code point
+    InvalidUnicodeEscape,
+    // String without the closing quote
+    UnterminatedString,
+    // Number literal which doesn't fit in i64 or f64
+    NumberOverflow,
+    // `0x` or `0b` without valid digits
+    InvalidNumber,
     Eof,
 }
 
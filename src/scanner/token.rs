@@ -5,6 +5,11 @@ use std::collections::HashMap;
 pub enum TokenType {
     /* Primitives */
     String,
+    // Parts of an interpolated string, "a {b} c {d} e" is scanned as
+    // InterpolationStart("a ") b InterpolationMid(" c ") d InterpolationEnd(" e")
+    InterpolationStart,
+    InterpolationMid,
+    InterpolationEnd,
     Number,
     True,
     False,
@@ -34,6 +39,10 @@ pub enum TokenType {
     Semicolon,
     Colon,
     Rem,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
 
     /* Double char tokens */
     EqualEqual,
@@ -41,6 +50,16 @@ pub enum TokenType {
     GreaterEqual,
     LessEqual,
     RightArrow, // '=>'
+    DotDot,
+    DotDotEqual,
+    QuestionQuestion,
+    LessLess,
+    GreaterGreater,
+    PlusEqual,
+    MinusEqual,
+    StarEqual,
+    SlashEqual,
+    RemEqual,
 
     /* Keywords */
     Let,
@@ -59,6 +78,8 @@ pub enum TokenType {
     As,
     In,
     Var,
+    And,
+    Or,
 
     Eof,
     Invalid,
@@ -92,6 +113,8 @@ lazy_static! {
         "as" => TokenType::As,
         "in" => TokenType::In,
         "var" => TokenType::Var,
+        "and" => TokenType::And,
+        "or" => TokenType::Or,
         // "print" => TokenType::Print,
     };
 }
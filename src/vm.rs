@@ -1,19 +1,39 @@
+mod arithmetic;
+mod coroutine;
 mod error;
 mod frame;
+mod interrupt;
+mod iterator;
 pub mod lib;
+mod limits;
+mod memory;
+mod metamethod;
+mod module;
 #[cfg(test)]
 mod tests;
 mod value;
 
-use crate::compiler::{BinaryInstr, Chunk, CompiledSource, Instruction, UnaryInstr};
+use crate::compiler::{
+    BinaryInstr, Chunk, CompiledSource, ConstantTableStruct, Instruction, UnaryInstr,
+};
+pub use arithmetic::Overflow;
 pub use error::RuntimeError;
 use frame::Frame;
-pub use lib::PREDEFINED_CONSTANTS;
+pub use interrupt::InterruptHandle;
+pub use lib::predefined_globals;
+pub use limits::Limits;
+use module::Modules;
+use std::any::TypeId;
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::convert::TryInto;
+use std::mem::size_of;
 use std::rc::Rc;
+use std::time::Instant;
 pub use value::{
-    ArgsLen, Float, FuncProtoRef, Function, Integer, NativeFunction, Table, UserFunction, Value,
+    ArgsLen, CoroutineRef, CoroutineStatus, Float, FromValue, FuncProtoRef, Function, Integer,
+    IntoNative, IntoValue, Iter, IterItem, IterRef, IterSource, NativeFunction, Range, Table,
+    TableRef, UserData, UserDataRef, UserFunction, Value,
 };
 
 pub type RuntimeResult<T> = Result<T, RuntimeError>;
@@ -24,6 +44,26 @@ pub struct Vm {
     stack: Vec<Value>,
     globals: HashMap<Value, Value>,
     compiled: Option<CompiledSource>,
+    limits: Limits,
+    overflow: Overflow,
+    // Number of natives currently running a script function on the host stack
+    native_depth: usize,
+    interrupt: InterruptHandle,
+    // Instructions executed in the current run
+    executed: u64,
+    deadline: Option<Instant>,
+    // Estimate of the bytes held by the scripts, see `alloc`
+    memory: usize,
+    // Bytes held by the vms importing the module run by this one
+    outer_memory: usize,
+    // Methods of userdata types
+    metatables: HashMap<TypeId, TableRef>,
+    // Resumed coroutines with the native depth they run at
+    coroutines: Vec<(CoroutineRef, usize)>,
+    // Frames and stacks of the code resuming the coroutines
+    callers: Vec<(Vec<Frame>, Vec<Value>)>,
+    // Shared with the vms running the imported modules
+    modules: Rc<RefCell<Modules>>,
 }
 
 impl Vm {
@@ -31,19 +71,146 @@ impl Vm {
         Self::default()
     }
 
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    pub fn limits_mut(&mut self) -> &mut Limits {
+        &mut self.limits
+    }
+
+    pub fn interrupt_handle(&self) -> InterruptHandle {
+        self.interrupt.clone()
+    }
+
     pub fn run(&mut self, source: CompiledSource) -> RuntimeResult<Value> {
+        self.start_run();
+        self.continue_run(source)
+    }
+
+    // Runs the source with the budget, deadline and memory left by the current run
+    fn continue_run(&mut self, mut source: CompiledSource) -> RuntimeResult<Value> {
         /* self.set_chunk(chunk);
         self.set_constants(constants); */
+        self.modules.borrow_mut().add_imports(&mut source);
         self.set_compiled_source(source);
         self.init_call();
-        self.main_loop()
+        let result = self.main_loop();
+        if result.is_err() {
+            self.abort_run(0, 0);
+        }
+        result
+    }
+
+    /// Calls a function value with the arguments in source order
+    pub fn call_value(&mut self, function: Value, args: Vec<Value>) -> RuntimeResult<Value> {
+        let pushed_args: u8 = args
+            .len()
+            .try_into()
+            .map_err(|_| RuntimeError::TooManyArgs(args.len()))?;
+        let (depth, stack_len) = (self.frames.len(), self.stack.len());
+        if depth == 0 {
+            self.start_run();
+        }
+        self.stack.extend(args);
+        let result = match function {
+            Value::Function(Function::User(function)) => self
+                .call_user_blocking(function, pushed_args)
+                .and_then(|_| self.pop_stack()),
+            Value::Function(Function::Native(native_fn)) => {
+                self.invoke_native(native_fn, pushed_args)
+            }
+            _ => Err(RuntimeError::TypeError),
+        };
+        if result.is_err() {
+            self.abort_run(depth, stack_len);
+        }
+        result
+    }
+
+    pub fn get_global(&self, name: &str) -> Option<&Value> {
+        self.globals.get(&Value::new_str(name))
+    }
+
+    pub fn set_global(&mut self, name: &str, value: Value) {
+        self.globals.insert(Value::new_str(name), value);
+    }
+
+    pub fn remove_global(&mut self, name: &str) -> Option<Value> {
+        let key = Value::new_str(name);
+        self.modules.borrow_mut().remove_native(&key);
+        self.globals.remove(&key)
+    }
+
+    /// Registers a Rust closure as a global native function,
+    /// its arguments are converted from the script values with `FromValue`
+    pub fn register<Args>(&mut self, name: &str, function: impl IntoNative<Args>) {
+        self.register_native(name, function.into_native());
+    }
+
+    /// Registers the native as a global of the vm and of its imported modules
+    pub fn register_native(&mut self, name: &str, function: NativeFunction) {
+        let native = Value::Function(Function::Native(function));
+        self.modules
+            .borrow_mut()
+            .set_native(Value::new_str(name), native.clone());
+        self.set_global(name, native);
+    }
+
+    /// Sets the table which methods of the userdata type `T` are looked up in
+    pub fn set_metatable<T: UserData>(&mut self, metatable: TableRef) {
+        self.metatables.insert(TypeId::of::<T>(), metatable);
+    }
+
+    pub fn metatable<T: UserData>(&self) -> Option<&TableRef> {
+        self.metatables.get(&TypeId::of::<T>())
+    }
+
+    /// Registers a Rust closure as a method of the userdata type `T`,
+    /// the object is passed as the first argument
+    pub fn register_method<T: UserData, Args>(
+        &mut self,
+        name: &str,
+        function: impl IntoNative<Args>,
+    ) {
+        let function = Value::Function(Function::Native(function.into_native()));
+        self.metatables
+            .entry(TypeId::of::<T>())
+            .or_insert_with(|| Table::new().shared())
+            .borrow_mut()
+            .set(Value::new_str(name), function);
+    }
+
+    pub fn global_names(&self) -> impl Iterator<Item = String> + '_ {
+        self.globals.keys().map(|name| name.to_string())
+    }
+
+    fn start_run(&mut self) {
+        self.executed = 0;
+        self.deadline = self.limits.timeout.map(|timeout| Instant::now() + timeout);
+    }
+
+    // Discard the state of the aborted run so vm can be used again
+    fn abort_run(&mut self, depth: usize, stack_len: usize) {
+        self.frames.truncate(depth);
+        self.stack.truncate(stack_len);
+        if depth == 0 {
+            self.native_depth = 0;
+        }
     }
 
     fn main_loop(&mut self) -> RuntimeResult<Value> {
+        self.run_frames_above(0)?;
+        self.pop_stack()
+    }
+
+    // Runs until the frames above 'depth' are returned
+    fn run_frames_above(&mut self, depth: usize) -> RuntimeResult<()> {
         loop {
             self.execute()?;
-            if self.frames.is_empty() {
-                return Ok(self.pop_stack()?);
+            if self.frames.len() <= depth {
+                return Ok(());
             }
             let f = self.current_frame_mut()?;
             f.pc += 1;
@@ -58,6 +225,7 @@ impl Vm {
 
     fn execute(&mut self) -> RuntimeResult<()> {
         loop {
+            self.tick()?;
             let instr = self.next_instr()?;
             match instr {
                 Instruction::Nil => self.stack.push(Value::Nil),
@@ -100,19 +268,26 @@ impl Vm {
                 }
                 Instruction::SetGlobal { index } => {
                     let name = self.constant_table()[index as usize].clone();
-                    let value = self.stack.pop().unwrap();
+                    let value = self.pop_stack()?;
                     self.globals.insert(name, value);
                 }
                 Instruction::GetLocal { index, frame } => {
-                    let frame_index = self.frame_from_offset(frame);
-                    let index = self.frames[frame_index].stack_top() + index as usize;
-                    self.stack.push(self.stack[index as usize].clone());
+                    let index = self.local_index(index, frame)?;
+                    let value = self
+                        .stack
+                        .get(index)
+                        .ok_or(RuntimeError::InvalidLocal { index })?
+                        .clone();
+                    self.stack.push(value);
                 }
                 Instruction::SetLocal { index, frame } => {
-                    let frame_index = self.frame_from_offset(frame);
-                    let index = self.frames[frame_index].stack_top() + index as usize;
-                    if self.stack.len() != index as usize {
-                        self.stack[index as usize] = self.pop_stack()?;
+                    let index = self.local_index(index, frame)?;
+                    if self.stack.len() != index {
+                        let value = self.pop_stack()?;
+                        *self
+                            .stack
+                            .get_mut(index)
+                            .ok_or(RuntimeError::InvalidLocal { index })? = value;
                     }
                 }
                 Instruction::Jump { offset } => self.jump(offset)?,
@@ -122,6 +297,25 @@ impl Vm {
                         self.jump(offset)?;
                     }
                 }
+                Instruction::JumpIfOrPop { offset, when_true } => {
+                    if self.top_stack()?.as_bool() == when_true {
+                        self.jump(offset)?;
+                    } else {
+                        self.pop_stack()?;
+                    }
+                }
+                Instruction::JumpIfNil { offset } => {
+                    if *self.top_stack()? == Value::Nil {
+                        self.jump(offset)?;
+                    }
+                }
+                Instruction::JumpIfNotNilOrPop { offset } => {
+                    if *self.top_stack()? != Value::Nil {
+                        self.jump(offset)?;
+                    } else {
+                        self.pop_stack()?;
+                    }
+                }
                 Instruction::InitTable { len, has_keys } => self.init_table(len, has_keys)?,
                 Instruction::GetField => self.get_field()?,
                 Instruction::GetFieldImm { index } => self.get_field_imm(index)?,
@@ -131,11 +325,26 @@ impl Vm {
                 } => self.get_method_imm(index, table_stack_index)?,
                 Instruction::SetField => self.set_field()?,
                 Instruction::SetFieldImm { index } => self.set_field_imm(index)?,
+                Instruction::SetIndex => {
+                    let value = self.pop_stack()?;
+                    let key = self.pop_stack()?;
+                    let table = self.pop_stack()?;
+                    self.new_index(table, key, value)?
+                }
+                Instruction::Dup { len } => {
+                    let start = self
+                        .stack
+                        .len()
+                        .checked_sub(len as usize)
+                        .ok_or(RuntimeError::EmptyStack)?;
+                    self.stack.extend_from_within(start..);
+                }
                 Instruction::Print => {
                     let value = self.pop_stack()?;
-                    println!("{}", value)
+                    println!("{}", self.to_display(&value)?)
                 }
                 Instruction::Tuple { len } => {
+                    self.alloc(len as usize * size_of::<Value>())?;
                     let mut values = Vec::with_capacity(len as usize);
                     for _ in 0..len {
                         values.push(self.pop_stack()?)
@@ -148,14 +357,12 @@ impl Vm {
                     has_env,
                 } => {
                     let proto = self.prototypes()[proto_index as usize].clone();
+                    let constants = Rc::clone(self.constants());
                     let function = Value::Function(if has_env {
-                        let env = self
-                            .pop_stack()?
-                            .into_table()
-                            .expect("Expected a table as env");
-                        Function::new_user_with_env(proto, env)
+                        let env = self.pop_stack()?.into_table()?;
+                        Function::new_user_with_env(proto, constants, env)
                     } else {
-                        Function::new_user(proto)
+                        Function::new_user(proto, constants)
                     });
                     self.stack.push(function)
                 }
@@ -166,11 +373,39 @@ impl Vm {
                             self.call(function, args_len)?;
                             continue; // Don't increment pc
                         }
-                        _ => return Err(RuntimeError::TypeError),
+                        // Calling a coroutine resumes it, nil marks its end for `for` loops
+                        Value::Coroutine(coroutine) => {
+                            let args_start = self.args_start(args_len)?;
+                            let args = self.stack.split_off(args_start);
+                            let value = self.resume_generator(&coroutine, args)?;
+                            self.stack.push(value);
+                        }
+                        Value::Iter(iter) if args_len == 0 => {
+                            let value = self.call_iter(&iter)?;
+                            self.stack.push(value);
+                        }
+                        value => match self.metamethod(&value, "__call") {
+                            // Called object is the first argument
+                            Some(Value::Function(function)) => {
+                                let args_len = args_len
+                                    .checked_add(1)
+                                    .ok_or(RuntimeError::TooManyArgs(args_len as usize + 1))?;
+                                // The arguments don't include the object yet
+                                let args_start = self.args_start(args_len - 1)?;
+                                self.stack.insert(args_start, value);
+                                self.call(function, args_len)?;
+                                continue;
+                            }
+                            _ => return Err(RuntimeError::TypeError),
+                        },
                     }
                 }
                 Instruction::Integer(value) => self.stack.push(value.into()),
-                Instruction::Import { name_index } => self.import(name_index as usize)?,
+                Instruction::Import { path_index } => self.import(path_index as usize)?,
+                Instruction::ImportFrom {
+                    path_index,
+                    name_index,
+                } => self.import_from(path_index as usize, name_index as usize)?,
                 Instruction::ExitBlock { pop, return_value } => {
                     let return_value = if return_value {
                         let value = self.pop_stack()?;
@@ -186,128 +421,199 @@ impl Vm {
                     }
                 }
                 Instruction::Rec => {
-                    let frame = self.frames.last().expect("Expected a call frame");
-                    let func = frame
+                    let func = self
+                        .current_frame()?
                         .function()
-                        .expect("Expected call has a function")
+                        .ok_or(RuntimeError::EmptyFrame)?
                         .clone();
                     self.stack.push(func.into());
                 }
+                Instruction::Iter => {
+                    let value = self.pop_stack()?;
+                    let iter = self.iterate(value)?;
+                    self.stack.push(Value::Iter(iter));
+                }
+                Instruction::IterNext { len } => self.iter_next(len)?,
+                Instruction::Range { inclusive } => {
+                    let end = self.pop_stack()?;
+                    let start = self.pop_stack()?;
+                    let range = Range::new(
+                        Integer::from_value(start)?,
+                        Integer::from_value(end)?,
+                        inclusive,
+                    );
+                    self.stack.push(Value::Range(range));
+                }
                 _ => return Err(RuntimeError::UnsupportedInstruction(instr)),
             }
             let f = self.current_frame_mut()?;
-            f.pc += 1;
-            self.print_call_stack();
-            self.print_stack();
+            // Wraps back from the pc before a jump to 0
+            f.pc = f.pc.wrapping_add(1);
+            if cfg!(debug_assertions) && crate::trace_enabled() {
+                self.print_call_stack();
+                self.print_stack();
+            }
             // self.print_globals();
         }
     }
 
-    fn import(&mut self, name_index: usize) -> RuntimeResult<()> {
-        let mod_name = self.constant_table()[name_index].as_str()?.to_string();
-        let chunk = self
-            .current_chunk_mut()
-            .imports()
-            .remove(&mod_name)
-            .expect("Expected module");
-        let mut vm = Vm::new();
-        let source = CompiledSource {
-            chunk,
-            constant_table: Rc::clone(
-                &self
-                    .compiled
-                    .as_ref()
-                    .expect("Expected a compiled source")
-                    .constant_table,
-            ),
-        };
-        // TODO: wrap error
-        // vm.run(chunk, Rc::clone(self.constant_table.as_ref().expect("Expected a constant table")))?;
-        vm.run(source)?;
-        self.globals
-            .insert(mod_name.into(), Table::from_map(vm.globals).into());
+    fn tick(&mut self) -> RuntimeResult<()> {
+        self.executed += 1;
+        if let Some(budget) = self.limits.instruction_budget {
+            if self.executed > budget {
+                return Err(RuntimeError::BudgetExhausted { budget });
+            }
+        }
+        if self
+            .executed
+            .is_multiple_of(u64::from(self.limits.check_interval.max(1)))
+        {
+            if self.interrupt.take() {
+                return Err(RuntimeError::Interrupted);
+            }
+            if let Some(deadline) = self.deadline {
+                if Instant::now() >= deadline {
+                    return Err(RuntimeError::Timeout);
+                }
+            }
+        }
         Ok(())
     }
 
-    // TODO: look recursively for '__class__' attribute when something is returns nil
+    // Instances inherit from their class, classes from their base. The class is searched
+    // with its own links before the base, the links are followed without recursing.
     fn get_table(key: &Value, table: &Value) -> RuntimeResult<Value> {
-        match table {
-            Value::Table(rc) => {
-                let table = rc.borrow_mut();
-                let value = match table.get(&key) {
-                    Value::Nil => match table.klass() {
-                        Value::Nil => Value::Nil,
-                        value => Self::get_table(key, value)?,
-                    },
-                    value => value.clone(),
-                };
-                Ok(value)
+        let mut visited = Vec::new();
+        let mut pending = vec![table.clone()];
+        while let Some(table) = pending.pop() {
+            let rc = match table {
+                Value::Table(rc) => rc,
+                _ => return Err(RuntimeError::TypeError),
+            };
+            if visited.iter().any(|table| Rc::ptr_eq(table, &rc)) {
+                return Err(RuntimeError::LookupCycle);
             }
-            _ => Err(RuntimeError::TypeError),
+            {
+                let table = rc.borrow();
+                match table.get(key) {
+                    Value::Nil => {
+                        for link in [table.parent(), table.klass()] {
+                            if *link != Value::Nil {
+                                pending.push(link.clone());
+                            }
+                        }
+                    }
+                    value => return Ok(value.clone()),
+                }
+            }
+            visited.push(rc);
         }
+        Ok(Value::Nil)
     }
 
     fn get_field(&mut self) -> RuntimeResult<()> {
         let key = self.pop_stack()?;
         let table = self.pop_stack()?;
-        let value = Self::get_table(&key, &table)?;
+        let value = match key {
+            Value::Range(range) => self.slice(&table, range)?,
+            key => self.index(&table, &key)?,
+        };
         self.stack.push(value);
         Ok(())
     }
 
+    // Strings are sliced by characters, tables by integer keys into a new array
+    fn slice(&mut self, value: &Value, range: Range) -> RuntimeResult<Value> {
+        let out_of_bounds = |len| RuntimeError::SliceOutOfBounds { range, len };
+        match value {
+            Value::Str(_) | Value::Embedded(_) => {
+                let string: &str = match value {
+                    Value::Str(string) => string,
+                    Value::Embedded(string) => string,
+                    _ => unreachable!(),
+                };
+                let len = string.chars().count();
+                let (start, end) = range.bounds(len).ok_or_else(|| out_of_bounds(len))?;
+                let slice: String = string.chars().skip(start).take(end - start).collect();
+                self.alloc(slice.len())?;
+                Ok(slice.into())
+            }
+            Value::Table(table) => {
+                let table = table.borrow();
+                let len = table.array_len();
+                let (start, end) = range.bounds(len).ok_or_else(|| out_of_bounds(len))?;
+                self.alloc(Table::HEADER_SIZE + (end - start) * Table::ENTRY_SIZE)?;
+                let array = (start..end)
+                    .enumerate()
+                    .map(|(i, key)| {
+                        let value = table.get(&Value::Int(key as Integer)).clone();
+                        (Value::Int(i as Integer), value)
+                    })
+                    .collect();
+                Ok(Table::from_array(array).into())
+            }
+            Value::Tuple(values) => {
+                let (start, end) = range
+                    .bounds(values.len())
+                    .ok_or_else(|| out_of_bounds(values.len()))?;
+                Ok(Value::Tuple(values[start..end].to_vec()))
+            }
+            _ => Err(RuntimeError::TypeError),
+        }
+    }
+
     fn get_field_imm(&mut self, index: u8) -> RuntimeResult<()> {
         let table = self.pop_stack()?;
-        let key = &self.constant_table()[index as usize];
-        let value = Self::get_table(key, &table)?;
+        let key = self.constant_table()[index as usize].clone();
+        let value = self.index(&table, &key)?;
         self.stack.push(value);
         Ok(())
     }
 
     fn get_method_imm(&mut self, index: u8, table_stack_index: u8) -> RuntimeResult<()> {
         // let field = self.get_field_imm(index)?;
-        let table_stack_index = self.stack.len() - table_stack_index as usize - 1;
-        let table = self.stack[table_stack_index].clone();
-        let key = &self.constant_table()[index as usize];
-        let value = Self::get_table(&key, &table)?;
-        self.stack.push(value.into_user_fn()?.into());
-        Ok(())
+        let table = self
+            .stack
+            .iter()
+            .rev()
+            .nth(table_stack_index as usize)
+            .ok_or(RuntimeError::EmptyStack)?
+            .clone();
+        let key = self.constant_table()[index as usize].clone();
+        match self.index(&table, &key)? {
+            function @ Value::Function(_) => {
+                self.stack.push(function);
+                Ok(())
+            }
+            _ => Err(RuntimeError::TypeError),
+        }
     }
 
     fn set_field(&mut self) -> RuntimeResult<()> {
         let table = self.pop_stack()?;
         let key = self.pop_stack()?;
         let value = self.pop_stack()?;
-        match table {
-            Value::Table(rc) => {
-                let mut table = rc.borrow_mut();
-                table.set(key, value);
-                Ok(())
-            }
-            _ => Err(RuntimeError::TypeError),
-        }
+        self.new_index(table, key, value)
     }
 
     fn set_field_imm(&mut self, index: u8) -> RuntimeResult<()> {
         let value = self.pop_stack()?;
         let table = self.pop_stack()?;
-        let key = &self.constant_table()[index as usize];
-        match table {
-            Value::Table(rc) => {
-                let mut table = rc.borrow_mut();
-                table.set(key.clone(), value);
-                Ok(())
-            }
-            _ => Err(RuntimeError::TypeError),
-        }
+        let key = self.constant_table()[index as usize].clone();
+        self.new_index(table, key, value)
     }
 
     fn init_table(&mut self, len: u16, has_keys: bool) -> RuntimeResult<()> {
+        self.alloc(Table::HEADER_SIZE + len as usize * Table::ENTRY_SIZE)?;
         let table = if has_keys {
             let mut table = Table::new();
             for _ in 0..len {
                 let value = self.pop_stack()?;
                 let key = self.pop_stack()?;
-                table.set(key, value)
+                if key.is_nan() {
+                    return Err(RuntimeError::NanKey);
+                }
+                table.set(key, value);
             }
             table
         } else {
@@ -321,16 +627,24 @@ impl Vm {
         Ok(())
     }
 
+    // Sets the pc before the target since it is incremented after the instruction
     fn jump(&mut self, offset: i8) -> RuntimeResult<()> {
         let f = self.current_frame_mut()?;
-        if offset > 0 {
-            f.pc += (offset - 1) as usize
-        } else {
-            f.pc -= (-offset + 1) as usize
+        let target = f.pc as isize + offset as isize;
+        if target < 0 {
+            return Err(RuntimeError::InvalidJump { pc: f.pc, offset });
         }
+        f.pc = (target as usize).wrapping_sub(1);
         Ok(())
     }
 
+    fn args_start(&self, args_len: u8) -> RuntimeResult<usize> {
+        self.stack
+            .len()
+            .checked_sub(args_len as usize)
+            .ok_or(RuntimeError::EmptyStack)
+    }
+
     fn call(&mut self, function: Function, pushed_args: u8) -> RuntimeResult<()> {
         match function {
             Function::User(function) => self.call_user(function, pushed_args),
@@ -339,8 +653,9 @@ impl Vm {
     }
 
     fn call_user(&mut self, function: UserFunction, pushed_args: u8) -> RuntimeResult<()> {
+        self.check_stack()?;
         if pushed_args == function.args_len() {
-            let stack_top = self.stack.len() - function.args_len() as usize;
+            let stack_top = self.args_start(function.args_len())?;
 
             // Push env if exists
             if let Some(env) = function.env() {
@@ -361,47 +676,83 @@ impl Vm {
     }
 
     fn call_user_blocking(&mut self, function: UserFunction, pushed_args: u8) -> RuntimeResult<()> {
+        if self.native_depth >= self.limits.max_native_depth {
+            return Err(RuntimeError::StackOverflow {
+                depth: self.frames.len(),
+            });
+        }
+        let depth = self.frames.len();
         self.call_user(function, pushed_args)?;
-        self.execute()
+        self.native_depth += 1;
+        let result = self.run_frames_above(depth);
+        self.native_depth -= 1;
+        result
+    }
+
+    fn check_stack(&self) -> RuntimeResult<()> {
+        if self.frames.len() >= self.limits.max_call_depth
+            || self.stack.len() >= self.limits.max_stack_size
+        {
+            Err(RuntimeError::StackOverflow {
+                depth: self.frames.len(),
+            })
+        } else {
+            Ok(())
+        }
     }
 
     fn call_native(&mut self, native_fn: NativeFunction, pushed_args: u8) -> RuntimeResult<()> {
-        let mut args = Vec::new();
-        match native_fn.args_len() {
-            ArgsLen::Variadic => {
-                for _ in 0..pushed_args {
-                    args.push(self.pop_stack()?);
-                }
-            }
-            ArgsLen::Exact(n) => {
-                if n == pushed_args {
-                    for _ in 0..pushed_args {
-                        args.push(self.pop_stack()?);
-                    }
-                } else {
-                    return Err(RuntimeError::WrongNumberOfArgs {
-                        expected: n,
-                        found: pushed_args,
-                    });
-                }
-            }
-        }
-        let value = (native_fn.function)(self, args)?;
+        let value = self.invoke_native(native_fn, pushed_args)?;
         self.stack.push(value);
         let f = self.current_frame_mut()?;
         f.pc += 1;
         Ok(())
     }
 
+    // Pops the arguments and returns the result of native function
+    fn invoke_native(
+        &mut self,
+        native_fn: NativeFunction,
+        pushed_args: u8,
+    ) -> RuntimeResult<Value> {
+        self.check_stack()?;
+        if let ArgsLen::Exact(n) = native_fn.args_len() {
+            if n != pushed_args {
+                return Err(RuntimeError::WrongNumberOfArgs {
+                    expected: n,
+                    found: pushed_args,
+                });
+            }
+        }
+        let args_start = self
+            .stack
+            .len()
+            .checked_sub(pushed_args as usize)
+            .ok_or(RuntimeError::EmptyStack)?;
+        let args = self.stack.split_off(args_start);
+        native_fn.call(self, args)
+    }
+
     fn binary(&mut self, op: BinaryInstr) -> RuntimeResult<()> {
         let right = self.pop_stack()?;
         let left = self.pop_stack()?;
-        if op == BinaryInstr::Eq {
+        if let Some(value) = self.binary_meta(op, &left, &right)? {
+            self.stack.push(value);
+        } else if op == BinaryInstr::Eq {
             self.stack.push(Value::Bool(left == right));
         } else if op == BinaryInstr::Ne {
             self.stack.push(Value::Bool(left != right));
         } else {
             let new_value = match (left, right) {
+                (Value::Int(a), Value::Int(b)) => self.int_binary(op, a, b),
+                // Bitwise operators only take integers
+                (left, right) if op.is_bitwise() => {
+                    let value = match left {
+                        Value::Int(_) => right,
+                        left => left,
+                    };
+                    Err(RuntimeError::UnsupportedBinary { value, op })
+                }
                 (Value::Number(a), Value::Number(b)) => Ok(match op {
                     BinaryInstr::Add => Value::Number(a + b),
                     BinaryInstr::Sub => Value::Number(a - b),
@@ -440,30 +791,6 @@ impl Vm {
                     BinaryInstr::Le => Value::Bool(a <= (b as f64)),
                     _ => unreachable!(),
                 }),
-                (Value::Int(a), Value::Int(b)) => Ok({
-                    if op.is_arithmetic() {
-                        match op {
-                            BinaryInstr::Add => Value::Int(a + b),
-                            BinaryInstr::Sub => Value::Int(a - b),
-                            BinaryInstr::Mul => Value::Int(a * b),
-                            BinaryInstr::Div => match b {
-                                0 => return Err(RuntimeError::DivideByZero),
-                                n if a % n == 0 => Value::Int(a / b),
-                                _ => Value::Number(a as f64 / b as f64),
-                            },
-                            BinaryInstr::Rem => Value::Int(a % b),
-                            _ => unreachable!(),
-                        }
-                    } else {
-                        match op {
-                            BinaryInstr::Gt => Value::Bool(a > b),
-                            BinaryInstr::Lt => Value::Bool(a < b),
-                            BinaryInstr::Ge => Value::Bool(a >= b),
-                            BinaryInstr::Le => Value::Bool(a <= b),
-                            _ => unreachable!(),
-                        }
-                    }
-                }),
                 (Value::Int(a), Value::Number(b)) => Ok({
                     match op {
                         BinaryInstr::Add => Value::Number((a as f64) + b),
@@ -486,6 +813,7 @@ impl Vm {
                 }),
                 (Value::Str(a), Value::Str(b)) => match op {
                     BinaryInstr::Add => {
+                        self.alloc(size_of::<String>() + a.len() + b.len())?;
                         let mut new_string = String::with_capacity(a.len() + b.len());
                         new_string.extend(a.chars());
                         new_string.extend(b.chars());
@@ -502,9 +830,16 @@ impl Vm {
 
     fn unary(&mut self, op: UnaryInstr) -> RuntimeResult<()> {
         let value = self.pop_stack()?;
+        if let Some(value) = self.unary_meta(op, &value)? {
+            self.stack.push(value);
+            return Ok(());
+        }
         match op {
             UnaryInstr::Negate => match value {
-                Value::Int(i) => self.stack.push(Value::Int(-i)),
+                Value::Int(i) => {
+                    let value = self.int_negate(i)?;
+                    self.stack.push(value)
+                }
                 Value::Number(f) => self.stack.push(Value::Number(-f)),
                 _ => return Err(RuntimeError::TypeError),
             },
@@ -512,13 +847,25 @@ impl Vm {
                 Value::Bool(b) => self.stack.push(Value::Bool(!b)),
                 _ => return Err(RuntimeError::TypeError),
             },
+            UnaryInstr::BitNot => match value {
+                Value::Int(i) => self.stack.push(Value::Int(!i)),
+                _ => return Err(RuntimeError::TypeError),
+            },
+            UnaryInstr::ToString => {
+                let string = self.to_display(&value)?;
+                self.alloc(string.len())?;
+                self.stack.push(string.into())
+            }
         }
         Ok(())
     }
 
     fn next_instr(&mut self) -> RuntimeResult<Instruction> {
         let f = self.current_frame()?;
-        let instr = self.instructions()?[f.pc];
+        let instr = *self
+            .instructions()?
+            .get(f.pc)
+            .ok_or(RuntimeError::PcOutOfBounds { pc: f.pc })?;
         debug!("pc: {}, instr: {:?}", f.pc, instr);
         Ok(instr)
     }
@@ -546,27 +893,27 @@ impl Vm {
             .chunk
     }
 
-    #[inline]
-    fn current_chunk_mut(&mut self) -> &mut Chunk {
-        &mut self.compiled.as_mut().expect("Expected a chunk").chunk
+    // Functions use the constants of the source they are compiled in,
+    // which is not necessarily the last one run
+    fn constants(&self) -> &Rc<ConstantTableStruct> {
+        match self.frames.last().and_then(Frame::function) {
+            Some(function) => function.constants(),
+            None => {
+                &self
+                    .compiled
+                    .as_ref()
+                    .expect("Expected a constant table")
+                    .constant_table
+            }
+        }
     }
 
     fn constant_table(&self) -> &[Value] {
-        &self
-            .compiled
-            .as_ref()
-            .expect("Expected a constant table")
-            .constant_table
-            .constants
+        &self.constants().constants
     }
 
     fn prototypes(&self) -> &[FuncProtoRef] {
-        &self
-            .compiled
-            .as_ref()
-            .expect("Expected a constant table")
-            .constant_table
-            .prototypes
+        &self.constants().prototypes
     }
 
     fn instructions(&self) -> RuntimeResult<&[Instruction]> {
@@ -583,7 +930,6 @@ impl Vm {
         }
     }
 
-    #[allow(dead_code)]
     fn top_stack(&self) -> RuntimeResult<&Value> {
         match self.stack.last() {
             Some(value) => Ok(value),
@@ -618,27 +964,45 @@ impl Vm {
     }
 
     #[inline]
-    fn frame_from_offset(&self, offset: u8) -> usize {
-        if offset != 0 {
-            self.frames.len() - offset as usize
-        } else {
-            0
-        }
+    // Index of the local in the stack, `frame` counts the frames from the top, 0 is the first one
+    fn local_index(&self, index: u16, frame: u8) -> RuntimeResult<usize> {
+        let frame = match frame {
+            0 => self.frames.first(),
+            offset => self
+                .frames
+                .len()
+                .checked_sub(offset as usize)
+                .and_then(|index| self.frames.get(index)),
+        };
+        Ok(frame.ok_or(RuntimeError::EmptyFrame)?.stack_top() + index as usize)
     }
 }
 
 impl Default for Vm {
     fn default() -> Self {
+        let globals: HashMap<Value, Value> = predefined_globals()
+            .into_iter()
+            .map(|(s, f)| (Value::Embedded(s), f))
+            .collect();
         Vm {
             frames: Vec::new(),
             stack: Vec::new(),
             compiled: None,
+            limits: Limits::default(),
+            overflow: Overflow::default(),
+            native_depth: 0,
+            interrupt: InterruptHandle::new(),
+            executed: 0,
+            deadline: None,
+            memory: 0,
+            outer_memory: 0,
+            metatables: HashMap::new(),
+            coroutines: Vec::new(),
+            callers: Vec::new(),
+            modules: Rc::new(RefCell::new(Modules::new(globals.clone()))),
             // current_chunk: None,
             // constant_table: None,
-            globals: PREDEFINED_CONSTANTS
-                .iter()
-                .map(|(s, f)| (Value::Embedded(s), f.clone()))
-                .collect(),
+            globals,
         }
     }
 }
@@ -0,0 +1,87 @@
+use super::{BinaryInstr, Float, Integer, RuntimeError, RuntimeResult, Value, Vm};
+
+/// Result of integer arithmetic which doesn't fit in an `Int`
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Raise `RuntimeError::IntegerOverflow`
+    #[default]
+    Error,
+    /// Compute the result as a float instead
+    Promote,
+}
+
+impl Vm {
+    pub fn with_overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub(super) fn int_binary(
+        &self,
+        op: BinaryInstr,
+        a: Integer,
+        b: Integer,
+    ) -> RuntimeResult<Value> {
+        let result = match op {
+            BinaryInstr::Add => a.checked_add(b),
+            BinaryInstr::Sub => a.checked_sub(b),
+            BinaryInstr::Mul => a.checked_mul(b),
+            BinaryInstr::Div => match (b, a.checked_rem(b)) {
+                (0, _) => return Err(RuntimeError::DivideByZero),
+                (_, Some(0)) => a.checked_div(b),
+                (_, Some(_)) => return Ok(Value::Number(a as Float / b as Float)),
+                (_, None) => None,
+            },
+            // i64::MIN % -1 is 0 even though the division overflows
+            BinaryInstr::Rem => match b {
+                0 => return Err(RuntimeError::DivideByZero),
+                _ => Some(a.wrapping_rem(b)),
+            },
+            BinaryInstr::BitAnd => Some(a & b),
+            BinaryInstr::BitOr => Some(a | b),
+            BinaryInstr::BitXor => Some(a ^ b),
+            BinaryInstr::Shl => Some(shift_left(a, b)),
+            BinaryInstr::Shr => Some(shift_left(a, b.saturating_neg())),
+            BinaryInstr::Gt => return Ok(Value::Bool(a > b)),
+            BinaryInstr::Lt => return Ok(Value::Bool(a < b)),
+            BinaryInstr::Ge => return Ok(Value::Bool(a >= b)),
+            BinaryInstr::Le => return Ok(Value::Bool(a <= b)),
+            BinaryInstr::Eq => return Ok(Value::Bool(a == b)),
+            BinaryInstr::Ne => return Ok(Value::Bool(a != b)),
+        };
+        match result {
+            Some(result) => Ok(Value::Int(result)),
+            None => self.overflowed(|| match op {
+                BinaryInstr::Add => a as Float + b as Float,
+                BinaryInstr::Sub => a as Float - b as Float,
+                BinaryInstr::Mul => a as Float * b as Float,
+                BinaryInstr::Div => a as Float / b as Float,
+                _ => unreachable!("only arithmetic overflows"),
+            }),
+        }
+    }
+
+    pub(super) fn int_negate(&self, i: Integer) -> RuntimeResult<Value> {
+        match i.checked_neg() {
+            Some(i) => Ok(Value::Int(i)),
+            None => self.overflowed(|| -(i as Float)),
+        }
+    }
+
+    fn overflowed(&self, promoted: impl FnOnce() -> Float) -> RuntimeResult<Value> {
+        match self.overflow {
+            Overflow::Error => Err(RuntimeError::IntegerOverflow),
+            Overflow::Promote => Ok(Value::Number(promoted())),
+        }
+    }
+}
+
+// Shifts by 64 bits or more give 0 and negative shifts go the other way.
+// Shifting right is logical, the sign bit isn't copied
+fn shift_left(a: Integer, b: Integer) -> Integer {
+    match b {
+        64.. | ..=-64 => 0,
+        0.. => ((a as u64) << b) as Integer,
+        _ => ((a as u64) >> -b) as Integer,
+    }
+}
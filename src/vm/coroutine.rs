@@ -0,0 +1,126 @@
+use super::{CoroutineRef, CoroutineStatus, RuntimeError, RuntimeResult, UserFunction, Value, Vm};
+use std::convert::TryInto;
+use std::mem;
+use std::rc::Rc;
+
+// A resumed coroutine runs on the host stack of `resume` until it yields.
+// `yield` unwinds it with `RuntimeError::Yielded`, leaving the pc at the call of `yield`.
+impl Vm {
+    /// Resumes the coroutine with the arguments of its function on the first resume,
+    /// later with the value returned by `yield`. Returns the yielded or the returned value.
+    pub fn resume(&mut self, coroutine: &CoroutineRef, args: Vec<Value>) -> RuntimeResult<Value> {
+        if self.native_depth >= self.limits.max_native_depth {
+            return Err(RuntimeError::StackOverflow {
+                depth: self.frames.len(),
+            });
+        }
+        let (function, frames, stack) = {
+            let mut coroutine = coroutine.borrow_mut();
+            match coroutine.status {
+                CoroutineStatus::Suspended => (),
+                CoroutineStatus::Running => return Err(RuntimeError::CoroutineRunning),
+                CoroutineStatus::Dead => return Err(RuntimeError::DeadCoroutine),
+            }
+            coroutine.status = CoroutineStatus::Running;
+            (
+                coroutine.function.take(),
+                mem::take(&mut coroutine.frames),
+                mem::take(&mut coroutine.stack),
+            )
+        };
+        let caller_frames = mem::replace(&mut self.frames, frames);
+        let caller_stack = mem::replace(&mut self.stack, stack);
+        self.callers.push((caller_frames, caller_stack));
+        self.native_depth += 1;
+        self.coroutines
+            .push((Rc::clone(coroutine), self.native_depth));
+
+        let result = self.run_coroutine(function, args);
+
+        self.coroutines.pop();
+        self.native_depth -= 1;
+        let (caller_frames, caller_stack) = self.callers.pop().expect("Expected the caller");
+        let frames = mem::replace(&mut self.frames, caller_frames);
+        let stack = mem::replace(&mut self.stack, caller_stack);
+        let mut coroutine = coroutine.borrow_mut();
+        match result {
+            Err(RuntimeError::Yielded(value)) => {
+                coroutine.frames = frames;
+                coroutine.stack = stack;
+                coroutine.status = CoroutineStatus::Suspended;
+                Ok(value)
+            }
+            result => {
+                coroutine.status = CoroutineStatus::Dead;
+                result
+            }
+        }
+    }
+
+    /// Resumes the coroutine as an iterator, which returns nil once it is finished
+    pub(super) fn resume_generator(
+        &mut self,
+        coroutine: &CoroutineRef,
+        args: Vec<Value>,
+    ) -> RuntimeResult<Value> {
+        let is_dead = |coroutine: &CoroutineRef| coroutine.borrow().status == CoroutineStatus::Dead;
+        if is_dead(coroutine) {
+            return Ok(Value::Nil);
+        }
+        let value = self.resume(coroutine, args)?;
+        Ok(if is_dead(coroutine) {
+            Value::Nil
+        } else {
+            value
+        })
+    }
+
+    fn run_coroutine(
+        &mut self,
+        function: Option<UserFunction>,
+        args: Vec<Value>,
+    ) -> RuntimeResult<Value> {
+        match function {
+            Some(function) => {
+                let pushed_args: u8 = args
+                    .len()
+                    .try_into()
+                    .map_err(|_| RuntimeError::TooManyArgs(args.len()))?;
+                self.stack.extend(args);
+                self.call_user(function, pushed_args)?;
+            }
+            None => {
+                // Continue after the call of `yield`
+                self.stack
+                    .push(args.into_iter().next().unwrap_or(Value::Nil));
+                self.current_frame_mut()?.pc += 1;
+            }
+        }
+        self.run_frames_above(0)?;
+        self.pop_stack()
+    }
+
+    /// Suspends the running coroutine, the value is returned by its `resume`
+    pub(super) fn yield_value(&self, value: Value) -> RuntimeResult<Value> {
+        match self.coroutines.last() {
+            None => Err(RuntimeError::YieldOutsideCoroutine),
+            Some((_, native_depth)) if *native_depth != self.native_depth => {
+                Err(RuntimeError::YieldAcrossNative)
+            }
+            Some(_) => Err(RuntimeError::Yielded(value)),
+        }
+    }
+
+    /// Status of the coroutine as seen from the running code, a coroutine
+    /// which resumed another one is "normal"
+    pub fn coroutine_status(&self, coroutine: &CoroutineRef) -> &'static str {
+        match coroutine.borrow().status {
+            CoroutineStatus::Suspended => "suspended",
+            CoroutineStatus::Dead => "dead",
+            CoroutineStatus::Running => match self.coroutines.last() {
+                Some((running, _)) if Rc::ptr_eq(running, coroutine) => "running",
+                _ => "normal",
+            },
+        }
+    }
+}
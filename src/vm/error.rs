@@ -1,6 +1,6 @@
 use crate::compiler::{BinaryInstr, Instruction};
 use crate::error::FluxError;
-use crate::vm::Value;
+use crate::vm::{Range, Value};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum RuntimeError {
@@ -8,13 +8,85 @@ pub enum RuntimeError {
     EmptyFrame,
     UnsupportedInstruction(Instruction),
     EmptyStack,
-    UndefinedVariable { name: String },
-    UnsupportedBinary { value: Value, op: BinaryInstr },
+    UndefinedVariable {
+        name: String,
+    },
+    UnsupportedBinary {
+        value: Value,
+        op: BinaryInstr,
+    },
     IOError,
     InvalidFormat,
-    WrongNumberOfArgs { expected: u8, found: u8 },
+    WrongNumberOfArgs {
+        expected: u8,
+        found: u8,
+    },
+    TooManyArgs(usize),
     ExpectedArgsAtLeast(u8),
+    UnexpectedType {
+        expected: &'static str,
+        found: &'static str,
+    },
+    InvalidArgument {
+        index: usize,
+        expected: &'static str,
+        found: &'static str,
+    },
     DivideByZero,
+    // Only raised with `Overflow::Error`
+    IntegerOverflow,
+    // NaN is never equal to a key, so it can't be one
+    NanKey,
     AssertionFailed(Value),
-    ImportError { error: FluxError, module: String },
+    ImportError {
+        error: FluxError,
+        module: String,
+    },
+    // The canonical paths of the modules importing each other, the first one is repeated last
+    CircularImport {
+        cycle: Vec<String>,
+    },
+    MissingExport {
+        module: String,
+        name: String,
+    },
+    StackOverflow {
+        depth: usize,
+    },
+    BudgetExhausted {
+        budget: u64,
+    },
+    Timeout,
+    Interrupted,
+    OutOfMemory {
+        limit: usize,
+    },
+    // Unwinds the running coroutine to its resume, never returned from a run
+    Yielded(Value),
+    YieldOutsideCoroutine,
+    // Natives running script functions can't be suspended
+    YieldAcrossNative,
+    DeadCoroutine,
+    CoroutineRunning,
+    NotIterable {
+        found: &'static str,
+    },
+    ZeroStep,
+    SliceOutOfBounds {
+        range: Range,
+        len: usize,
+    },
+    // The `__class__`, `__super__` or `__index` links lead back to a table already searched
+    LookupCycle,
+    // Raised by malformed bytecode, the compiler never emits it
+    PcOutOfBounds {
+        pc: usize,
+    },
+    InvalidJump {
+        pc: usize,
+        offset: i8,
+    },
+    InvalidLocal {
+        index: usize,
+    },
 }
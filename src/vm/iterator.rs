@@ -0,0 +1,85 @@
+use super::{Iter, IterItem, IterRef, IterSource, RuntimeError, RuntimeResult, Value, Vm};
+use std::mem::size_of;
+use std::rc::Rc;
+
+// A `for` loop keeps the iterator in a hidden local and advances it with `IterNext`,
+// so finished iterators are told apart from nil items.
+// Functions and coroutines are called until they return nil.
+impl Vm {
+    /// Iterator over the value, `__iter` of its class is called to get the iterated value
+    pub fn iterate(&mut self, value: Value) -> RuntimeResult<IterRef> {
+        let value = match self.metamethod(&value, "__iter") {
+            Some(method) => self.call_value(method, vec![value])?,
+            None => value,
+        };
+        let iter = match value {
+            Value::Iter(iter) => return Ok(iter),
+            Value::Table(table) => {
+                let iter = Iter::table(table);
+                if let IterSource::Table { keys, .. } = &iter.source {
+                    self.alloc(keys.len() * size_of::<Value>())?;
+                }
+                iter
+            }
+            Value::Range(range) => Iter::range(range, 1),
+            Value::Str(string) => Iter::chars(string),
+            Value::Embedded(string) => Iter::chars(Rc::new(string.to_owned())),
+            Value::Function(_) => Iter::new(IterSource::Function(value)),
+            Value::Coroutine(coroutine) => Iter::new(IterSource::Coroutine(coroutine)),
+            value => {
+                return Err(RuntimeError::NotIterable {
+                    found: value.type_name(),
+                })
+            }
+        };
+        self.alloc(size_of::<Iter>())?;
+        Ok(iter.shared())
+    }
+
+    /// Advances the iterator, `None` once it is finished
+    pub(super) fn next_item(&mut self, iter: &IterRef) -> RuntimeResult<Option<IterItem>> {
+        let generator = match &iter.borrow().source {
+            IterSource::Function(function) => Some(function.clone()),
+            IterSource::Coroutine(coroutine) => Some(Value::Coroutine(Rc::clone(coroutine))),
+            _ => None,
+        };
+        let value = match generator {
+            None => return Ok(iter.borrow_mut().next_item()),
+            Some(Value::Coroutine(coroutine)) => self.resume_generator(&coroutine, vec![])?,
+            Some(function) => self.call_value(function, vec![])?,
+        };
+        Ok(match value {
+            Value::Nil => None,
+            value => Some(IterItem::Value(value)),
+        })
+    }
+
+    /// Pushes the values of the next item for `len` variables and whether there was one
+    pub(super) fn iter_next(&mut self, len: u8) -> RuntimeResult<()> {
+        let iter = match self.pop_stack()? {
+            Value::Iter(iter) => iter,
+            _ => return Err(RuntimeError::TypeError),
+        };
+        let has_item = match self.next_item(&iter)? {
+            Some(item) => {
+                iter.borrow().bind(item, len as usize, &mut self.stack);
+                true
+            }
+            None => false,
+        };
+        self.stack.push(Value::Bool(has_item));
+        Ok(())
+    }
+
+    /// Calling an iterator returns its next item, nil once it is finished
+    pub(super) fn call_iter(&mut self, iter: &IterRef) -> RuntimeResult<Value> {
+        Ok(match self.next_item(iter)? {
+            Some(item) => {
+                let mut values = Vec::with_capacity(1);
+                iter.borrow().bind(item, 1, &mut values);
+                values.pop().unwrap_or(Value::Nil)
+            }
+            None => Value::Nil,
+        })
+    }
+}
@@ -2,13 +2,15 @@ mod array;
 #[cfg(test)]
 mod tests;
 
-use super::value::{ArgsLen, Function, NativeFunction, Table};
-use super::{Integer, Value};
+use super::value::{
+    ArgsLen, Function, Iter, IterSource, NativeBody, NativeFunction, Range, Table, TableRef,
+};
+use super::{FromValue, Integer, Value};
 use crate::vm::{RuntimeError, Vm};
 use std::io::{self, Write};
 use std::rc::Rc;
 
-pub const PREDEFINED_CONSTANTS: [(&str, Value); 9] = [
+pub const PREDEFINED_CONSTANTS: [(&str, Value); 19] = [
     ("print", PRINT),
     ("println", PRINTLN),
     ("readline", READLINE),
@@ -18,25 +20,58 @@ pub const PREDEFINED_CONSTANTS: [(&str, Value); 9] = [
     ("new", NEW),
     ("for_each", FOR_EACH),
     ("arity", ARITY),
+    ("len", LEN),
+    ("rawget", RAWGET),
+    ("rawset", RAWSET),
+    ("instanceof", INSTANCEOF),
+    ("iter", ITER),
+    ("pairs", PAIRS),
+    ("ipairs", IPAIRS),
+    ("range", RANGE),
+    ("tostring", TOSTRING),
+    ("require", REQUIRE),
 ];
 
+/// Sources of the `std` modules, embedded so they are found wherever the binary runs
+pub const STD_MODULES: [(&str, &str); 1] = [("array", include_str!("lib/array.flux"))];
+
 macro_rules! define_native {
     ($name:ident, $function:expr, $len:expr) => {
         pub const $name: Value = Value::Function(Function::Native(NativeFunction {
-            function: $function,
+            function: NativeBody::Fn($function),
             args_len: $len,
         }));
     };
 }
 
+mod coroutine;
+
+type ModuleInit = fn() -> Value;
+
+// Modules are tables of natives, they are created for every vm
+const PREDEFINED_MODULES: [(&str, ModuleInit); 1] = [("coroutine", coroutine::module)];
+
+/// Natives and modules every vm starts with
+pub fn predefined_globals() -> Vec<(&'static str, Value)> {
+    PREDEFINED_CONSTANTS
+        .iter()
+        .cloned()
+        .chain(
+            PREDEFINED_MODULES
+                .iter()
+                .map(|(name, module)| (*name, module())),
+        )
+        .collect()
+}
+
 define_native! {
     PRINT,
-    |_vm, args| {
-        let mut args_iter = args.into_iter().rev();
+    |vm, args| {
+        let mut args_iter = args.into_iter();
         if let Some(arg) = args_iter.next() {
-            print!("{}", arg);
+            print!("{}", vm.to_display(&arg)?);
             for arg in args_iter {
-                print!(" {}", arg);
+                print!(" {}", vm.to_display(&arg)?);
             }
         }
         match io::stdout().flush() {
@@ -49,9 +84,9 @@ define_native! {
 
 define_native! {
     PRINTLN,
-    |_vm, args| {
-        for arg in args.into_iter().rev() {
-            print!("{} ", arg);
+    |vm, args| {
+        for arg in args.into_iter() {
+            print!("{} ", vm.to_display(&arg)?);
         }
         println!("");
         Ok(Value::Unit)
@@ -141,19 +176,21 @@ define_native! {
     NEW,
     |vm, args| {
         let table = Table::new().shared();
-        let klass = match args.last() {
+        let mut args = args.into_iter();
+        let klass = match args.next() {
             Some(arg) => arg,
             None => return Err(RuntimeError::ExpectedArgsAtLeast(1)),
         };
+        vm.alloc(Table::HEADER_SIZE + Table::ENTRY_SIZE)?;
         {
             let mut table = table.borrow_mut();
             table.set(Value::Embedded("__class__"), klass.clone());
         }
         if let Ok(init) = Vm::get_table(&Value::Embedded("init"), &klass)?.into_user_fn() {
-            let pushed_args = args.len() as u8;
-            for arg in args.into_iter().rev() {
-                vm.stack.push(arg)
-            }
+            // The instance takes the place of the class as `self`
+            let pushed_args = args.len() as u8 + 1;
+            vm.stack.push(table.clone().into());
+            vm.stack.extend(args);
             vm.call_user_blocking(init, pushed_args)?;
             vm.pop_stack()?;
         }
@@ -166,12 +203,12 @@ define_native! {
 define_native! {
     FOR_EACH,
     |vm, args| {
-        let func = args.get(0)
-            .expect("Expected a function")
-            .clone()
-            .into_user_fn().expect("Expected a function");
+        let mut args = args.into_iter();
+        let table = args.next().expect("Expected a table");
         // Check function args to be 1
-        let table = args.get(1).expect("Expected a table");
+        let func = args.next()
+            .expect("Expected a function")
+            .into_user_fn()?;
         match table {
             Value::Table(table) => {
                 for v in table.borrow().values() {
@@ -205,3 +242,133 @@ define_native! {
     },
     ArgsLen::Exact(1)
 }
+
+define_native! {
+    LEN,
+    |vm, args| vm.len_of(&args[0]),
+    ArgsLen::Exact(1)
+}
+
+// String interpolation converts the values with it
+define_native! {
+    TOSTRING,
+    |vm, args| {
+        let string = vm.to_display(&args[0])?;
+        vm.alloc(string.len())?;
+        Ok(string.into())
+    },
+    ArgsLen::Exact(1)
+}
+
+// Imports a module given by its dotted path when called
+define_native! {
+    REQUIRE,
+    |vm, args| {
+        let path = args[0].as_str()?.to_string();
+        vm.require(&path)
+    },
+    ArgsLen::Exact(1)
+}
+
+// Table access without metamethods
+define_native! {
+    RAWGET,
+    |_vm, args| {
+        match &args[0] {
+            Value::Table(table) => Ok(table.borrow().get(&args[1]).clone()),
+            _ => Err(RuntimeError::TypeError),
+        }
+    },
+    ArgsLen::Exact(2)
+}
+
+define_native! {
+    RAWSET,
+    |vm, args| {
+        let mut args = args.into_iter();
+        match (args.next(), args.next(), args.next()) {
+            (Some(Value::Table(table)), Some(key), Some(value)) => {
+                vm.raw_set(&table, key, value)?;
+                Ok(Value::Unit)
+            },
+            _ => Err(RuntimeError::TypeError),
+        }
+    },
+    ArgsLen::Exact(3)
+}
+
+// Whether the class of the object is the class or inherits from it
+define_native! {
+    INSTANCEOF,
+    |_vm, args| {
+        let class = &args[1];
+        let mut current = match &args[0] {
+            Value::Table(table) => table.borrow().klass().clone(),
+            _ => return Ok(Value::Bool(false)),
+        };
+        let mut visited: Vec<TableRef> = Vec::new();
+        while let Value::Table(table) = current {
+            if Value::Table(Rc::clone(&table)) == *class {
+                return Ok(Value::Bool(true));
+            }
+            if visited.iter().any(|visited| Rc::ptr_eq(visited, &table)) {
+                return Err(RuntimeError::LookupCycle);
+            }
+            current = table.borrow().parent().clone();
+            visited.push(table);
+        }
+        Ok(Value::Bool(false))
+    },
+    ArgsLen::Exact(2)
+}
+
+// Iterator the `for` loop would use for the value
+define_native! {
+    ITER,
+    |vm, mut args| {
+        let value = args.pop().expect("Expected a value");
+        Ok(Value::Iter(vm.iterate(value)?))
+    },
+    ArgsLen::Exact(1)
+}
+
+// Keys and values of the table, bound as a tuple to a single variable
+define_native! {
+    PAIRS,
+    |_vm, mut args| {
+        let table = TableRef::from_value(args.pop().expect("Expected a table"))?;
+        Ok(Value::Iter(Iter::table(table).with_entries().shared()))
+    },
+    ArgsLen::Exact(1)
+}
+
+// Indices from 0 and values of the table until the first missing index
+define_native! {
+    IPAIRS,
+    |_vm, mut args| {
+        let table = TableRef::from_value(args.pop().expect("Expected a table"))?;
+        Ok(Value::Iter(Iter::new(IterSource::Sequence(table)).with_entries().shared()))
+    },
+    ArgsLen::Exact(1)
+}
+
+// range(end), range(start, end) or range(start, end, step), end is excluded
+define_native! {
+    RANGE,
+    |_vm, args| {
+        let mut bounds = Vec::with_capacity(args.len());
+        for arg in args {
+            bounds.push(Integer::from_value(arg)?);
+        }
+        let (start, end, step) = match bounds.as_slice() {
+            [end] => (0, *end, 1),
+            [start, end] => (*start, *end, 1),
+            [_, _, 0] => return Err(RuntimeError::ZeroStep),
+            [start, end, step] => (*start, *end, *step),
+            [] => return Err(RuntimeError::ExpectedArgsAtLeast(1)),
+            _ => return Err(RuntimeError::TooManyArgs(bounds.len())),
+        };
+        Ok(Value::Iter(Iter::range(Range::new(start, end, false), step).shared()))
+    },
+    ArgsLen::Variadic
+}
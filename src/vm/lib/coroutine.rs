@@ -0,0 +1,64 @@
+use super::super::value::{ArgsLen, Coroutine, Function, NativeBody, NativeFunction, Table};
+use super::super::Value;
+use crate::vm::RuntimeError;
+
+pub fn module() -> Value {
+    let mut table = Table::new();
+    for (name, function) in [
+        ("create", CREATE),
+        ("resume", RESUME),
+        ("yield", YIELD),
+        ("status", STATUS),
+    ] {
+        table.set(Value::Embedded(name), function);
+    }
+    table.into()
+}
+
+define_native! {
+    CREATE,
+    |_vm, mut args| {
+        let function = args.pop().expect("Expected a function").into_user_fn()?;
+        Ok(Value::Coroutine(Coroutine::new(function).shared()))
+    },
+    ArgsLen::Exact(1)
+}
+
+// Arguments after the coroutine are passed to it
+define_native! {
+    RESUME,
+    |vm, args| {
+        let mut args = args.into_iter();
+        match args.next() {
+            Some(Value::Coroutine(coroutine)) => vm.resume(&coroutine, args.collect()),
+            Some(_) => Err(RuntimeError::TypeError),
+            None => Err(RuntimeError::ExpectedArgsAtLeast(1)),
+        }
+    },
+    ArgsLen::Variadic
+}
+
+// Multiple values are yielded as a tuple
+define_native! {
+    YIELD,
+    |vm, mut args| {
+        let value = match args.len() {
+            0 => Value::Nil,
+            1 => args.pop().unwrap(),
+            _ => Value::Tuple(args),
+        };
+        vm.yield_value(value)
+    },
+    ArgsLen::Variadic
+}
+
+define_native! {
+    STATUS,
+    |vm, args| {
+        match &args[0] {
+            Value::Coroutine(coroutine) => Ok(Value::Embedded(vm.coroutine_status(coroutine))),
+            _ => Err(RuntimeError::TypeError),
+        }
+    },
+    ArgsLen::Exact(1)
+}
@@ -0,0 +1,72 @@
+use std::time::Duration;
+
+/// Resource limits of a `Vm`. Exceeding any of them aborts the run with an error
+/// instead of exhausting the memory or the host stack.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Limits {
+    /// Maximum number of call frames
+    pub max_call_depth: usize,
+    /// Maximum number of values on the stack
+    pub max_stack_size: usize,
+    /// Maximum nesting of natives calling back into scripts, each of them recurses on the host stack
+    pub max_native_depth: usize,
+    /// Maximum number of instructions executed in a single run
+    pub instruction_budget: Option<u64>,
+    /// Maximum wall-clock time of a single run
+    pub timeout: Option<Duration>,
+    /// Number of instructions between checks of the timeout and the interrupt handle
+    pub check_interval: u32,
+    /// Maximum number of bytes held by the tables, strings and tuples of the vm
+    pub max_memory: Option<usize>,
+}
+
+impl Limits {
+    pub fn with_max_call_depth(mut self, depth: usize) -> Self {
+        self.max_call_depth = depth;
+        self
+    }
+
+    pub fn with_max_stack_size(mut self, size: usize) -> Self {
+        self.max_stack_size = size;
+        self
+    }
+
+    pub fn with_max_native_depth(mut self, depth: usize) -> Self {
+        self.max_native_depth = depth;
+        self
+    }
+
+    pub fn with_instruction_budget(mut self, budget: u64) -> Self {
+        self.instruction_budget = Some(budget);
+        self
+    }
+
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = Some(timeout);
+        self
+    }
+
+    pub fn with_check_interval(mut self, interval: u32) -> Self {
+        self.check_interval = interval.max(1);
+        self
+    }
+
+    pub fn with_max_memory(mut self, bytes: usize) -> Self {
+        self.max_memory = Some(bytes);
+        self
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            max_call_depth: 1024,
+            max_stack_size: 1 << 16,
+            max_native_depth: 64,
+            instruction_budget: None,
+            timeout: None,
+            check_interval: 1024,
+            max_memory: None,
+        }
+    }
+}
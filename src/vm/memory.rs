@@ -0,0 +1,160 @@
+use super::frame::Frame;
+use super::{
+    CoroutineRef, Function, Iter, IterSource, RuntimeError, RuntimeResult, Table, TableRef,
+    UserFunction, Value, Vm,
+};
+use std::collections::HashSet;
+use std::mem::size_of;
+use std::rc::Rc;
+
+// Values are reference counted, so the vm doesn't see them being released. The allocations
+// are added to an estimate which is replaced by the bytes still reachable from the vm
+// once it goes over the limit.
+impl Vm {
+    /// Approximate number of bytes held by the tables, strings and tuples reachable
+    /// from the globals, the stack and the loaded modules
+    pub fn memory_usage(&self) -> usize {
+        self.live_memory()
+    }
+
+    pub(super) fn alloc(&mut self, bytes: usize) -> RuntimeResult<()> {
+        self.memory += bytes;
+        match self.limits.max_memory {
+            Some(limit) if self.memory > limit => {
+                self.memory = self.live_memory() + bytes;
+                if self.memory > limit {
+                    Err(RuntimeError::OutOfMemory { limit })
+                } else {
+                    Ok(())
+                }
+            }
+            _ => Ok(()),
+        }
+    }
+
+    fn live_memory(&self) -> usize {
+        let mut counter = MemoryCounter::default();
+        let callers = self.callers.iter().flat_map(|(_, stack)| stack);
+        for value in self.stack.iter().chain(callers) {
+            counter.value(value);
+        }
+        for (key, value) in &self.globals {
+            counter.value(key);
+            counter.value(value);
+        }
+        let caller_frames = self.callers.iter().flat_map(|(frames, _)| frames);
+        for frame in self.frames.iter().chain(caller_frames) {
+            counter.frame(frame);
+        }
+        for table in self.metatables.values() {
+            counter.table(table);
+        }
+        for module in self.modules.borrow().loaded() {
+            counter.value(module);
+        }
+        for (coroutine, _) in &self.coroutines {
+            counter.coroutine(coroutine);
+        }
+        counter.bytes + self.outer_memory
+    }
+}
+
+// Counts every shared value once, the same sizes as the allocations are used.
+// Nested values are queued instead of recursed into, they can be nested arbitrarily deep.
+#[derive(Default)]
+struct MemoryCounter {
+    bytes: usize,
+    visited: HashSet<*const ()>,
+    pending: Vec<Value>,
+}
+
+impl MemoryCounter {
+    fn first_visit<T>(&mut self, rc: &Rc<T>) -> bool {
+        self.visited.insert(Rc::as_ptr(rc) as *const ())
+    }
+
+    fn value(&mut self, value: &Value) {
+        self.pending.push(value.clone());
+        while let Some(value) = self.pending.pop() {
+            self.visit(value);
+        }
+    }
+
+    fn table(&mut self, table: &TableRef) {
+        self.value(&Value::Table(Rc::clone(table)))
+    }
+
+    fn frame(&mut self, frame: &Frame) {
+        if let Some(function) = frame.function() {
+            self.value(&Value::Function(Function::User(function.clone())))
+        }
+    }
+
+    fn coroutine(&mut self, coroutine: &CoroutineRef) {
+        self.value(&Value::Coroutine(Rc::clone(coroutine)))
+    }
+
+    fn visit(&mut self, value: Value) {
+        match value {
+            Value::Str(string) if self.first_visit(&string) => {
+                self.bytes += size_of::<String>() + string.len();
+            }
+            Value::Tuple(values) => {
+                self.bytes += values.len() * size_of::<Value>();
+                self.pending.extend(values);
+            }
+            // A table being modified is skipped, it is counted again by the next check
+            Value::Table(table) if self.first_visit(&table) => {
+                if let Ok(table) = table.try_borrow() {
+                    self.bytes += Table::HEADER_SIZE + table.len() * Table::ENTRY_SIZE;
+                    for (key, value) in table.pairs() {
+                        self.pending.push(key.clone());
+                        self.pending.push(value.clone());
+                    }
+                }
+            }
+            Value::Function(Function::User(function)) => self.function(&function),
+            Value::Coroutine(coroutine) if self.first_visit(&coroutine) => {
+                if let Ok(coroutine) = coroutine.try_borrow() {
+                    if let Some(function) = &coroutine.function {
+                        self.function(function);
+                    }
+                    for frame in &coroutine.frames {
+                        if let Some(function) = frame.function() {
+                            self.function(function);
+                        }
+                    }
+                    self.pending.extend(coroutine.stack.iter().cloned());
+                }
+            }
+            Value::Iter(iter) if self.first_visit(&iter) => {
+                self.bytes += size_of::<Iter>();
+                if let Ok(iter) = iter.try_borrow() {
+                    self.iter_source(&iter.source);
+                }
+            }
+            _ => (),
+        }
+    }
+
+    fn function(&mut self, function: &UserFunction) {
+        if let Some(env) = function.env() {
+            self.pending.push(Value::Table(Rc::clone(env)));
+        }
+    }
+
+    fn iter_source(&mut self, source: &IterSource) {
+        let value = match source {
+            IterSource::Table { table, keys } => {
+                self.bytes += keys.len() * size_of::<Value>();
+                Value::Table(Rc::clone(table))
+            }
+            IterSource::Sequence(table) => Value::Table(Rc::clone(table)),
+            IterSource::Chars { string, .. } => Value::Str(Rc::clone(string)),
+            IterSource::Function(function) => function.clone(),
+            IterSource::Coroutine(coroutine) => Value::Coroutine(Rc::clone(coroutine)),
+            IterSource::Range { .. } => return,
+        };
+        self.pending.push(value);
+    }
+}
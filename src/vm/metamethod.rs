@@ -0,0 +1,235 @@
+use super::{RuntimeError, RuntimeResult, Table, TableRef, Value, Vm};
+use crate::compiler::{BinaryInstr, UnaryInstr};
+use std::rc::Rc;
+
+// Metamethods of a table are looked up in its class chain, starting at `__class__`,
+// so a class does not apply its own operators to itself.
+// Metamethods of userdata are looked up in the metatable of its type.
+impl Vm {
+    fn metatable_of(&self, value: &Value) -> Option<Value> {
+        match value {
+            Value::Table(table) => match table.borrow().klass() {
+                Value::Nil => None,
+                klass => Some(klass.clone()),
+            },
+            Value::UserData(data) => self
+                .metatables
+                .get(&data.type_id())
+                .map(|metatable| Value::Table(Rc::clone(metatable))),
+            _ => None,
+        }
+    }
+
+    pub(super) fn metamethod(&self, value: &Value, name: &'static str) -> Option<Value> {
+        let metatable = self.metatable_of(value)?;
+        match Self::get_table(&Value::Embedded(name), &metatable) {
+            Ok(Value::Nil) | Err(_) => None,
+            Ok(method) => Some(method),
+        }
+    }
+
+    fn has_metatable(&self, value: &Value) -> bool {
+        self.metatable_of(value).is_some()
+    }
+
+    /// Tries the metamethod of the left operand, then of the right one
+    fn binary_metamethod(
+        &mut self,
+        name: &'static str,
+        left: &Value,
+        right: &Value,
+    ) -> RuntimeResult<Option<Value>> {
+        let method = self
+            .metamethod(left, name)
+            .or_else(|| self.metamethod(right, name));
+        match method {
+            Some(method) => self
+                .call_value(method, vec![left.clone(), right.clone()])
+                .map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Result of a binary operation on values with metatables,
+    /// `None` if there is no metamethod for it
+    pub(super) fn binary_meta(
+        &mut self,
+        op: BinaryInstr,
+        left: &Value,
+        right: &Value,
+    ) -> RuntimeResult<Option<Value>> {
+        if !self.has_metatable(left) && !self.has_metatable(right) {
+            return Ok(None);
+        }
+        let compare = |result: Option<Value>, negate: bool| {
+            result.map(|value| Value::Bool(value.as_bool() != negate))
+        };
+        Ok(match op {
+            BinaryInstr::Add => self.binary_metamethod("__add", left, right)?,
+            BinaryInstr::Sub => self.binary_metamethod("__sub", left, right)?,
+            BinaryInstr::Mul => self.binary_metamethod("__mul", left, right)?,
+            BinaryInstr::Div => self.binary_metamethod("__div", left, right)?,
+            BinaryInstr::Rem => self.binary_metamethod("__rem", left, right)?,
+            BinaryInstr::BitAnd => self.binary_metamethod("__band", left, right)?,
+            BinaryInstr::BitOr => self.binary_metamethod("__bor", left, right)?,
+            BinaryInstr::BitXor => self.binary_metamethod("__bxor", left, right)?,
+            BinaryInstr::Shl => self.binary_metamethod("__shl", left, right)?,
+            BinaryInstr::Shr => self.binary_metamethod("__shr", left, right)?,
+            BinaryInstr::Eq | BinaryInstr::Ne => {
+                // Only values of the same kind are compared with `__eq`
+                let comparable = matches!(
+                    (left, right),
+                    (Value::Table(_), Value::Table(_)) | (Value::UserData(_), Value::UserData(_))
+                );
+                if comparable && left != right {
+                    let result = self.binary_metamethod("__eq", left, right)?;
+                    compare(result, op == BinaryInstr::Ne)
+                } else {
+                    None
+                }
+            }
+            BinaryInstr::Lt => compare(self.binary_metamethod("__lt", left, right)?, false),
+            BinaryInstr::Gt => compare(self.binary_metamethod("__lt", right, left)?, false),
+            // a <= b is !(b < a) without `__le`
+            BinaryInstr::Le => match self.binary_metamethod("__le", left, right)? {
+                Some(result) => compare(Some(result), false),
+                None => compare(self.binary_metamethod("__lt", right, left)?, true),
+            },
+            BinaryInstr::Ge => match self.binary_metamethod("__le", right, left)? {
+                Some(result) => compare(Some(result), false),
+                None => compare(self.binary_metamethod("__lt", left, right)?, true),
+            },
+        })
+    }
+
+    pub(super) fn unary_meta(
+        &mut self,
+        op: UnaryInstr,
+        value: &Value,
+    ) -> RuntimeResult<Option<Value>> {
+        let name = match op {
+            UnaryInstr::Negate => "__neg",
+            UnaryInstr::BitNot => "__bnot",
+            // `__tostring` is called by the conversion itself
+            UnaryInstr::Not | UnaryInstr::ToString => return Ok(None),
+        };
+        match self.metamethod(value, name) {
+            Some(method) => self.call_value(method, vec![value.clone()]).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Looks up the key in the table and its classes, then falls back to `__index`
+    pub(super) fn index(&mut self, object: &Value, key: &Value) -> RuntimeResult<Value> {
+        let mut object = object.clone();
+        let mut visited = Vec::new();
+        loop {
+            let value = match &object {
+                Value::Table(_) => Self::get_table(key, &object)?,
+                Value::UserData(_) => match self.metatable_of(&object) {
+                    Some(metatable) => Self::get_table(key, &metatable)?,
+                    None => Value::Nil,
+                },
+                _ => return Err(RuntimeError::TypeError),
+            };
+            if value != Value::Nil {
+                return Ok(value);
+            }
+            match self.metamethod(&object, "__index") {
+                Some(function @ Value::Function(_)) => {
+                    return self.call_value(function, vec![object, key.clone()])
+                }
+                Some(Value::Table(table)) => {
+                    Self::visit(&mut visited, &table)?;
+                    object = Value::Table(table);
+                }
+                _ => return Ok(Value::Nil),
+            }
+        }
+    }
+
+    // `__index` and `__newindex` tables are followed in a loop, meeting one twice is a cycle
+    fn visit(visited: &mut Vec<TableRef>, table: &TableRef) -> RuntimeResult<()> {
+        if visited.iter().any(|visited| Rc::ptr_eq(visited, table)) {
+            return Err(RuntimeError::LookupCycle);
+        }
+        visited.push(Rc::clone(table));
+        Ok(())
+    }
+
+    /// Sets the key of the table, `__newindex` is called instead for missing keys
+    pub(super) fn new_index(
+        &mut self,
+        mut object: Value,
+        key: Value,
+        value: Value,
+    ) -> RuntimeResult<()> {
+        let mut visited = Vec::new();
+        loop {
+            let exists = match &object {
+                Value::Table(table) => table.borrow().get(&key) != &Value::Nil,
+                Value::UserData(_) => false,
+                _ => return Err(RuntimeError::TypeError),
+            };
+            if exists {
+                break;
+            }
+            match self.metamethod(&object, "__newindex") {
+                Some(function @ Value::Function(_)) => {
+                    return self
+                        .call_value(function, vec![object, key, value])
+                        .map(|_| ());
+                }
+                Some(Value::Table(table)) => {
+                    Self::visit(&mut visited, &table)?;
+                    object = Value::Table(table);
+                }
+                _ => break,
+            }
+        }
+        match object {
+            Value::Table(table) => self.raw_set(&table, key, value),
+            _ => Err(RuntimeError::TypeError),
+        }
+    }
+
+    pub(super) fn raw_set(
+        &mut self,
+        table: &TableRef,
+        key: Value,
+        value: Value,
+    ) -> RuntimeResult<()> {
+        if key.is_nan() {
+            return Err(RuntimeError::NanKey);
+        }
+        let inserted = table.borrow_mut().set(key, value).is_none();
+        if inserted {
+            self.alloc(Table::ENTRY_SIZE)?;
+        }
+        Ok(())
+    }
+
+    /// Converts the value to a string, using `__tostring` if it has one
+    pub fn to_display(&mut self, value: &Value) -> RuntimeResult<String> {
+        match self.metamethod(value, "__tostring") {
+            Some(method) => Ok(self.call_value(method, vec![value.clone()])?.to_string()),
+            None => Ok(value.to_string()),
+        }
+    }
+
+    /// Length of the value, using `__len` if it has one
+    pub fn len_of(&mut self, value: &Value) -> RuntimeResult<Value> {
+        if let Some(method) = self.metamethod(value, "__len") {
+            return self.call_value(method, vec![value.clone()]);
+        }
+        let len = match value {
+            Value::Str(string) => string.chars().count(),
+            Value::Embedded(string) => string.chars().count(),
+            Value::Table(table) => table.borrow().len(),
+            Value::Tuple(values) => values.len(),
+            Value::Range(range) => range.len(),
+            _ => return Err(RuntimeError::TypeError),
+        };
+        Ok(Value::Int(len as i64))
+    }
+}
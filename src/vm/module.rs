@@ -0,0 +1,222 @@
+use super::{RuntimeError, RuntimeResult, Table, Value, Vm};
+use crate::compiler::{
+    resolve_module, CompileError, CompileOptions, CompileResult, CompiledSource, Compiler,
+};
+use crate::sourcefile::{MetaData, SourceFile};
+use std::collections::HashMap;
+use std::path::Path;
+use std::rc::Rc;
+
+/// Modules known by a vm and the vms running its imports, keyed by their canonical path.
+/// A module is run at its first import, the next ones share its export table.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Modules {
+    sources: HashMap<String, CompiledSource>,
+    // Export tables of the modules already run
+    loaded: HashMap<String, Value>,
+    // Modules being run, the last one is the innermost import
+    loading: Vec<String>,
+    // Used to compile the modules required at runtime
+    options: CompileOptions,
+    // Globals the vms running the modules start with, the natives enabled or
+    // registered in the importing vm
+    natives: HashMap<Value, Value>,
+}
+
+impl Modules {
+    pub fn new(natives: HashMap<Value, Value>) -> Self {
+        Modules {
+            natives,
+            ..Self::default()
+        }
+    }
+
+    // Moves the modules imported by the source to the registry
+    pub fn add_imports(&mut self, source: &mut CompiledSource) {
+        for (key, chunk) in source.chunk.take_imports() {
+            let constant_table = Rc::clone(&source.constant_table);
+            self.sources.insert(
+                key,
+                CompiledSource {
+                    chunk,
+                    constant_table,
+                },
+            );
+        }
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.loaded.contains_key(key) || self.sources.contains_key(key)
+    }
+
+    pub(super) fn loaded(&self) -> impl Iterator<Item = &Value> {
+        self.loaded.values()
+    }
+
+    fn native_names(&self) -> Vec<String> {
+        self.natives.keys().map(Value::to_string).collect()
+    }
+
+    pub(super) fn set_native(&mut self, name: Value, native: Value) {
+        self.natives.insert(name, native);
+    }
+
+    pub(super) fn remove_native(&mut self, name: &Value) {
+        self.natives.remove(name);
+    }
+}
+
+impl Vm {
+    /// Names of the natives the imported modules can use
+    pub(crate) fn native_names(&self) -> Vec<String> {
+        self.modules.borrow().native_names()
+    }
+
+    pub(crate) fn set_compile_options(&mut self, options: CompileOptions) {
+        self.modules.borrow_mut().options = options;
+    }
+
+    /// Imports a module while running, the module is compiled unless it already was
+    pub(super) fn require(&mut self, path: &str) -> RuntimeResult<Value> {
+        let key = self
+            .compile_module(path)
+            .map_err(|error| RuntimeError::ImportError {
+                error: error.into(),
+                module: path.to_string(),
+            })?;
+        self.module(key)
+    }
+
+    // Compiles the module with its imports into the registry and returns its key
+    fn compile_module(&mut self, path: &str) -> CompileResult<String> {
+        let options = self.modules.borrow().options.clone();
+        if !options.imports {
+            return Err(CompileError::ImportDisabled {
+                name: path.to_string(),
+            });
+        }
+        let path: Vec<String> = path.split('.').map(str::to_string).collect();
+        let options = CompileOptions {
+            natives: Some(self.native_names()),
+            ..options
+        };
+        let current_dir = match &self.compiled {
+            Some(compiled) => compiled.chunk.dir(),
+            None => Path::new(""),
+        };
+        let module = resolve_module(&path, current_dir, &options.search_paths)?;
+        let key = module.key.clone();
+        if self.modules.borrow().contains(&key) {
+            return Ok(key);
+        }
+        let ast = module.parse(&options)?;
+        let metadata = MetaData { dir: module.dir };
+        let mut compiled = Compiler::compile_with(SourceFile { ast, metadata }, options)?;
+        let mut modules = self.modules.borrow_mut();
+        modules.add_imports(&mut compiled);
+        modules.sources.insert(key.clone(), compiled);
+        Ok(key)
+    }
+
+    pub(super) fn import(&mut self, path_index: usize) -> RuntimeResult<()> {
+        let key = self.constant_table()[path_index].as_str()?.to_string();
+        let module = self.module(key)?;
+        self.stack.push(module);
+        Ok(())
+    }
+
+    pub(super) fn import_from(
+        &mut self,
+        path_index: usize,
+        name_index: usize,
+    ) -> RuntimeResult<()> {
+        let key = self.constant_table()[path_index].as_str()?.to_string();
+        let name = self.constant_table()[name_index].clone();
+        let value = match self.module(key.clone())? {
+            Value::Table(table) => table.borrow().get(&name).clone(),
+            _ => unreachable!("Modules are tables"),
+        };
+        if value == Value::Nil {
+            return Err(RuntimeError::MissingExport {
+                module: key,
+                name: name.to_string(),
+            });
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn module(&mut self, key: String) -> RuntimeResult<Value> {
+        if let Some(module) = self.modules.borrow().loaded.get(&key) {
+            return Ok(module.clone());
+        }
+        let source = {
+            let mut modules = self.modules.borrow_mut();
+            if let Some(start) = modules.loading.iter().position(|module| *module == key) {
+                let mut cycle = modules.loading[start..].to_vec();
+                cycle.push(key);
+                return Err(RuntimeError::CircularImport { cycle });
+            }
+            modules.loading.push(key.clone());
+            modules
+                .sources
+                .get(&key)
+                .cloned()
+                .expect("Expected a compiled module")
+        };
+        let mut vm = Vm::new()
+            .with_limits(self.limits.clone())
+            .with_overflow(self.overflow);
+        vm.globals = self.modules.borrow().natives.clone();
+        vm.interrupt = self.interrupt.clone();
+        vm.metatables = self.metatables.clone();
+        vm.modules = Rc::clone(&self.modules);
+        // The module runs as part of the importing run
+        vm.executed = self.executed;
+        vm.deadline = self.deadline;
+        vm.memory = self.memory;
+        vm.outer_memory = self.memory;
+        let predefined = vm.globals.clone();
+        let exports = source.chunk.exports().map(<[String]>::to_vec);
+        let result = vm
+            .continue_run(source)
+            .and_then(|_| vm.exports(&predefined, exports));
+        self.executed = vm.executed;
+        self.memory = vm.memory;
+        self.modules.borrow_mut().loading.pop();
+        let module = result.map_err(|error| match error {
+            // Reported once for the whole cycle
+            RuntimeError::CircularImport { .. } => error,
+            error => RuntimeError::ImportError {
+                error: error.into(),
+                module: key.clone(),
+            },
+        })?;
+        self.modules.borrow_mut().loaded.insert(key, module.clone());
+        Ok(module)
+    }
+
+    // The exported globals, or all the ones defined by the module when it doesn't export
+    fn exports(
+        &self,
+        predefined: &HashMap<Value, Value>,
+        names: Option<Vec<String>>,
+    ) -> RuntimeResult<Value> {
+        let exports = match names {
+            Some(names) => names
+                .into_iter()
+                .map(|name| match self.globals.get(&Value::new_str(&name)) {
+                    Some(value) => Ok((Value::new_str(name), value.clone())),
+                    None => Err(RuntimeError::UndefinedVariable { name }),
+                })
+                .collect::<RuntimeResult<_>>()?,
+            None => self
+                .globals
+                .iter()
+                .filter(|(name, value)| predefined.get(*name) != Some(*value))
+                .map(|(name, value)| (name.clone(), value.clone()))
+                .collect(),
+        };
+        Ok(Table::from_map(exports).into())
+    }
+}
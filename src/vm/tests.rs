@@ -1,8 +1,19 @@
-use super::RuntimeError;
-use crate::error::FluxError;
-use crate::parser::{ParserError, ParserErrorKind};
+use super::{Limits, Range, RuntimeError, Vm};
+use crate::compiler::Compiler;
+use crate::error::{FluxError, FluxResult};
+use crate::parser::{Parser, ParserError, ParserErrorKind};
+use crate::sourcefile::{MetaData, SourceFile};
 use crate::vm::Value;
 
+fn eval_with(vm: &mut Vm, source: &str) -> FluxResult<Value> {
+    let ast = Parser::parse_str(source)?;
+    let compiled = Compiler::compile(SourceFile {
+        ast,
+        metadata: MetaData::default(),
+    })?;
+    vm.run(compiled).map_err(|e| e.into())
+}
+
 unit_test! {
     wrong_number_of_args,
     "
@@ -266,6 +277,24 @@ unit_test! {
     Ok(Value::Int(15))
 }
 
+unit_test! {
+    if_stmt_discards_values,
+    "
+    let i = 0;
+    while i < 3 then
+        if i == 1 then
+            10
+        else
+            20
+        end
+        i = i + 1;
+    end
+    let x = 5;
+    return (i, x);
+    ",
+    Ok(Value::Tuple(vec![Value::Int(3), Value::Int(5)]))
+}
+
 unit_test! {
     func_expr_works,
     "
@@ -377,6 +406,825 @@ fn divide_by_zero() {
     );
 }
 
+unit_test! {
+    new_init_calls_function,
+    "
+    let class = {
+        \"init\" = fn(self, x)
+            let double = fn(y) y * 2 end;
+            self.x = double(x);
+        end
+    };
+    new(class, 4).x
+    ",
+    Ok(Value::Int(8))
+}
+
+unit_test! {
+    for_each_visits_values,
+    "
+    let acc = { \"sum\" = 0 };
+    for_each({ 1, 2, 3 }, fn(x) acc.sum = acc.sum + x; end);
+    acc.sum
+    ",
+    Ok(Value::Int(6))
+}
+
+#[test]
+fn stack_overflow() {
+    let mut vm = Vm::new().with_limits(Limits::default().with_max_call_depth(32));
+    let source = "
+    let f = fn(n) f(n + 1) end;
+    f(0)
+    ";
+    assert_eq!(
+        eval_with(&mut vm, source),
+        Err(RuntimeError::StackOverflow { depth: 32 }.into())
+    );
+    // Vm is still usable after the error
+    assert_eq!(eval_with(&mut vm, "1 + 1"), Ok(Value::Int(2)));
+}
+
+#[test]
+fn native_reentry_overflow() {
+    let mut vm = Vm::new().with_limits(Limits::default().with_max_native_depth(8));
+    let source = "
+    let f = fn(x)
+        for_each({ x }, f)
+    end;
+    f(0)
+    ";
+    match eval_with(&mut vm, source) {
+        Err(FluxError::Runtime(error)) => match *error {
+            RuntimeError::StackOverflow { .. } => (),
+            error => panic!("Expected stack overflow, found {:?}", error),
+        },
+        result => panic!("Expected stack overflow, found {:?}", result),
+    }
+}
+
+#[test]
+fn instruction_budget() {
+    let mut vm = Vm::new().with_limits(Limits::default().with_instruction_budget(1000));
+    assert_eq!(
+        eval_with(&mut vm, "let i = 0; while true then i = i + 1; end"),
+        Err(RuntimeError::BudgetExhausted { budget: 1000 }.into())
+    );
+    assert_eq!(eval_with(&mut vm, "1 + 1"), Ok(Value::Int(2)));
+}
+
+#[test]
+fn timeout() {
+    use std::time::Duration;
+
+    let limits = Limits::default()
+        .with_timeout(Duration::from_millis(10))
+        .with_check_interval(16);
+    let mut vm = Vm::new().with_limits(limits);
+    assert_eq!(
+        eval_with(&mut vm, "let i = 0; while true then i = i + 1; end"),
+        Err(RuntimeError::Timeout.into())
+    );
+}
+
+#[test]
+fn interrupt() {
+    let mut vm = Vm::new().with_limits(Limits::default().with_check_interval(1));
+    let handle = vm.interrupt_handle();
+    std::thread::spawn(move || handle.interrupt())
+        .join()
+        .unwrap();
+    assert_eq!(
+        eval_with(&mut vm, "let i = 0; while true then i = i + 1; end"),
+        Err(RuntimeError::Interrupted.into())
+    );
+    // Interrupt request is consumed
+    assert_eq!(eval_with(&mut vm, "1 + 1"), Ok(Value::Int(2)));
+}
+
+#[test]
+fn memory_limit() {
+    let mut vm = Vm::new().with_limits(Limits::default().with_max_memory(4096));
+    let source = "
+    let t = {};
+    let i = 0;
+    while true then
+        t[i] = \"value\";
+        i = i + 1;
+    end
+    ";
+    assert_eq!(
+        eval_with(&mut vm, source),
+        Err(RuntimeError::OutOfMemory { limit: 4096 }.into())
+    );
+    // The table of the aborted run is released
+    assert!(vm.memory_usage() < 4096);
+    assert_eq!(eval_with(&mut vm, "1 + 1"), Ok(Value::Int(2)));
+    // Values which are no longer reachable don't count
+    let source = "
+    let i = 0;
+    while i < 1000 then
+        let t = { i, \"value\" };
+        i = i + 1;
+    end
+    i
+    ";
+    assert_eq!(eval_with(&mut vm, source), Ok(Value::Int(1000)));
+}
+
+#[test]
+fn memory_usage() {
+    let mut vm = Vm::new();
+    let empty = vm.memory_usage();
+    eval_with(&mut vm, "var t = { 1, 2, 3 };").unwrap();
+    let table = vm.memory_usage();
+    assert!(table > empty);
+    // Globals are held between runs
+    eval_with(&mut vm, "1 + 1").unwrap();
+    assert_eq!(vm.memory_usage(), table);
+    // Shared values are counted once
+    let source = "
+    var list = nil;
+    let i = 0;
+    while i < 1000 then
+        list = { list, list };
+        i = i + 1;
+    end
+    ";
+    eval_with(&mut vm, source).unwrap();
+    assert!(vm.memory_usage() > table);
+    eval_with(&mut vm, "var t = nil; var list = nil;").unwrap();
+    assert!(vm.memory_usage() < table);
+}
+
+unit_test! {
+    metamethod_operators,
+    "
+    let Vec = {
+        \"init\" = fn(self, x, y)
+            self.x = x;
+            self.y = y;
+        end,
+        \"__add\" = fn(a, b) new(a.__class__, a.x + b.x, a.y + b.y) end,
+        \"__eq\" = fn(a, b) if a.x == b.x then a.y == b.y else false end end,
+        \"__lt\" = fn(a, b) a.x < b.x end,
+        \"__neg\" = fn(a) new(a.__class__, -a.x, -a.y) end,
+    };
+    let a = new(Vec, 1, 2);
+    let b = new(Vec, 3, 4);
+    let c = a + b;
+    assert(c.y == 6);
+    assert(a + b == new(Vec, 4, 6));
+    assert(a != b);
+    assert(a < b);
+    assert(b > a);
+    assert(a <= b);
+    assert(!(a >= b));
+    (-a).x
+    ",
+    Ok(Value::Int(-1))
+}
+
+unit_test! {
+    metamethod_index,
+    "
+    let Defaults = {
+        \"__index\" = fn(self, key) key + \"!\" end,
+    };
+    let obj = new(Defaults);
+    obj.own = \"own\";
+    assert(obj.own == \"own\");
+    assert(obj.foo == \"foo!\");
+
+    let Doubled = {
+        \"__newindex\" = fn(self, key, value)
+            rawset(self, key, value * 2);
+        end,
+    };
+    let d = new(Doubled);
+    d.x = 5;
+    assert(d.x == 10);
+    // Existing keys are set directly
+    d.x = 7;
+    d.x
+    ",
+    Ok(Value::Int(7))
+}
+
+#[test]
+fn lookup_cycles() {
+    use crate::util::eval;
+
+    let cycle = Err(RuntimeError::LookupCycle.into());
+    assert_eq!(eval("let t = {}; t.__class__ = t; t.x", ""), cycle);
+    let classes = "
+    let A = {};
+    let B = {};
+    A.__super__ = B;
+    B.__super__ = A;
+    let o = new(A);
+    ";
+    assert_eq!(eval(&format!("{} o.x", classes), ""), cycle);
+    assert_eq!(eval(&format!("{} instanceof(o, {{}})", classes), ""), cycle);
+    let index = "
+    let C = {};
+    let o = new(C);
+    C.__index = o;
+    C.__newindex = o;
+    ";
+    assert_eq!(eval(&format!("{} o.x", index), ""), cycle);
+    assert_eq!(eval(&format!("{} o.x = 1;", index), ""), cycle);
+    // Operators find no metamethod instead
+    assert_eq!(
+        eval("let t = {}; t.__class__ = t; t == t", ""),
+        Ok(Value::Bool(true))
+    );
+}
+
+unit_test! {
+    metamethod_call_len,
+    "
+    let Adder = {
+        \"__call\" = fn(self, x) self.n + x end,
+        \"__len\" = fn(self) 42 end,
+    };
+    let add = new(Adder);
+    add.n = 10;
+    (add(5), len(add), len(\"abc\"), len({ 1, 2 }))
+    ",
+    Ok(Value::Tuple(vec![
+        Value::Int(15),
+        Value::Int(42),
+        Value::Int(3),
+        Value::Int(2),
+    ]))
+}
+
+#[test]
+fn metamethod_tostring() {
+    let mut vm = Vm::new();
+    let source = "
+    let Point = {
+        \"__tostring\" = fn(self) \"point\" end,
+    };
+    new(Point)
+    ";
+    let point = eval_with(&mut vm, source).unwrap();
+    assert_eq!(vm.to_display(&point), Ok("point".to_string()));
+    assert_eq!(vm.to_display(&Value::Int(5)), Ok("5".to_string()));
+}
+
+unit_test! {
+    class_inheritance,
+    "
+    class Animal
+        fn init(self, name)
+            self.name = name;
+        end
+
+        fn speak(self) self.name + \" makes a sound\" end
+
+        fn kind(self) \"animal\" end
+    end
+
+    class Dog extends Animal
+        fn init(self, name)
+            super:init(name);
+            self.tricks = 0;
+        end
+
+        fn speak(self) super:speak() + \" and barks\" end
+    end
+
+    let dog = new(Dog, \"rex\");
+    assert(dog:kind() == \"animal\");
+    assert(dog.tricks == 0);
+    assert(instanceof(dog, Dog));
+    assert(instanceof(dog, Animal));
+    assert(!instanceof(new(Animal, \"cat\"), Dog));
+    assert(!instanceof(5, Dog));
+    dog:speak()
+    ",
+    Ok(Value::new_str("rex makes a sound and barks"))
+}
+
+unit_test! {
+    coroutine_generator,
+    "
+    let gen = coroutine.create(fn()
+        let i = 0;
+        while i < 3 then
+            i = i + 1;
+            coroutine.yield(i);
+        end
+    end);
+    let sum = 0;
+    for x in gen do
+        sum = sum + x;
+    end
+    sum
+    ",
+    Ok(Value::Int(6))
+}
+
+unit_test! {
+    coroutine_resume,
+    "
+    let co = coroutine.create(fn(a, b)
+        let c = coroutine.yield(a + b);
+        let d = coroutine.yield(c * 2);
+        d + 1
+    end);
+    assert(coroutine.status(co) == \"suspended\");
+    let first = coroutine.resume(co, 1, 2);
+    let second = coroutine.resume(co, 10);
+    let third = coroutine.resume(co, 5);
+    assert(coroutine.status(co) == \"dead\");
+    (first, second, third)
+    ",
+    Ok(Value::Tuple(vec![Value::Int(3), Value::Int(20), Value::Int(6)]))
+}
+
+unit_test! {
+    coroutine_status_running,
+    "
+    var outer = nil;
+    outer = coroutine.create(fn()
+        let inner = coroutine.create(fn()
+            coroutine.status(outer)
+        end);
+        (coroutine.status(outer), coroutine.resume(inner))
+    end);
+    coroutine.resume(outer)
+    ",
+    Ok(Value::Tuple(vec![Value::new_str("running"), Value::new_str("normal")]))
+}
+
+#[test]
+fn coroutine_errors() {
+    use crate::util::eval;
+
+    assert_eq!(
+        eval("coroutine.yield(1)", ""),
+        Err(RuntimeError::YieldOutsideCoroutine.into())
+    );
+    let source = "
+    let co = coroutine.create(fn() 1 end);
+    coroutine.resume(co);
+    coroutine.resume(co)
+    ";
+    assert_eq!(eval(source, ""), Err(RuntimeError::DeadCoroutine.into()));
+    let source = "
+    let co = coroutine.create(fn()
+        for_each({ 1 }, fn(x) coroutine.yield(x); end)
+    end);
+    coroutine.resume(co)
+    ";
+    assert_eq!(
+        eval(source, ""),
+        Err(RuntimeError::YieldAcrossNative.into())
+    );
+}
+
+unit_test! {
+    iterate_table,
+    "
+    let sum = 0;
+    let count = 0;
+    // Nil elements don't end the loop
+    for x in { 1, nil, 3 } do
+        count = count + 1;
+        if x != nil then
+            sum = sum + x;
+        end
+    end
+    let keys = 0;
+    for k, v in { 10, 20, 30 } do
+        keys = keys + k;
+        sum = sum + v;
+    end
+    (sum, count, keys)
+    ",
+    Ok(Value::Tuple(vec![Value::Int(64), Value::Int(3), Value::Int(3)]))
+}
+
+unit_test! {
+    iterate_pairs,
+    "
+    let t = { 5, 6 };
+    t[3] = 8;
+    let last = nil;
+    for entry in pairs({ 5 }) do
+        last = entry;
+    end
+    let indices = 0;
+    for i, v in ipairs(t) do
+        indices = indices + 1;
+    end
+    let count = 0;
+    for k, v in pairs(t) do
+        count = count + 1;
+    end
+    (last, indices, count)
+    ",
+    Ok(Value::Tuple(vec![
+        Value::Tuple(vec![Value::Int(0), Value::Int(5)]),
+        Value::Int(2),
+        Value::Int(3)
+    ]))
+}
+
+unit_test! {
+    iterate_range,
+    "
+    let sum = 0;
+    for i in range(5) do
+        sum = sum + i;
+    end
+    for i in range(10, 0, -3) do
+        sum = sum + i;
+    end
+    for i in range(1, 1) do
+        sum = sum + 100;
+    end
+    let it = range(2, 4);
+    (sum, it(), it(), it())
+    ",
+    Ok(Value::Tuple(vec![
+        Value::Int(32),
+        Value::Int(2),
+        Value::Int(3),
+        Value::Nil
+    ]))
+}
+
+unit_test! {
+    iterate_string,
+    "
+    let reversed = \"\";
+    for c in \"abc\" do
+        reversed = c + reversed;
+    end
+    let last = 0;
+    for i, c in \"héllo\" do
+        last = i;
+    end
+    (reversed, last)
+    ",
+    Ok(Value::Tuple(vec![Value::new_str("cba"), Value::Int(4)]))
+}
+
+unit_test! {
+    iterate_metamethod,
+    "
+    class Bag
+        fn init(self)
+            self.items = { 1, 2, 3 };
+        end
+
+        fn __iter(self) self.items end
+    end
+    let sum = 0;
+    for x in new(Bag) do
+        sum = sum + x;
+    end
+    sum
+    ",
+    Ok(Value::Int(6))
+}
+
+#[test]
+fn iterate_errors() {
+    use crate::util::eval;
+
+    assert_eq!(
+        eval("for x in 5 do end", ""),
+        Err(RuntimeError::NotIterable { found: "int" }.into())
+    );
+    assert_eq!(
+        eval("for x in range(0, 5, 0) do end", ""),
+        Err(RuntimeError::ZeroStep.into())
+    );
+}
+
+unit_test! {
+    range_loop,
+    "
+    let sum = 0;
+    for i in 0..5 do
+        sum = sum + i;
+    end
+    for i in 1..=3 do
+        sum = sum + i * 10;
+    end
+    let n = 2;
+    for i in 3..n + 1 do
+        sum = sum + 100;
+    end
+    (sum, len(0..=9), 1..3)
+    ",
+    Ok(Value::Tuple(vec![
+        Value::Int(70),
+        Value::Int(10),
+        Value::Range(Range::new(1, 3, false))
+    ]))
+}
+
+unit_test! {
+    range_slice,
+    "
+    let s = \"héllo\";
+    let t = { 1, 2, 3, 4 };
+    let part = t[1..=2];
+    (s[1..3], s[2..2], part[0], part[1], len(part), (1, 2, 3)[1..3])
+    ",
+    Ok(Value::Tuple(vec![
+        Value::new_str("él"),
+        Value::new_str(""),
+        Value::Int(2),
+        Value::Int(3),
+        Value::Int(2),
+        Value::Tuple(vec![Value::Int(2), Value::Int(3)])
+    ]))
+}
+
+#[test]
+fn range_errors() {
+    use crate::util::eval;
+
+    assert_eq!(
+        eval("\"abc\"[2..5]", ""),
+        Err(RuntimeError::SliceOutOfBounds {
+            range: Range::new(2, 5, false),
+            len: 3
+        }
+        .into())
+    );
+    // Only the array part of a table is sliced
+    assert_eq!(
+        eval("let t = { 1, 2 }; t.name = 3; t[5] = 4; t[0..3]", ""),
+        Err(RuntimeError::SliceOutOfBounds {
+            range: Range::new(0, 3, false),
+            len: 2
+        }
+        .into())
+    );
+    assert_eq!(
+        eval(
+            "let t = {}; t[0] = 1; t[1] = 2; t.name = 3; len(t[0..2])",
+            ""
+        ),
+        Ok(Value::Int(2))
+    );
+    assert_eq!(
+        eval("1.5..3", ""),
+        Err(RuntimeError::UnexpectedType {
+            expected: "int",
+            found: "number"
+        }
+        .into())
+    );
+}
+
+unit_test! {
+    logical_operators,
+    "
+    let t = { \"x\" = 5 };
+    let missing = nil;
+    // The right operand is only evaluated when needed
+    let calls = { \"n\" = 0 };
+    let count = fn() calls.n = calls.n + 1; true end;
+    assert((false and count()) == false);
+    assert((1 or count()) == 1);
+    assert(calls.n == 0);
+    assert(missing == nil or missing.y);
+    assert(t != nil and t.x == 5);
+    assert((nil and 1) == nil);
+    assert((false or nil) == nil);
+    assert((1 and 2 or 3) == 2);
+    assert((1 < 2 and 3 < 2 or 4 > 3) == true);
+    (missing ?? 7, false ?? 7, t.x ?? 0 > 4, missing ?? nil ?? 9)
+    ",
+    Ok(Value::Tuple(vec![
+        Value::Int(7),
+        Value::Bool(false),
+        Value::Bool(true),
+        Value::Int(9)
+    ]))
+}
+
+unit_test! {
+    optional_chaining,
+    "
+    let config = {
+        \"server\" = { \"port\" = 80 },
+        \"name\" = fn(self) \"app\" end,
+    };
+    let missing = nil;
+    let calls = { \"n\" = 0 };
+    let count = fn() calls.n = calls.n + 1; 1 end;
+    assert(missing?.server?.port == nil);
+    assert(config.missing?.port == nil);
+    assert(missing?[count()] == nil);
+    assert(missing?:name(count()) == nil);
+    // Arguments are not evaluated for a nil receiver
+    assert(calls.n == 0);
+    (config?.server?.port, config?[\"server\"].port, config?:name(), config.other?.x ?? 5)
+    ",
+    Ok(Value::Tuple(vec![
+        Value::Int(80),
+        Value::Int(80),
+        Value::new_str("app"),
+        Value::Int(5)
+    ]))
+}
+
+unit_test! {
+    compound_assignment,
+    "
+    let calls = { \"n\" = 0 };
+    let key = fn() calls.n = calls.n + 1; 1 end;
+    let t = { \"x\" = 1, \"list\" = { 1, 2, 3 } };
+    t.x += 4;
+    t.list[key()] *= 10;
+    t.list[key()] -= 5;
+    let a = 10;
+    a /= 2;
+    a %= 3;
+    // The table and the key are evaluated once
+    assert(calls.n == 2);
+    (t.x, t.list[1], a)
+    ",
+    Ok(Value::Tuple(vec![Value::Int(5), Value::Int(15), Value::Int(2)]))
+}
+
+unit_test! {
+    parallel_assignment,
+    "
+    let a = 1;
+    let b = 2;
+    a, b = b, a;
+    let t = { \"x\" = 3, \"y\" = 4 };
+    t.x, t.y, a = t.y, t.x, a + b;
+    (a, b, t.x, t.y)
+    ",
+    Ok(Value::Tuple(vec![
+        Value::Int(3),
+        Value::Int(1),
+        Value::Int(4),
+        Value::Int(3)
+    ]))
+}
+
+unit_test! {
+    string_interpolation,
+    "
+    let name = \"flux\";
+    let t = { \"n\" = 2 };
+    class Point
+        fn __tostring(self) \"point\" end
+    end
+    let nested = \"{name} {\"inner {t.n}\"} {{ 1, 2 }[0] + 1}\";
+    (\"hello {name}, {t.n + 1}!\", \"{new(Point)}\", nested, \"\\{x\\}\")
+    ",
+    Ok(Value::Tuple(vec![
+        Value::new_str("hello flux, 3!"),
+        Value::new_str("point"),
+        Value::new_str("flux inner 2 2"),
+        Value::new_str("{x}")
+    ]))
+}
+
+unit_test! {
+    interpolation_ignores_tostring_global,
+    "
+    let tostring = fn(x) \"HIJACKED\" end;
+    \"{1} {tostring(2)}\"
+    ",
+    Ok(Value::new_str("1 HIJACKED"))
+}
+
+unit_test! {
+    string_escapes,
+    r##"
+    let raw = r#"a "quoted" {b}
+\n"#;
+    ("tab\there \"quoted\"\n\u{48}\u{1F600}", raw, len(raw))
+    "##,
+    Ok(Value::Tuple(vec![
+        Value::new_str("tab\there \"quoted\"\nH\u{1F600}"),
+        Value::new_str("a \"quoted\" {b}\n\\n"),
+        Value::Int(17)
+    ]))
+}
+
+unit_test! {
+    numeric_literals,
+    "
+    assert(0xFF == 255);
+    assert(0b1010 == 10);
+    assert(1_000_000 == 1000000);
+    assert(.5 + .5 == 1.0);
+    assert(2e3 == 2000.0);
+    (9007199254740993, -9223372036854775807, 0x8000_0000_0000_0000, 1.0, 1e-9)
+    ",
+    Ok(Value::Tuple(vec![
+        Value::Int(9007199254740993),
+        Value::Int(-9223372036854775807),
+        Value::Int(i64::MIN),
+        Value::Number(1.0),
+        Value::Number(1e-9)
+    ]))
+}
+
+unit_test! {
+    bitwise_operators,
+    "
+    assert((0b1100 & 0b1010) == 0b1000);
+    assert((0b1100 | 0b1010) == 0b1110);
+    assert((0b1100 ^ 0b1010) == 0b0110);
+    assert(~0 == -1);
+    assert(1 << 62 == 0x4000_0000_0000_0000);
+    assert(1 << 64 == 0);
+    assert(8 >> -1 == 16);
+    // Shifting right is logical
+    assert(-1 >> 60 == 0xF);
+    // Shifts bind tighter than `&`, which binds tighter than `^` and `|`
+    assert(1 | 2 ^ 3 & 1 << 1 == 1);
+    let hash = 0x811c_9dc5;
+    for c in \"ab\" do
+        hash = (hash ^ len(c)) * 0x0100_0193 & 0xFFFF_FFFF;
+    end
+    (hash, 1 + 2 << 1, 5 % -3)
+    ",
+    Ok(Value::Tuple(vec![
+        Value::Int(0xec74_1ef7),
+        Value::Int(6),
+        Value::Int(2)
+    ]))
+}
+
+#[test]
+fn integer_overflow() {
+    use crate::compiler::BinaryInstr;
+    use crate::util::eval;
+
+    let overflow = Err(RuntimeError::IntegerOverflow.into());
+    assert_eq!(eval("9223372036854775807 + 1", ""), overflow);
+    assert_eq!(eval("-9223372036854775807 - 2", ""), overflow);
+    assert_eq!(eval("0x4000_0000_0000_0000 * 2", ""), overflow);
+    assert_eq!(eval("0x8000_0000_0000_0000 / -1", ""), overflow);
+    assert_eq!(eval("-0x8000_0000_0000_0000", ""), overflow);
+    assert_eq!(eval("0x8000_0000_0000_0000 % -1", ""), Ok(Value::Int(0)));
+    assert_eq!(eval("5 % 0", ""), Err(RuntimeError::DivideByZero.into()));
+    assert_eq!(
+        eval("1.5 & 1", ""),
+        Err(RuntimeError::UnsupportedBinary {
+            value: Value::Number(1.5),
+            op: BinaryInstr::BitAnd
+        }
+        .into())
+    );
+}
+
+unit_test! {
+    numeric_keys,
+    "
+    let t = {};
+    t[5] = \"int\";
+    t[2.5] = \"float\";
+    t[1.0] = \"one\";
+    let half = 5 / 2;
+    assert(t[10 / 2] == \"int\");
+    assert(t[5.0] == \"int\");
+    assert(t[half] == \"float\");
+    assert(t[1] == \"one\");
+    assert(5 == 5.0 and 2.5 != 2 and 0.0 == -0.0);
+    let nan = (1e308 * 10) - (1e308 * 10);
+    assert(nan != nan);
+    assert(t[nan] == nil);
+    let keys = 0;
+    for k, v in t do
+        keys += 1;
+    end
+    (keys, len(t))
+    ",
+    Ok(Value::Tuple(vec![Value::Int(3), Value::Int(3)]))
+}
+
+#[test]
+fn nan_key() {
+    use crate::util::eval;
+
+    let nan = "let nan = (1e308 * 10) - (1e308 * 10);";
+    for source in [
+        format!("{} let t = {{}}; t[nan] = 1;", nan),
+        format!("{} {{ nan = 1 }}", nan),
+    ] {
+        assert_eq!(eval(&source, ""), Err(RuntimeError::NanKey.into()));
+    }
+}
+
 macro_rules! test_file {
     ($test_name: ident, $name:expr, $expected:expr) => {
         #[test]
@@ -411,3 +1259,89 @@ test_file! {
     "import_closure",
     Ok(Value::Int(10))
 }
+
+test_file! {
+    import_once,
+    "modules/main",
+    Ok(Value::Int(12))
+}
+
+#[test]
+fn import_errors() {
+    use crate::compiler::CompileError;
+    use crate::util::eval;
+    use std::path::PathBuf;
+
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("src/vm/tests/modules/main.flux");
+    let path = path.to_str().unwrap();
+    // Unexported globals can't be imported
+    match eval("from counter import hidden;", path) {
+        Err(FluxError::Runtime(error)) => match *error {
+            RuntimeError::MissingExport { module, name } => {
+                assert!(module.ends_with("counter.flux"));
+                assert_eq!(name, "hidden");
+            }
+            error => panic!("Unexpected error {:?}", error),
+        },
+        result => panic!("Unexpected result {:?}", result),
+    }
+    match eval("import cycle_a;", path) {
+        Err(FluxError::Compile(CompileError::CircularImport { cycle })) => {
+            let names: Vec<_> = cycle
+                .iter()
+                .map(|path| PathBuf::from(path).file_stem().unwrap().to_owned())
+                .collect();
+            assert_eq!(names, ["cycle_a", "cycle_b", "cycle_a"]);
+        }
+        result => panic!("Unexpected result {:?}", result),
+    }
+    // Read errors name the module like parse errors do
+    assert_eq!(
+        eval("import binary;", path),
+        Err(FluxError::Compile(CompileError::ModuleError {
+            name: "binary".to_string(),
+            error: Box::new(CompileError::IoError(std::io::ErrorKind::InvalidData)),
+        }))
+    );
+}
+
+#[test]
+fn require_works() {
+    use crate::compiler::CompileError;
+    use crate::util::eval;
+    use std::path::PathBuf;
+
+    let mut path = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    path.push("src/vm/tests/modules/main.flux");
+    let path = path.to_str().unwrap();
+    // Shares the modules of import statements
+    let source = "
+    import counter;
+    let name = \"count\" + \"er\";
+    let required = require(name);
+    required.next();
+    counter.next()
+    ";
+    assert_eq!(eval(source, path), Ok(Value::Int(2)));
+    match eval("require(\"missing\")", path) {
+        Err(FluxError::Runtime(error)) => match *error {
+            RuntimeError::ImportError {
+                error: FluxError::Compile(CompileError::ModuleNotFound { name, .. }),
+                module,
+            } => {
+                assert_eq!(name, "missing");
+                assert_eq!(module, "missing");
+            }
+            error => panic!("Unexpected error {:?}", error),
+        },
+        result => panic!("Unexpected result {:?}", result),
+    }
+    match eval("require(\"self_require\")", path) {
+        Err(FluxError::Runtime(error)) => match *error {
+            RuntimeError::CircularImport { cycle } => assert_eq!(cycle.len(), 2),
+            error => panic!("Unexpected error {:?}", error),
+        },
+        result => panic!("Unexpected result {:?}", result),
+    }
+}
@@ -5,11 +5,21 @@ use std::fmt::{self, Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::rc::Rc;
 
-pub use function::{ArgsLen, FuncProtoRef, Function, NativeFunction, UserFunction};
+pub use convert::{FromValue, IntoNative, IntoValue};
+pub use coroutine::{Coroutine, CoroutineRef, CoroutineStatus};
+pub use function::{ArgsLen, FuncProtoRef, Function, NativeBody, NativeFunction, UserFunction};
+pub use iterator::{Iter, IterItem, IterRef, IterSource};
+pub use range::Range;
 pub use table::{Table, TableRef};
+pub use userdata::{UserData, UserDataRef};
 
+mod convert;
+mod coroutine;
 mod function;
+mod iterator;
+mod range;
 mod table;
+mod userdata;
 
 pub type Integer = i64;
 pub type Float = f64;
@@ -25,6 +35,10 @@ pub enum Value {
     Table(TableRef),
     Tuple(Vec<Value>),
     Function(Function),
+    UserData(UserDataRef),
+    Coroutine(CoroutineRef),
+    Iter(IterRef),
+    Range(Range),
     Unit,
 }
 
@@ -33,6 +47,28 @@ impl Value {
         Value::Str(Rc::new(string.into()))
     }
 
+    pub fn user_data<T: UserData>(data: T) -> Self {
+        Value::UserData(UserDataRef::new(data))
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Nil => "nil",
+            Value::Bool(_) => "bool",
+            Value::Int(_) => "int",
+            Value::Number(_) => "number",
+            Value::Str(_) | Value::Embedded(_) => "string",
+            Value::Table(_) => "table",
+            Value::Tuple(_) => "tuple",
+            Value::Function(_) => "function",
+            Value::UserData(data) => data.type_name(),
+            Value::Coroutine(_) => "coroutine",
+            Value::Iter(_) => "iterator",
+            Value::Range(_) => "range",
+            Value::Unit => "unit",
+        }
+    }
+
     pub fn as_str(&self) -> RuntimeResult<&str> {
         match self {
             Value::Str(rc) => Ok(rc.as_ref()),
@@ -43,17 +79,23 @@ impl Value {
     pub fn convert_int(&self) -> Option<Integer> {
         match self {
             Value::Int(i) => Some(*i),
-            Value::Number(n) => {
-                if n.fract() == 0.0 {
-                    Some(n.round() as Integer)
-                } else {
-                    None
-                }
-            }
+            Value::Number(n) => float_to_int(*n),
             _ => None,
         }
     }
 
+    /// Integral floats become `Int`, so `t[1]` and `t[1.0]` are the same key
+    pub fn into_key(self) -> Value {
+        match self {
+            Value::Number(n) => float_to_int(n).map_or(self, Value::Int),
+            value => value,
+        }
+    }
+
+    pub fn is_nan(&self) -> bool {
+        matches!(self, Value::Number(n) if n.is_nan())
+    }
+
     pub fn as_bool(&self) -> bool {
         match self {
             Value::Bool(b) => *b,
@@ -98,7 +140,7 @@ impl PartialEq for Value {
             (Bool(a), Bool(b)) => a == b,
             (Int(a), Int(b)) => a == b,
             (Number(a), Number(b)) => a == b,
-            // TODO: number and int equality
+            (Int(a), Number(b)) | (Number(b), Int(a)) => float_to_int(*b) == Some(*a),
             (Str(a), Str(b)) => a == b,
             (Embedded(a), Embedded(b)) => a == b,
             (Str(a), Embedded(b)) => a.as_str() == *b,
@@ -106,6 +148,10 @@ impl PartialEq for Value {
             (Table(a), Table(b)) => a.as_ptr() == b.as_ptr(),
             (Tuple(a), Tuple(b)) => a == b,
             (Function(a), Function(b)) => a == b,
+            (UserData(a), UserData(b)) => a == b,
+            (Coroutine(a), Coroutine(b)) => Rc::ptr_eq(a, b),
+            (Iter(a), Iter(b)) => Rc::ptr_eq(a, b),
+            (Range(a), Range(b)) => a == b,
             (Unit, Unit) => true,
             _ => false,
         }
@@ -125,10 +171,16 @@ impl Hash for Value {
                 3.hash(state);
                 i.hash(state);
             }
-            Value::Number(d) => {
-                4.hash(state);
-                (*d as u64).hash(state);
-            }
+            Value::Number(d) => match float_to_int(*d) {
+                Some(i) => {
+                    3.hash(state);
+                    i.hash(state);
+                }
+                None => {
+                    4.hash(state);
+                    d.to_bits().hash(state);
+                }
+            },
             Value::Str(s) => {
                 5.hash(state);
                 (*s.as_str()).hash(state);
@@ -155,12 +207,40 @@ impl Hash for Value {
             Value::Unit => {
                 9.hash(state);
             }
+            Value::UserData(data) => {
+                10.hash(state);
+                data.hash(state);
+            }
+            Value::Coroutine(coroutine) => {
+                11.hash(state);
+                coroutine.as_ptr().hash(state);
+            }
+            Value::Iter(iter) => {
+                12.hash(state);
+                iter.as_ptr().hash(state);
+            }
+            Value::Range(range) => {
+                13.hash(state);
+                range.hash(state);
+            }
         }
     }
 }
 
 impl Eq for Value {}
 
+// Exact value of the float as an integer, `None` for fractions, NaN and floats out of range.
+// Equality, hashing and table keys of numbers all go through it
+fn float_to_int(n: Float) -> Option<Integer> {
+    // -2^63 and 2^63 are exact floats, unlike i64::MAX
+    const LIMIT: Float = 9_223_372_036_854_775_808.0;
+    if n.fract() == 0.0 && (-LIMIT..LIMIT).contains(&n) {
+        Some(n as Integer)
+    } else {
+        None
+    }
+}
+
 impl Display for Value {
     fn fmt(&self, f: &mut Formatter) -> Result<(), fmt::Error> {
         match self {
@@ -197,6 +277,12 @@ impl Display for Value {
             }
             Value::Unit => write!(f, "()"),
             Value::Embedded(string) => write!(f, "{}", string),
+            Value::UserData(data) => write!(f, "{}", data),
+            Value::Coroutine(coroutine) => {
+                write!(f, "coroutine({})", RefCell::borrow(coroutine).status())
+            }
+            Value::Iter(_) => write!(f, "iterator"),
+            Value::Range(range) => write!(f, "{}", range),
         }
     }
 }
@@ -248,7 +334,6 @@ mod tests {
     use super::*;
     use std::collections::HashMap;
 
-    // TODO
     #[test]
     fn hash_works() {
         let mut map: HashMap<Value, ()> = HashMap::new();
@@ -265,6 +350,26 @@ mod tests {
         assert!(!map.contains_key(&b));
         assert!(map.contains_key(&c));
         // Value::Int == Value::Number
-        //...
+        map.insert(Value::Int(5), ());
+        assert!(map.contains_key(&Value::Number(5.0)));
+        map.insert(Value::Number(0.0), ());
+        assert!(map.contains_key(&Value::Number(-0.0)));
+        assert!(map.contains_key(&Value::Int(0)));
+        // Fractions are only equal to the same float
+        map.insert(Value::Number(5.5), ());
+        assert!(map.contains_key(&Value::Number(5.5)));
+        assert!(!map.contains_key(&Value::Number(5.25)));
+        // 2^53 + 1 isn't a float, so it differs from the nearest one
+        let big = (1 << 53) + 1;
+        assert_ne!(Value::Int(big), Value::Number(big as Float));
+        assert_eq!(Value::Int(1 << 53), Value::Number((1u64 << 53) as Float));
+        // Floats out of the integer range never equal an integer
+        assert_ne!(Value::Int(i64::MAX), Value::Number(i64::MAX as Float));
+        assert_eq!(Value::Int(i64::MIN), Value::Number(i64::MIN as Float));
+        // NaN is never equal to itself, so it can't be found
+        map.insert(Value::Number(f64::NAN), ());
+        assert!(!map.contains_key(&Value::Number(f64::NAN)));
+        assert!(matches!(Value::Number(7.0).into_key(), Value::Int(7)));
+        assert!(matches!(Value::Number(7.5).into_key(), Value::Number(_)));
     }
 }
@@ -0,0 +1,172 @@
+use super::{ArgsLen, Float, Function, Integer, NativeFunction, TableRef, Value};
+use crate::vm::{RuntimeError, RuntimeResult};
+use std::rc::Rc;
+
+/// Conversion of script values into Rust arguments of natives
+pub trait FromValue: Sized {
+    fn from_value(value: Value) -> RuntimeResult<Self>;
+}
+
+/// Conversion of values returned by natives into script values
+pub trait IntoValue {
+    fn into_value(self) -> Value;
+}
+
+fn unexpected<T>(expected: &'static str, found: &Value) -> RuntimeResult<T> {
+    Err(RuntimeError::UnexpectedType {
+        expected,
+        found: found.type_name(),
+    })
+}
+
+impl FromValue for Value {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        Ok(value)
+    }
+}
+
+impl FromValue for bool {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::Bool(b) => Ok(b),
+            value => unexpected("bool", &value),
+        }
+    }
+}
+
+impl FromValue for Integer {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value.convert_int() {
+            Some(i) => Ok(i),
+            None => unexpected("int", &value),
+        }
+    }
+}
+
+impl FromValue for Float {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::Int(i) => Ok(i as Float),
+            Value::Number(n) => Ok(n),
+            value => unexpected("number", &value),
+        }
+    }
+}
+
+impl FromValue for String {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::Str(s) => Ok(Rc::try_unwrap(s).unwrap_or_else(|s| s.as_ref().clone())),
+            Value::Embedded(s) => Ok(s.to_owned()),
+            value => unexpected("string", &value),
+        }
+    }
+}
+
+impl FromValue for TableRef {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::Table(table) => Ok(table),
+            value => unexpected("table", &value),
+        }
+    }
+}
+
+impl FromValue for Function {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::Function(function) => Ok(function),
+            value => unexpected("function", &value),
+        }
+    }
+}
+
+// Nil is None
+impl<T: FromValue> FromValue for Option<T> {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::Nil => Ok(None),
+            value => T::from_value(value).map(Some),
+        }
+    }
+}
+
+impl<T: Into<Value>> IntoValue for T {
+    fn into_value(self) -> Value {
+        self.into()
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Self {
+        Value::Unit
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Self {
+        Value::Bool(b)
+    }
+}
+
+impl From<Function> for Value {
+    fn from(function: Function) -> Self {
+        Value::Function(function)
+    }
+}
+
+impl<T: Into<Value>> From<Option<T>> for Value {
+    fn from(option: Option<T>) -> Self {
+        option.map_or(Value::Nil, Into::into)
+    }
+}
+
+/// Rust closures which can be registered as natives,
+/// `Args` is the tuple of their argument types
+pub trait IntoNative<Args> {
+    fn into_native(self) -> NativeFunction;
+}
+
+// Reports which argument failed to convert
+fn argument<T: FromValue>(value: Value, index: usize) -> RuntimeResult<T> {
+    T::from_value(value).map_err(|error| match error {
+        RuntimeError::UnexpectedType { expected, found } => RuntimeError::InvalidArgument {
+            index,
+            expected,
+            found,
+        },
+        error => error,
+    })
+}
+
+macro_rules! impl_into_native {
+    ($len:expr $(, $arg:ident: $index:expr)*) => {
+        impl<F, R, $($arg),*> IntoNative<($($arg,)*)> for F
+        where
+            F: Fn($($arg),*) -> RuntimeResult<R> + 'static,
+            R: IntoValue,
+            $($arg: FromValue,)*
+        {
+            #[allow(unused_variables, unused_mut)]
+            fn into_native(self) -> NativeFunction {
+                NativeFunction::closure(
+                    move |_vm, args| {
+                        // Arity is checked by the vm before the call
+                        let mut args = args.into_iter();
+                        self($(argument::<$arg>(args.next().unwrap(), $index)?),*)
+                            .map(IntoValue::into_value)
+                    },
+                    ArgsLen::Exact($len),
+                )
+            }
+        }
+    };
+}
+
+impl_into_native!(0);
+impl_into_native!(1, A: 0);
+impl_into_native!(2, A: 0, B: 1);
+impl_into_native!(3, A: 0, B: 1, C: 2);
+impl_into_native!(4, A: 0, B: 1, C: 2, D: 3);
+impl_into_native!(5, A: 0, B: 1, C: 2, D: 3, E: 4);
+impl_into_native!(6, A: 0, B: 1, C: 2, D: 3, E: 4, G: 5);
@@ -0,0 +1,63 @@
+use super::{UserFunction, Value};
+use crate::vm::frame::Frame;
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Display, Formatter};
+use std::rc::Rc;
+
+pub type CoroutineRef = Rc<RefCell<Coroutine>>;
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum CoroutineStatus {
+    Suspended,
+    Running,
+    Dead,
+}
+
+/// Function running on its own frames and stack, which are swapped
+/// into the vm while it is resumed
+#[derive(Clone, PartialEq)]
+pub struct Coroutine {
+    // Taken on the first resume
+    pub(in crate::vm) function: Option<UserFunction>,
+    pub(in crate::vm) frames: Vec<Frame>,
+    pub(in crate::vm) stack: Vec<Value>,
+    pub(in crate::vm) status: CoroutineStatus,
+}
+
+impl Coroutine {
+    pub fn new(function: UserFunction) -> Self {
+        Coroutine {
+            function: Some(function),
+            frames: Vec::new(),
+            stack: Vec::new(),
+            status: CoroutineStatus::Suspended,
+        }
+    }
+
+    pub fn shared(self) -> CoroutineRef {
+        Rc::new(RefCell::new(self))
+    }
+
+    pub fn status(&self) -> CoroutineStatus {
+        self.status
+    }
+}
+
+// Frames and stack are omitted, they may contain the coroutine itself
+impl Debug for Coroutine {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Coroutine")
+            .field("status", &self.status)
+            .finish()
+    }
+}
+
+impl Display for CoroutineStatus {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        match self {
+            CoroutineStatus::Suspended => write!(f, "suspended"),
+            CoroutineStatus::Running => write!(f, "running"),
+            CoroutineStatus::Dead => write!(f, "dead"),
+        }
+    }
+}
@@ -1,5 +1,5 @@
 use super::{TableRef, Value};
-use crate::compiler::FuncProto;
+use crate::compiler::{ConstantTableStruct, FuncProto};
 use crate::vm::{RuntimeResult, Vm};
 use std::fmt::{Debug, Display, Formatter};
 use std::hash::{Hash, Hasher};
@@ -13,18 +13,30 @@ pub enum Function {
 
 pub type FuncProtoRef = Rc<FuncProto>;
 
-#[derive(Clone, Debug)]
+#[derive(Clone)]
 pub struct UserFunction {
     args_len: u8,
     proto: FuncProtoRef,
     env: Option<TableRef>,
+    // Constants of the source the function is compiled in
+    constants: Rc<ConstantTableStruct>,
 }
 
-type NativeFn = fn(&mut Vm, Vec<Value>) -> RuntimeResult<Value>;
+pub type NativeFn = fn(&mut Vm, Vec<Value>) -> RuntimeResult<Value>;
+pub type NativeClosure = dyn Fn(&mut Vm, Vec<Value>) -> RuntimeResult<Value>;
+
+/// Body of a native function. Plain functions can be defined as constants,
+/// closures can capture host state
+#[derive(Clone)]
+pub enum NativeBody {
+    Fn(NativeFn),
+    Closure(Rc<NativeClosure>),
+}
 
+/// Function implemented in Rust. Arguments are passed in source order
 #[derive(Clone)]
 pub struct NativeFunction {
-    pub function: NativeFn,
+    pub function: NativeBody,
     pub args_len: ArgsLen,
 }
 
@@ -35,12 +47,16 @@ pub enum ArgsLen {
 }
 
 impl Function {
-    pub fn new_user(proto: FuncProtoRef) -> Self {
-        Function::User(UserFunction::new(proto))
+    pub fn new_user(proto: FuncProtoRef, constants: Rc<ConstantTableStruct>) -> Self {
+        Function::User(UserFunction::new(proto, constants))
     }
 
-    pub fn new_user_with_env(proto: FuncProtoRef, env: TableRef) -> Self {
-        Function::User(UserFunction::new(proto).with_env(env))
+    pub fn new_user_with_env(
+        proto: FuncProtoRef,
+        constants: Rc<ConstantTableStruct>,
+        env: TableRef,
+    ) -> Self {
+        Function::User(UserFunction::new(proto, constants).with_env(env))
     }
 
     pub fn args_len(&self) -> ArgsLen {
@@ -59,11 +75,12 @@ impl Function {
 }
 
 impl UserFunction {
-    pub fn new(proto: FuncProtoRef) -> Self {
+    pub fn new(proto: FuncProtoRef, constants: Rc<ConstantTableStruct>) -> Self {
         UserFunction {
             args_len: proto.args_len,
             proto,
             env: None,
+            constants,
         }
     }
 
@@ -80,6 +97,10 @@ impl UserFunction {
         &self.proto
     }
 
+    pub fn constants(&self) -> &Rc<ConstantTableStruct> {
+        &self.constants
+    }
+
     pub fn take_env(&mut self) -> Option<TableRef> {
         self.env.take()
     }
@@ -90,9 +111,40 @@ impl UserFunction {
 }
 
 impl NativeFunction {
+    pub fn new(function: NativeFn, args_len: ArgsLen) -> Self {
+        NativeFunction {
+            function: NativeBody::Fn(function),
+            args_len,
+        }
+    }
+
+    pub fn closure(
+        function: impl Fn(&mut Vm, Vec<Value>) -> RuntimeResult<Value> + 'static,
+        args_len: ArgsLen,
+    ) -> Self {
+        NativeFunction {
+            function: NativeBody::Closure(Rc::new(function)),
+            args_len,
+        }
+    }
+
     pub fn args_len(&self) -> ArgsLen {
         self.args_len
     }
+
+    pub fn call(&self, vm: &mut Vm, args: Vec<Value>) -> RuntimeResult<Value> {
+        match &self.function {
+            NativeBody::Fn(function) => function(vm, args),
+            NativeBody::Closure(closure) => closure(vm, args),
+        }
+    }
+
+    fn address(&self) -> *const u8 {
+        match &self.function {
+            NativeBody::Fn(function) => *function as *const u8,
+            NativeBody::Closure(closure) => Rc::as_ptr(closure) as *const u8,
+        }
+    }
 }
 
 impl PartialEq for UserFunction {
@@ -115,6 +167,17 @@ impl Into<Value> for UserFunction {
     }
 }
 
+// Constants are omitted, they would be printed for every function
+impl Debug for UserFunction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("UserFunction")
+            .field("args_len", &self.args_len)
+            .field("proto", &self.proto)
+            .field("env", &self.env)
+            .finish()
+    }
+}
+
 impl Debug for NativeFunction {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         write!(f, "native fn({})", self.args_len())
@@ -123,13 +186,13 @@ impl Debug for NativeFunction {
 
 impl PartialEq for NativeFunction {
     fn eq(&self, other: &NativeFunction) -> bool {
-        (self.function as *const NativeFn) == (other.function as *const NativeFn)
+        self.address() == other.address()
     }
 }
 
 impl Hash for NativeFunction {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        (self.function as *const NativeFn).hash(state);
+        self.address().hash(state);
     }
 }
 
@@ -0,0 +1,161 @@
+use super::{CoroutineRef, Integer, Range, TableRef, Value};
+use std::cell::RefCell;
+use std::fmt::{self, Debug, Formatter};
+use std::rc::Rc;
+
+pub type IterRef = Rc<RefCell<Iter>>;
+
+/// Values walked by an iterator
+#[derive(Clone, PartialEq)]
+pub enum IterSource {
+    // Keys are collected when the iteration starts
+    Table {
+        table: TableRef,
+        keys: Vec<Value>,
+    },
+    // Integer keys from 0 until the first missing one
+    Sequence(TableRef),
+    Chars {
+        string: Rc<String>,
+        offset: usize,
+    },
+    // Finished once the next value would overflow
+    Range {
+        next: Option<Integer>,
+        end: Integer,
+        step: Integer,
+        inclusive: bool,
+    },
+    // Called until it returns nil
+    Function(Value),
+    Coroutine(CoroutineRef),
+}
+
+/// Single step of an iterator
+#[derive(Clone, Debug, PartialEq)]
+pub enum IterItem {
+    Entry(Value, Value),
+    Value(Value),
+}
+
+/// State of a `for` loop over a value
+#[derive(Clone, PartialEq)]
+pub struct Iter {
+    pub(in crate::vm) source: IterSource,
+    // Number of items returned so far
+    index: usize,
+    // Entries are bound as tuples to a single variable
+    entries: bool,
+}
+
+impl Iter {
+    pub fn new(source: IterSource) -> Self {
+        Iter {
+            source,
+            index: 0,
+            entries: false,
+        }
+    }
+
+    /// Iterator over the keys and values of the table
+    pub fn table(table: TableRef) -> Self {
+        let keys = table.borrow().keys().cloned().collect();
+        Self::new(IterSource::Table { table, keys })
+    }
+
+    pub fn chars(string: Rc<String>) -> Self {
+        Self::new(IterSource::Chars { string, offset: 0 })
+    }
+
+    pub fn range(range: Range, step: Integer) -> Self {
+        Self::new(IterSource::Range {
+            next: Some(range.start),
+            end: range.end,
+            step,
+            inclusive: range.inclusive,
+        })
+    }
+
+    pub fn with_entries(mut self) -> Self {
+        self.entries = true;
+        self
+    }
+
+    pub fn shared(self) -> IterRef {
+        Rc::new(RefCell::new(self))
+    }
+
+    /// Next item of sources which don't run script code, `None` once finished
+    pub(in crate::vm) fn next_item(&mut self) -> Option<IterItem> {
+        let key = Value::Int(self.index as Integer);
+        let item = match &mut self.source {
+            IterSource::Table { table, keys } => {
+                let key = keys.get(self.index)?.clone();
+                let value = table.borrow().get(&key).clone();
+                IterItem::Entry(key, value)
+            }
+            IterSource::Sequence(table) => {
+                let table = table.borrow();
+                if !table.contains(&key) {
+                    return None;
+                }
+                let value = table.get(&key).clone();
+                IterItem::Entry(key, value)
+            }
+            IterSource::Chars { string, offset } => {
+                let c = string[*offset..].chars().next()?;
+                *offset += c.len_utf8();
+                IterItem::Entry(key, Value::new_str(c.to_string()))
+            }
+            IterSource::Range {
+                next,
+                end,
+                step,
+                inclusive,
+            } => {
+                let value = (*next)?;
+                let in_range = match (*step > 0, *inclusive) {
+                    (true, false) => value < *end,
+                    (true, true) => value <= *end,
+                    (false, false) => value > *end,
+                    (false, true) => value >= *end,
+                };
+                if !in_range {
+                    return None;
+                }
+                *next = value.checked_add(*step);
+                IterItem::Entry(key, Value::Int(value))
+            }
+            IterSource::Function(_) | IterSource::Coroutine(_) => {
+                unreachable!("generators are resumed by the vm")
+            }
+        };
+        self.index += 1;
+        Some(item)
+    }
+
+    /// Pushes the values of the item for `len` loop variables
+    pub(in crate::vm) fn bind(&self, item: IterItem, len: usize, values: &mut Vec<Value>) {
+        let start = values.len();
+        match item {
+            IterItem::Entry(key, value) if len == 1 && self.entries => {
+                values.push(Value::Tuple(vec![key, value]))
+            }
+            IterItem::Entry(_, value) if len == 1 => values.push(value),
+            IterItem::Entry(key, value) => {
+                values.push(key);
+                values.push(value);
+            }
+            IterItem::Value(Value::Tuple(tuple)) if len > 1 => values.extend(tuple),
+            IterItem::Value(value) => values.push(value),
+        }
+        values.resize(start + len, Value::Nil);
+    }
+}
+
+// Sources are omitted, they may contain the iterator itself
+impl Debug for Iter {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        f.debug_struct("Iter").field("index", &self.index).finish()
+    }
+}
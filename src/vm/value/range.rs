@@ -0,0 +1,55 @@
+use super::Integer;
+use std::fmt::{self, Display, Formatter};
+
+/// Integer range created by `start..end` or `start..=end`
+#[derive(Copy, Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Range {
+    pub start: Integer,
+    pub end: Integer,
+    pub inclusive: bool,
+}
+
+impl Range {
+    pub fn new(start: Integer, end: Integer, inclusive: bool) -> Self {
+        Range {
+            start,
+            end,
+            inclusive,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        let end = if self.inclusive {
+            self.end.saturating_add(1)
+        } else {
+            self.end
+        };
+        end.saturating_sub(self.start).max(0) as usize
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Indices of the range in a sequence of `len` items, `None` if it is out of bounds.
+    /// Reversed ranges are empty
+    pub fn bounds(&self, len: usize) -> Option<(usize, usize)> {
+        if self.start < 0 {
+            return None;
+        }
+        let start = self.start as usize;
+        let end = start.checked_add(self.len())?;
+        if end <= len {
+            Some((start, end))
+        } else {
+            None
+        }
+    }
+}
+
+impl Display for Range {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        let op = if self.inclusive { "..=" } else { ".." };
+        write!(f, "{}{}{}", self.start, op, self.end)
+    }
+}
@@ -1,6 +1,7 @@
-use super::Value;
+use super::{Integer, Value};
 use std::cell::RefCell;
 use std::collections::HashMap;
+use std::mem::{self, size_of};
 use std::rc::Rc;
 
 pub type TableRef = Rc<RefCell<Table>>;
@@ -13,6 +14,10 @@ pub struct Table {
 
 impl Table {
     const NIL: Value = Value::Nil;
+    /// Approximate bytes allocated for a table without its entries
+    pub const HEADER_SIZE: usize = size_of::<RefCell<Table>>() + 2 * size_of::<usize>();
+    /// Approximate bytes allocated for a single key value pair
+    pub const ENTRY_SIZE: usize = 2 * size_of::<Value>();
 
     pub fn new() -> Self {
         Self::default()
@@ -31,37 +36,74 @@ impl Table {
 
     pub fn from_map(table: HashMap<Value, Value>) -> Self {
         Table {
-            table,
+            table: table.into_iter().map(|(k, v)| (k.into_key(), v)).collect(),
             array: vec![],
         }
     }
 
-    pub fn set(&mut self, key: Value, value: Value) {
-        self.table.insert(key, value);
+    // Returns the old value if key was present
+    pub fn set(&mut self, key: Value, value: Value) -> Option<Value> {
+        let key = key.into_key();
+        match self.array_index(&key) {
+            Some(i) => Some(mem::replace(&mut self.array[i].1, value)),
+            None => self.table.insert(key, value),
+        }
     }
 
     pub fn get(&self, key: &Value) -> &Value {
-        if let Some(i) = key.convert_int() {
-            if i >= 0 {
-                let i = i as usize;
-                if self.array.len() > i {
-                    return &self.array[i].1;
-                }
-            }
+        match self.array_index(key) {
+            Some(i) => &self.array[i].1,
+            None => self.table.get(key).unwrap_or(&Self::NIL),
+        }
+    }
+
+    // Keys set to nil are still present
+    pub fn contains(&self, key: &Value) -> bool {
+        self.array_index(key).is_some() || self.table.contains_key(key)
+    }
+
+    fn array_index(&self, key: &Value) -> Option<usize> {
+        match key.convert_int() {
+            Some(i) if i >= 0 && (i as usize) < self.array.len() => Some(i as usize),
+            _ => None,
         }
-        self.table.get(key).unwrap_or(&Self::NIL)
+    }
+
+    pub fn len(&self) -> usize {
+        self.array.len() + self.table.len()
+    }
+
+    /// Number of consecutive integer keys from 0, the other keys aren't counted
+    pub fn array_len(&self) -> usize {
+        (self.array.len()..)
+            .find(|i| !self.table.contains_key(&Value::Int(*i as Integer)))
+            .expect("Expected a missing key")
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
     }
 
     pub fn klass(&self) -> &Value {
         self.get(&Value::Embedded("__class__"))
     }
 
+    // Base of a class
+    pub fn parent(&self) -> &Value {
+        self.get(&Value::Embedded("__super__"))
+    }
+
     pub fn pairs(&self) -> impl Iterator<Item = (&Value, &Value)> {
         self.table
             .iter()
             .chain(self.array.iter().map(|(v1, v2)| (v1, v2)))
     }
 
+    // Array keys in order, then the others
+    pub fn keys(&self) -> impl Iterator<Item = &Value> {
+        self.array.iter().map(|(k, _)| k).chain(self.table.keys())
+    }
+
     pub fn values(&self) -> impl Iterator<Item = &Value> {
         self.array.iter().map(|(_, v)| v).chain(self.table.values())
     }
@@ -0,0 +1,128 @@
+use super::{FromValue, Value};
+use crate::vm::{RuntimeError, RuntimeResult};
+use std::any::{Any, TypeId};
+use std::fmt::{self, Debug, Display, Formatter};
+use std::hash::{Hash, Hasher};
+use std::rc::Rc;
+
+/// Host object which can be passed to scripts. Methods of a type are looked up
+/// in the metatable registered with `Vm::set_metatable`
+pub trait UserData: Any {
+    /// Name of the type in error messages
+    fn type_name(&self) -> &'static str {
+        std::any::type_name::<Self>()
+    }
+
+    /// Formats the value when it is printed by a script
+    fn display(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "<{}>", self.type_name())
+    }
+}
+
+/// Shared handle to a host object, compared by identity
+#[derive(Clone)]
+pub struct UserDataRef(Rc<dyn UserData>);
+
+impl UserDataRef {
+    pub fn new<T: UserData>(data: T) -> Self {
+        UserDataRef(Rc::new(data))
+    }
+
+    pub fn from_rc<T: UserData>(data: Rc<T>) -> Self {
+        UserDataRef(data)
+    }
+
+    /// Type of the wrapped object, not of the handle
+    pub fn type_id(&self) -> TypeId {
+        let any: &dyn Any = self.0.as_ref();
+        any.type_id()
+    }
+
+    pub fn type_name(&self) -> &'static str {
+        self.0.type_name()
+    }
+
+    pub fn is<T: UserData>(&self) -> bool {
+        self.type_id() == TypeId::of::<T>()
+    }
+
+    pub fn downcast_ref<T: UserData>(&self) -> Option<&T> {
+        let any: &dyn Any = self.0.as_ref();
+        any.downcast_ref()
+    }
+
+    pub fn downcast<T: UserData>(self) -> Result<Rc<T>, Self> {
+        if self.is::<T>() {
+            let any: Rc<dyn Any> = self.0;
+            Ok(any.downcast().expect("type is checked"))
+        } else {
+            Err(self)
+        }
+    }
+
+    fn address(&self) -> *const u8 {
+        Rc::as_ptr(&self.0) as *const u8
+    }
+}
+
+impl PartialEq for UserDataRef {
+    fn eq(&self, other: &Self) -> bool {
+        self.address() == other.address()
+    }
+}
+
+impl Hash for UserDataRef {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.address().hash(state)
+    }
+}
+
+impl Display for UserDataRef {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        self.0.display(f)
+    }
+}
+
+impl Debug for UserDataRef {
+    fn fmt(&self, f: &mut Formatter) -> fmt::Result {
+        write!(f, "UserData({})", self.type_name())
+    }
+}
+
+impl<T: UserData> FromValue for Rc<T> {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        let found = value.type_name();
+        let unexpected = RuntimeError::UnexpectedType {
+            expected: std::any::type_name::<T>(),
+            found,
+        };
+        match value {
+            Value::UserData(data) => data.downcast().map_err(|_| unexpected),
+            _ => Err(unexpected),
+        }
+    }
+}
+
+impl FromValue for UserDataRef {
+    fn from_value(value: Value) -> RuntimeResult<Self> {
+        match value {
+            Value::UserData(data) => Ok(data),
+            value => Err(RuntimeError::UnexpectedType {
+                expected: "userdata",
+                found: value.type_name(),
+            }),
+        }
+    }
+}
+
+impl<T: UserData> From<Rc<T>> for Value {
+    fn from(data: Rc<T>) -> Self {
+        Value::UserData(UserDataRef::from_rc(data))
+    }
+}
+
+impl From<UserDataRef> for Value {
+    fn from(data: UserDataRef) -> Self {
+        Value::UserData(data)
+    }
+}